@@ -14,4 +14,5 @@ module Test::A
   end
 end
 PI = 3.14
-F = {} # <= Not a simple literal so will not appear in the exploration list
\ No newline at end of file
+F = {} # <= Hashes, arrays and operations on known constants are evaluated as well
+G = compute_g # <= Method calls unknown to the send handler are reported as unevaluable
\ No newline at end of file
@@ -16,7 +16,7 @@ module Test
       end
     end
   end
-  # This documentation is lost forever because it's not meant to be documented several times
+  # This documentation is merged with the documentation of the first definition
   class A
     # Super constant
     A = 0
@@ -0,0 +1,9 @@
+module Config
+  NAME = "x".freeze
+  SIZES = [1, 2].freeze
+  KEYS = %w[a b].freeze
+  PREFIX = ("ab" * 2).freeze
+  NAMES = [NAME, "y"].freeze
+  LABELS = ["a".freeze, "b"]
+  COUNT = 1 + 2
+end
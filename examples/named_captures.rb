@@ -0,0 +1,12 @@
+def parse_date(input)
+  year = 0
+  /(?<year>\d+)-(?<month>\d+)/ =~ input
+  day = input.length
+  [year, month, day]
+end
+
+def parse_time(input, separator)
+  hours = 0
+  /(?<hours>\d+)#{separator}\d+/ =~ input
+  hours
+end
@@ -0,0 +1,4 @@
+# Rules evaluating method calls in constants, given to explore_constants with --send-rules
+def gen(a, b) = 384 + a + b * 8
+def Color.rgb(r, g, b) = (r << 16) | (g << 8) | b
+def level(name) = { low: 1, medium: 2, high: 3 }[name]
@@ -0,0 +1,19 @@
+class Foo
+  def a; end
+end
+Foo.configure # <= Must run before the second Foo
+class Foo
+  def b; end
+end
+class Bar
+end
+class Foo
+  include Bar
+end
+def helper; end
+class Foo
+  helper
+end
+class Foo
+  def z; end
+end
@@ -0,0 +1,43 @@
+# Inventory of the player.
+#
+# It keeps track of the items and their quantities.
+# @see Item
+# @api public
+class Inventory < Storage
+  include Enumerable
+
+  # Maximum quantity per item
+  MAX = 99
+
+  # Name of the inventory
+  # @return [String]
+  attr_reader :name
+
+  # Add an item to the inventory.
+  # @param item [Item] item to add
+  # @param [Integer] quantity number of items
+  # @param options [Hash{Symbol => Object}] extra options
+  # @option options [Boolean] :notify (false) tell the player
+  #   about the addition
+  # @return [Boolean, nil] if the item was added
+  # @raise [ArgumentError] when quantity is negative
+  # @example Add two potions
+  #   inventory.add(potion, 2)
+  #   inventory.add(potion, 3, notify: true)
+  def add(item, quantity = 1, **options)
+    return true
+  end
+
+  # Iterate over the items
+  # @yield [item, quantity] each item with its quantity
+  # @yieldparam item [Item]
+  # @yieldparam quantity [Integer]
+  # @deprecated Use {#each_item} instead
+  def each(&block)
+  end
+
+  # @!macro [attach] property
+  #   @return [$2] the $1 property
+  def self.property(name, type)
+  end
+end
@@ -0,0 +1,852 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    code_writer::code_to_string,
+    documentation_tree::{DocumentedKind, DocumentedObject},
+    tests::explore_constants::{find_constant_value, ConstantHashMap},
+    yard::{YardDocstring, YardTag},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceFormat {
+    Markdown,
+    Html,
+}
+
+impl ReferenceFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ReferenceFormat::Markdown => "md",
+            ReferenceFormat::Html => "html",
+        }
+    }
+}
+
+/** Inline content of a page */
+enum Inline {
+    Text(String),
+    Code(String),
+    Link(String, String),
+}
+
+/** Block content of a page */
+enum Block {
+    Anchor(String),
+    Heading(u8, Vec<Inline>),
+    Paragraph(Vec<Inline>),
+    CodeBlock(String),
+    List(Vec<Vec<Inline>>),
+    Table(Vec<&'static str>, Vec<Vec<Vec<Inline>>>),
+}
+
+/** Every documented namespace with the page describing it */
+struct ReferenceIndex<'a> {
+    namespaces: HashMap<String, &'a DocumentedObject>,
+    format: ReferenceFormat,
+}
+
+impl<'a> ReferenceIndex<'a> {
+    fn page_file_name(&self, path: &str) -> String {
+        return format!("{}.{}", path.replace("::", "__"), self.format.extension());
+    }
+
+    /** Resolve a constant name used inside a namespace to a documented namespace path */
+    fn resolve(&self, name: &str, current_namespace: &str) -> Option<String> {
+        let name = name.trim();
+        if let Some(absolute) = name.strip_prefix("::") {
+            return self
+                .namespaces
+                .get(absolute)
+                .map(|_| String::from(absolute));
+        }
+        let mut namespace = current_namespace;
+        loop {
+            let candidate = if namespace.is_empty() {
+                String::from(name)
+            } else {
+                format!("{}::{}", namespace, name)
+            };
+            if self.namespaces.contains_key(&candidate) {
+                return Some(candidate);
+            }
+            if namespace.is_empty() {
+                return None;
+            }
+            namespace = match namespace.rfind("::") {
+                Some(index) => &namespace[..index],
+                None => "",
+            };
+        }
+    }
+
+    /** Make a link to a `Namespace`, `Namespace#method`, `#method` or `Namespace.method` reference */
+    fn reference_href(&self, reference: &str, current_namespace: &str) -> Option<String> {
+        let split_index = reference.find(|c| c == '#' || c == '.');
+        let (namespace, member) = match split_index {
+            Some(index) => (&reference[..index], Some(&reference[index..])),
+            None => (reference, None),
+        };
+        let namespace = if namespace.is_empty() {
+            String::from(current_namespace)
+        } else {
+            self.resolve(namespace, current_namespace)?
+        };
+        let mut href = if namespace.is_empty() {
+            format!("index.{}", self.format.extension())
+        } else {
+            self.page_file_name(&namespace)
+        };
+        if let Some(member) = member {
+            let kind = if member.starts_with('#') {
+                DocumentedKind::InstanceMethod
+            } else {
+                DocumentedKind::ClassMethod
+            };
+            href.push('#');
+            href.push_str(&anchor_name(kind, &member[1..]));
+        }
+        return Some(href);
+    }
+}
+
+/** Write one page per class or module and an index page in the output directory */
+pub fn write_api_reference(
+    objects: &Vec<DocumentedObject>,
+    constants: &ConstantHashMap,
+    output_directory: &Path,
+    format: ReferenceFormat,
+) -> Result<(), std::io::Error> {
+    let mut index = ReferenceIndex {
+        namespaces: HashMap::new(),
+        format,
+    };
+    collect_namespaces(objects, &mut index.namespaces);
+    fs::create_dir_all(output_directory)?;
+
+    let mut namespace_paths: Vec<&String> = index.namespaces.keys().collect();
+    namespace_paths.sort();
+    for path in namespace_paths.iter() {
+        let object = index.namespaces[*path];
+        let blocks = namespace_page(object, &index, constants);
+        let title = format!("{} {}", object.kind.as_str(), object.path);
+        fs::write(
+            output_directory.join(index.page_file_name(path)),
+            render_page(&title, &blocks, format),
+        )?;
+    }
+
+    let blocks = index_page(objects, &namespace_paths, &index, constants);
+    fs::write(
+        output_directory.join(format!("index.{}", format.extension())),
+        render_page("API Reference", &blocks, format),
+    )?;
+    return Ok(());
+}
+
+fn collect_namespaces<'a>(
+    objects: &'a Vec<DocumentedObject>,
+    namespaces: &mut HashMap<String, &'a DocumentedObject>,
+) {
+    for object in objects.iter() {
+        if object.kind.is_namespace() {
+            namespaces.entry(object.path.clone()).or_insert(object);
+            collect_namespaces(&object.children, namespaces);
+        }
+    }
+}
+
+fn index_page(
+    objects: &Vec<DocumentedObject>,
+    namespace_paths: &Vec<&String>,
+    index: &ReferenceIndex,
+    constants: &ConstantHashMap,
+) -> Vec<Block> {
+    let mut blocks = vec![Block::Heading(
+        1,
+        vec![Inline::Text(String::from("API Reference"))],
+    )];
+    if !namespace_paths.is_empty() {
+        blocks.push(Block::Heading(
+            2,
+            vec![Inline::Text(String::from("Classes and modules"))],
+        ));
+        blocks.push(Block::List(
+            namespace_paths
+                .iter()
+                .map(|path| {
+                    let object = index.namespaces[*path];
+                    let mut item = vec![
+                        Inline::Text(format!("{} ", object.kind.as_str())),
+                        Inline::Link(path.to_string(), index.page_file_name(path)),
+                    ];
+                    push_summary(&mut item, &object.docstring, &object.path, index);
+                    item
+                })
+                .collect(),
+        ));
+    }
+    let top_level: Vec<&DocumentedObject> = objects
+        .iter()
+        .filter(|object| !object.kind.is_namespace())
+        .collect();
+    if !top_level.is_empty() {
+        blocks.push(Block::Heading(
+            2,
+            vec![Inline::Text(String::from("Top level"))],
+        ));
+        push_members(&mut blocks, &top_level, "", index, constants, 3);
+    }
+    return blocks;
+}
+
+fn namespace_page(
+    object: &DocumentedObject,
+    index: &ReferenceIndex,
+    constants: &ConstantHashMap,
+) -> Vec<Block> {
+    let mut blocks = vec![Block::Heading(
+        1,
+        vec![Inline::Text(format!(
+            "{} {}",
+            object.kind.as_str(),
+            object.path
+        ))],
+    )];
+    let mut details: Vec<Vec<Inline>> = vec![];
+    if object.kind == DocumentedKind::Class {
+        details.push(inheritance_line(object, index));
+    }
+    for method_name in ["include", "extend", "prepend"] {
+        let modules: Vec<&str> = object
+            .mixins
+            .iter()
+            .filter(|mixin| mixin.method_name == method_name)
+            .map(|mixin| mixin.module_name.as_str())
+            .collect();
+        if modules.is_empty() {
+            continue;
+        }
+        let mut line = vec![Inline::Text(format!("{}: ", mixin_title(method_name)))];
+        for (i, module) in modules.iter().enumerate() {
+            if i > 0 {
+                line.push(Inline::Text(String::from(", ")));
+            }
+            line.push(constant_inline(module, &object.path, index));
+        }
+        details.push(line);
+    }
+    details.push(vec![Inline::Text(format!(
+        "Defined at line {}",
+        object.line
+    ))]);
+    blocks.push(Block::List(details));
+    push_docstring(&mut blocks, &object.docstring, &object.path, index);
+
+    let nested: Vec<&DocumentedObject> = object
+        .children
+        .iter()
+        .filter(|child| child.kind.is_namespace())
+        .collect();
+    if !nested.is_empty() {
+        blocks.push(Block::Heading(
+            2,
+            vec![Inline::Text(String::from("Namespaces"))],
+        ));
+        blocks.push(Block::List(
+            nested
+                .iter()
+                .map(|child| {
+                    let mut item = vec![
+                        Inline::Text(format!("{} ", child.kind.as_str())),
+                        Inline::Link(child.name.clone(), index.page_file_name(&child.path)),
+                    ];
+                    push_summary(&mut item, &child.docstring, &object.path, index);
+                    item
+                })
+                .collect(),
+        ));
+    }
+    let members: Vec<&DocumentedObject> = object
+        .children
+        .iter()
+        .filter(|child| !child.kind.is_namespace())
+        .collect();
+    push_members(&mut blocks, &members, &object.path, index, constants, 2);
+    return blocks;
+}
+
+fn mixin_title(method_name: &str) -> &'static str {
+    match method_name {
+        "include" => "Includes",
+        "extend" => "Extends",
+        _ => "Prepends",
+    }
+}
+
+fn inheritance_line(object: &DocumentedObject, index: &ReferenceIndex) -> Vec<Inline> {
+    let mut line = vec![
+        Inline::Text(String::from("Inheritance: ")),
+        Inline::Code(object.path.clone()),
+    ];
+    let mut current = object;
+    let mut visited: Vec<&str> = vec![object.path.as_str()];
+    loop {
+        line.push(Inline::Text(String::from(" < ")));
+        let superclass = match &current.superclass {
+            Some(superclass) => superclass,
+            None => {
+                line.push(Inline::Code(String::from("Object")));
+                break;
+            }
+        };
+        match index.resolve(superclass, parent_namespace(&current.path)) {
+            Some(path) if !visited.contains(&path.as_str()) => {
+                line.push(Inline::Link(path.clone(), index.page_file_name(&path)));
+                current = index.namespaces[&path];
+                if current.kind != DocumentedKind::Class {
+                    break;
+                }
+                visited.push(current.path.as_str());
+            }
+            _ => {
+                line.push(Inline::Code(superclass.clone()));
+                break;
+            }
+        }
+    }
+    return line;
+}
+
+fn parent_namespace(path: &str) -> &str {
+    match path.rfind("::") {
+        Some(index) => &path[..index],
+        None => "",
+    }
+}
+
+fn push_members(
+    blocks: &mut Vec<Block>,
+    members: &Vec<&DocumentedObject>,
+    namespace: &str,
+    index: &ReferenceIndex,
+    constants: &ConstantHashMap,
+    heading_level: u8,
+) {
+    let constant_rows: Vec<Vec<Vec<Inline>>> = members
+        .iter()
+        .filter(|member| member.kind == DocumentedKind::Constant)
+        .map(|member| {
+            let value = match find_constant_value(constants, &member.path) {
+                Some(value) => code_to_string(value),
+                None => member
+                    .value
+                    .as_ref()
+                    .map(code_to_string)
+                    .unwrap_or_default(),
+            };
+            let mut description: Vec<Inline> = vec![];
+            if let Some(docstring) = &member.docstring {
+                description = doc_inlines(&docstring.summary, namespace, index);
+            }
+            vec![
+                vec![Inline::Code(member.name.clone())],
+                vec![Inline::Code(value)],
+                description,
+            ]
+        })
+        .collect();
+    if !constant_rows.is_empty() {
+        blocks.push(Block::Heading(
+            heading_level,
+            vec![Inline::Text(String::from("Constants"))],
+        ));
+        blocks.push(Block::Table(
+            vec!["Name", "Value", "Description"],
+            constant_rows,
+        ));
+    }
+
+    let sections = [
+        (DocumentedKind::Attribute, "Attributes"),
+        (DocumentedKind::ClassMethod, "Class methods"),
+        (DocumentedKind::InstanceMethod, "Instance methods"),
+    ];
+    for (kind, title) in sections {
+        let section_members: Vec<&&DocumentedObject> = members
+            .iter()
+            .filter(|member| member.kind == kind)
+            .collect();
+        if section_members.is_empty() {
+            continue;
+        }
+        blocks.push(Block::Heading(
+            heading_level,
+            vec![Inline::Text(String::from(title))],
+        ));
+        for member in section_members {
+            blocks.push(Block::Anchor(anchor_name(member.kind, &member.name)));
+            blocks.push(Block::Heading(
+                heading_level + 1,
+                vec![Inline::Code(member_signature(member))],
+            ));
+            push_docstring(blocks, &member.docstring, namespace, index);
+            if let Some(docstring) = &member.docstring {
+                push_member_tags(blocks, docstring, namespace, index);
+            }
+        }
+    }
+}
+
+fn member_signature(member: &DocumentedObject) -> String {
+    match member.kind {
+        DocumentedKind::Attribute => {
+            let access = match member.attribute_access.as_deref() {
+                Some("attr_reader") => "read only",
+                Some("attr_writer") => "write only",
+                _ => "read/write",
+            };
+            return format!("{} [{}]", member.name, access);
+        }
+        DocumentedKind::InstanceMethod | DocumentedKind::ClassMethod => {
+            let prefix = if member.kind == DocumentedKind::ClassMethod {
+                "self."
+            } else {
+                ""
+            };
+            let args = match &member.args {
+                Some(args) if !args.args.is_empty() => args
+                    .args
+                    .iter()
+                    .map(code_to_string)
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                _ => return format!("{}{}", prefix, member.name),
+            };
+            return format!("{}{}({})", prefix, member.name, args);
+        }
+        _ => return member.name.clone(),
+    }
+}
+
+fn push_summary(
+    item: &mut Vec<Inline>,
+    docstring: &Option<YardDocstring>,
+    namespace: &str,
+    index: &ReferenceIndex,
+) {
+    if let Some(docstring) = docstring {
+        if !docstring.summary.is_empty() {
+            item.push(Inline::Text(String::from(" — ")));
+            item.extend(doc_inlines(&docstring.summary, namespace, index));
+        }
+    }
+}
+
+fn push_docstring(
+    blocks: &mut Vec<Block>,
+    docstring: &Option<YardDocstring>,
+    namespace: &str,
+    index: &ReferenceIndex,
+) {
+    let docstring = match docstring {
+        Some(docstring) => docstring,
+        None => return,
+    };
+    if !docstring.summary.is_empty() {
+        blocks.push(Block::Paragraph(doc_inlines(
+            &docstring.summary,
+            namespace,
+            index,
+        )));
+    }
+    for paragraph in docstring.description.split("\n\n") {
+        if !paragraph.trim().is_empty() {
+            blocks.push(Block::Paragraph(doc_inlines(
+                paragraph.trim(),
+                namespace,
+                index,
+            )));
+        }
+    }
+    for tag in docstring.tags.iter() {
+        match tag.tag_name.as_str() {
+            "deprecated" => {
+                let mut paragraph = vec![Inline::Text(String::from("Deprecated: "))];
+                paragraph.extend(doc_inlines(&tag.text, namespace, index));
+                blocks.push(Block::Paragraph(paragraph));
+            }
+            "api" => blocks.push(Block::Paragraph(vec![Inline::Text(format!(
+                "API: {}",
+                tag.text
+            ))])),
+            "note" | "todo" | "since" => {
+                let mut paragraph = vec![Inline::Text(format!("{}: ", capitalize(&tag.tag_name)))];
+                paragraph.extend(doc_inlines(&tag.text, namespace, index));
+                blocks.push(Block::Paragraph(paragraph));
+            }
+            _ => {}
+        }
+    }
+    let see: Vec<Vec<Inline>> = docstring
+        .tags
+        .iter()
+        .filter(|tag| tag.tag_name == "see")
+        .map(|tag| doc_inlines(&see_reference(&tag.text), namespace, index))
+        .collect();
+    if !see.is_empty() {
+        blocks.push(Block::Paragraph(vec![Inline::Text(String::from(
+            "See also:",
+        ))]));
+        blocks.push(Block::List(see));
+    }
+    for tag in docstring
+        .tags
+        .iter()
+        .filter(|tag| tag.tag_name == "example")
+    {
+        let title = match &tag.title {
+            Some(title) => format!("Example: {}", title),
+            None => String::from("Example:"),
+        };
+        blocks.push(Block::Paragraph(vec![Inline::Text(title)]));
+        blocks.push(Block::CodeBlock(tag.text.clone()));
+    }
+}
+
+/** Turn a `@see Reference` into a `{Reference}` link when it names code */
+fn see_reference(text: &str) -> String {
+    let first_word = text.split_whitespace().next().unwrap_or("");
+    let starts_like_code = first_word
+        .chars()
+        .next()
+        .map_or(false, |c| c.is_ascii_uppercase() || c == '#');
+    if starts_like_code && !first_word.contains("://") {
+        return format!("{{{}}}{}", first_word, &text[first_word.len()..]);
+    }
+    return String::from(text);
+}
+
+fn push_member_tags(
+    blocks: &mut Vec<Block>,
+    docstring: &YardDocstring,
+    namespace: &str,
+    index: &ReferenceIndex,
+) {
+    let tag_list = |title: &str, tag_name: &str, blocks: &mut Vec<Block>| {
+        let items: Vec<Vec<Inline>> = docstring
+            .tags
+            .iter()
+            .filter(|tag| tag.tag_name == tag_name)
+            .map(|tag| tag_item(tag, docstring, namespace, index))
+            .collect();
+        if !items.is_empty() {
+            blocks.push(Block::Paragraph(vec![Inline::Text(String::from(title))]));
+            blocks.push(Block::List(items));
+        }
+    };
+    tag_list("Parameters:", "param", blocks);
+    tag_list("Yields:", "yield", blocks);
+    tag_list("Yield parameters:", "yieldparam", blocks);
+    tag_list("Returns:", "return", blocks);
+    tag_list("Raises:", "raise", blocks);
+}
+
+fn tag_item(
+    tag: &YardTag,
+    docstring: &YardDocstring,
+    namespace: &str,
+    index: &ReferenceIndex,
+) -> Vec<Inline> {
+    let mut item: Vec<Inline> = vec![];
+    if let Some(name) = &tag.name {
+        item.push(Inline::Code(name.clone()));
+        item.push(Inline::Text(String::from(" ")));
+    }
+    if !tag.types.is_empty() {
+        item.push(Inline::Text(String::from("(")));
+        for (i, tag_type) in tag.types.iter().enumerate() {
+            if i > 0 {
+                item.push(Inline::Text(String::from(", ")));
+            }
+            if tag.tag_name == "yield" {
+                item.push(Inline::Code(tag_type.clone()));
+            } else {
+                item.push(constant_inline(tag_type, namespace, index));
+            }
+        }
+        item.push(Inline::Text(String::from(")")));
+    }
+    if !tag.text.is_empty() {
+        item.push(Inline::Text(String::from(" ")));
+        item.extend(doc_inlines(&tag.text, namespace, index));
+    }
+    if tag.tag_name == "param" {
+        for option in docstring
+            .tags
+            .iter()
+            .filter(|option| option.tag_name == "option" && option.name == tag.name)
+        {
+            item.push(Inline::Text(String::from("; option ")));
+            item.push(Inline::Code(option.option_key.clone().unwrap_or_default()));
+            if !option.types.is_empty() {
+                item.push(Inline::Text(format!(" ({})", option.types.join(", "))));
+            }
+            if let Some(default) = &option.option_default {
+                item.push(Inline::Text(String::from(" default ")));
+                item.push(Inline::Code(default.clone()));
+            }
+            if !option.text.is_empty() {
+                item.push(Inline::Text(String::from(" ")));
+                item.extend(doc_inlines(&option.text, namespace, index));
+            }
+        }
+    }
+    return item;
+}
+
+/** Split documentation text into text, `code` and `{Reference}` links */
+fn doc_inlines(text: &str, namespace: &str, index: &ReferenceIndex) -> Vec<Inline> {
+    let mut inlines: Vec<Inline> = vec![];
+    let mut current = String::new();
+    let mut characters = text
+        .replace('\n', " ")
+        .chars()
+        .collect::<Vec<char>>()
+        .into_iter();
+    while let Some(character) = characters.next() {
+        let closing = match character {
+            '{' => '}',
+            '`' => '`',
+            _ => {
+                current.push(character);
+                continue;
+            }
+        };
+        let mut inner = String::new();
+        let mut closed = false;
+        for next in characters.by_ref() {
+            if next == closing {
+                closed = true;
+                break;
+            }
+            inner.push(next);
+        }
+        if !closed {
+            current.push(character);
+            current.push_str(&inner);
+            continue;
+        }
+        if !current.is_empty() {
+            inlines.push(Inline::Text(current.clone()));
+            current.clear();
+        }
+        if closing == '`' {
+            inlines.push(Inline::Code(inner));
+            continue;
+        }
+        let (reference, title) = match inner.find(' ') {
+            Some(space) => (&inner[..space], String::from(inner[space..].trim())),
+            None => (inner.as_str(), inner.clone()),
+        };
+        match index.reference_href(reference, namespace) {
+            Some(href) => inlines.push(Inline::Link(title, href)),
+            None => inlines.push(Inline::Code(title)),
+        }
+    }
+    if !current.is_empty() {
+        inlines.push(Inline::Text(current));
+    }
+    return inlines;
+}
+
+fn constant_inline(name: &str, namespace: &str, index: &ReferenceIndex) -> Inline {
+    match index.resolve(name, namespace) {
+        Some(path) => Inline::Link(String::from(name), index.page_file_name(&path)),
+        None => Inline::Code(String::from(name)),
+    }
+}
+
+fn anchor_name(kind: DocumentedKind, name: &str) -> String {
+    let mut anchor = String::from(kind.as_str().replace('_', "-"));
+    anchor.push('-');
+    for character in name.chars() {
+        if character.is_ascii_alphanumeric() || character == '_' {
+            anchor.push(character);
+        } else {
+            anchor.push_str(&format!("-{:x}", character as u32));
+        }
+    }
+    return anchor;
+}
+
+fn capitalize(text: &str) -> String {
+    let mut characters = text.chars();
+    match characters.next() {
+        Some(first) => first.to_uppercase().collect::<String>() + characters.as_str(),
+        None => String::new(),
+    }
+}
+
+fn render_page(title: &str, blocks: &Vec<Block>, format: ReferenceFormat) -> String {
+    match format {
+        ReferenceFormat::Markdown => {
+            let mut page = String::new();
+            for block in blocks.iter() {
+                render_markdown_block(&mut page, block);
+            }
+            return page;
+        }
+        ReferenceFormat::Html => {
+            let mut page = format!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n",
+                escape_html(title),
+                HTML_STYLE
+            );
+            if title != "API Reference" {
+                page.push_str(&format!(
+                    "<nav><a href=\"index.{}\">Index</a></nav>\n",
+                    format.extension()
+                ));
+            }
+            for block in blocks.iter() {
+                render_html_block(&mut page, block);
+            }
+            page.push_str("</body>\n</html>\n");
+            return page;
+        }
+    }
+}
+
+const HTML_STYLE: &str = "body{font-family:sans-serif;max-width:60em;margin:auto;padding:1em}code,pre{background:#f4f4f4}pre{padding:.5em}table{border-collapse:collapse}td,th{border:1px solid #ccc;padding:.2em .5em}";
+
+fn render_markdown_block(page: &mut String, block: &Block) {
+    match block {
+        Block::Anchor(name) => page.push_str(&format!("<a id=\"{}\"></a>\n\n", name)),
+        Block::Heading(level, inlines) => {
+            page.push_str(&"#".repeat(*level as usize));
+            page.push(' ');
+            render_markdown_inlines(page, inlines, false);
+            page.push_str("\n\n");
+        }
+        Block::Paragraph(inlines) => {
+            render_markdown_inlines(page, inlines, false);
+            page.push_str("\n\n");
+        }
+        Block::CodeBlock(code) => {
+            page.push_str("```ruby\n");
+            page.push_str(code);
+            page.push_str("\n```\n\n");
+        }
+        Block::List(items) => {
+            for item in items.iter() {
+                page.push_str("- ");
+                render_markdown_inlines(page, item, false);
+                page.push('\n');
+            }
+            page.push('\n');
+        }
+        Block::Table(headers, rows) => {
+            page.push_str(&format!("| {} |\n", headers.join(" | ")));
+            page.push_str(&format!("|{}\n", " --- |".repeat(headers.len())));
+            for row in rows.iter() {
+                page.push('|');
+                for cell in row.iter() {
+                    page.push(' ');
+                    render_markdown_inlines(page, cell, true);
+                    page.push_str(" |");
+                }
+                page.push('\n');
+            }
+            page.push('\n');
+        }
+    }
+}
+
+fn render_markdown_inlines(page: &mut String, inlines: &Vec<Inline>, in_table: bool) {
+    let mut rendered = String::new();
+    for inline in inlines.iter() {
+        match inline {
+            Inline::Text(text) => rendered.push_str(text),
+            Inline::Code(code) => {
+                let fence = if code.contains('`') { "`` " } else { "`" };
+                rendered.push_str(fence);
+                rendered.push_str(code);
+                rendered.push_str(&fence.chars().rev().collect::<String>());
+            }
+            Inline::Link(text, href) => rendered.push_str(&format!("[{}]({})", text, href)),
+        }
+    }
+    if in_table {
+        rendered = rendered.replace('|', "\\|");
+    }
+    page.push_str(&rendered);
+}
+
+fn render_html_block(page: &mut String, block: &Block) {
+    match block {
+        Block::Anchor(name) => page.push_str(&format!("<a id=\"{}\"></a>\n", name)),
+        Block::Heading(level, inlines) => {
+            page.push_str(&format!("<h{}>", level));
+            render_html_inlines(page, inlines);
+            page.push_str(&format!("</h{}>\n", level));
+        }
+        Block::Paragraph(inlines) => {
+            page.push_str("<p>");
+            render_html_inlines(page, inlines);
+            page.push_str("</p>\n");
+        }
+        Block::CodeBlock(code) => {
+            page.push_str("<pre><code>");
+            page.push_str(&escape_html(code));
+            page.push_str("</code></pre>\n");
+        }
+        Block::List(items) => {
+            page.push_str("<ul>\n");
+            for item in items.iter() {
+                page.push_str("<li>");
+                render_html_inlines(page, item);
+                page.push_str("</li>\n");
+            }
+            page.push_str("</ul>\n");
+        }
+        Block::Table(headers, rows) => {
+            page.push_str("<table>\n<tr>");
+            for header in headers.iter() {
+                page.push_str(&format!("<th>{}</th>", header));
+            }
+            page.push_str("</tr>\n");
+            for row in rows.iter() {
+                page.push_str("<tr>");
+                for cell in row.iter() {
+                    page.push_str("<td>");
+                    render_html_inlines(page, cell);
+                    page.push_str("</td>");
+                }
+                page.push_str("</tr>\n");
+            }
+            page.push_str("</table>\n");
+        }
+    }
+}
+
+fn render_html_inlines(page: &mut String, inlines: &Vec<Inline>) {
+    for inline in inlines.iter() {
+        match inline {
+            Inline::Text(text) => page.push_str(&escape_html(text)),
+            Inline::Code(code) => page.push_str(&format!("<code>{}</code>", escape_html(code))),
+            Inline::Link(text, href) => page.push_str(&format!(
+                "<a href=\"{}\">{}</a>",
+                escape_html(href),
+                escape_html(text)
+            )),
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    return text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
@@ -0,0 +1,762 @@
+use std::collections::HashSet;
+
+use lib_ruby_parser::{
+    nodes::{Arg, Args, Array, Begin, Block, Dstr, If, Lambda, Send},
+    source::{Comment, DecodedInput},
+    Loc, Node,
+};
+
+use crate::{
+    builder as b,
+    code_writer::code_to_string,
+    rename::Replacement,
+    scope_analysis::ScopeTree,
+    search_replace::{is_primary, write_replacement},
+    tests::edit_methods::RUBY_KEYWORDS,
+    visitor::{children_with_steps, walk_mut, PathStep, VisitorMut},
+};
+
+/** Style correction of the code, each of them being enabled on its own */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Autocorrection {
+    /** `{ :a => 1 }` => `{ a: 1 }` when every key can be written that way */
+    HashRockets,
+    /** `lambda { |x| x }` => `-> (x) { x }` */
+    StabbyLambda,
+    /** `if !x` => `unless x` */
+    NegatedIf,
+    /** `for x in list` => `list.each do |x|`, unless the body assigns locals or `x` is used outside of it */
+    ForToEach,
+    /** `x == nil` => `x.nil?` */
+    NilComparison,
+    /** `"a" + b + "c"` => `"a#{b}c"` */
+    StringInterpolation,
+    /** `LIST = [1, 2]` => `LIST = [1, 2].freeze` */
+    FrozenConstants,
+    /** `return x` as last statement of a method => `x` */
+    RedundantReturn,
+    /** `self.foo` => `foo` when no local variable is named `foo` */
+    RedundantSelf,
+    /** `a ? b : c ? d : e` => `if a ... else if c ...` */
+    NestedTernary,
+}
+
+impl Autocorrection {
+    pub const ALL: [Autocorrection; 10] = [
+        Autocorrection::HashRockets,
+        Autocorrection::StabbyLambda,
+        Autocorrection::NegatedIf,
+        Autocorrection::ForToEach,
+        Autocorrection::NilComparison,
+        Autocorrection::StringInterpolation,
+        Autocorrection::FrozenConstants,
+        Autocorrection::RedundantReturn,
+        Autocorrection::RedundantSelf,
+        Autocorrection::NestedTernary,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Autocorrection::HashRockets => "hash_rockets",
+            Autocorrection::StabbyLambda => "stabby_lambda",
+            Autocorrection::NegatedIf => "negated_if",
+            Autocorrection::ForToEach => "for_to_each",
+            Autocorrection::NilComparison => "nil_comparison",
+            Autocorrection::StringInterpolation => "string_interpolation",
+            Autocorrection::FrozenConstants => "frozen_constants",
+            Autocorrection::RedundantReturn => "redundant_return",
+            Autocorrection::RedundantSelf => "redundant_self",
+            Autocorrection::NestedTernary => "nested_ternary",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        return Autocorrection::ALL
+            .iter()
+            .find(|correction| correction.name() == name)
+            .copied();
+    }
+
+    /** Parse a comma separated list of names, `all` enabling every correction */
+    pub fn parse_list(list: &str) -> Result<Vec<Self>, String> {
+        let mut corrections: Vec<Autocorrection> = vec![];
+        for name in list
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            if name == "all" {
+                return Ok(Autocorrection::ALL.to_vec());
+            }
+            match Autocorrection::from_name(name) {
+                Some(correction) => corrections.push(correction),
+                None => return Err(format!("unknown correction {}", name)),
+            }
+        }
+        return Ok(corrections);
+    }
+}
+
+/** Code changed by a correction */
+#[derive(Debug, Clone)]
+pub struct Correction {
+    pub autocorrection: Autocorrection,
+    /** Range of the original code */
+    pub range: Loc,
+    /** Location of the node written in place of the range */
+    anchor: Loc,
+    /** Corrected code (inner corrections included) */
+    pub code: String,
+}
+
+impl Correction {
+    pub fn describe(&self, input: &DecodedInput) -> String {
+        let line = input
+            .line_col_for_pos(self.range.begin)
+            .map_or(0, |(line, _)| line + 1);
+        let before =
+            String::from_utf8_lossy(&input.bytes[self.range.begin..self.range.end]).to_string();
+        return format!(
+            "line {}: {}: {} => {}",
+            line,
+            self.autocorrection.name(),
+            first_line(&before),
+            first_line(&self.code)
+        );
+    }
+}
+
+fn first_line(code: &str) -> String {
+    let mut lines = code.trim().lines();
+    let first = lines.next().unwrap_or("");
+    if lines.next().is_some() {
+        return format!("{} ...", first);
+    }
+    return String::from(first);
+}
+
+/** Apply the enabled corrections to a program, innermost code first */
+pub fn autocorrect(
+    node: &mut Node,
+    autocorrections: &[Autocorrection],
+    comments: &[Comment],
+    input: &DecodedInput,
+) -> Vec<Correction> {
+    let scope_tree = ScopeTree::new(node);
+    let local_names: HashSet<String> = scope_tree
+        .variables
+        .iter()
+        .map(|variable| variable.name.clone())
+        .collect();
+    let frozen_string_literals = comments.iter().any(|comment| {
+        String::from_utf8_lossy(&input.bytes[comment.location.begin..comment.location.end])
+            .replace(' ', "")
+            .contains("frozen_string_literal:true")
+    });
+    let mut corrector = Corrector {
+        autocorrections,
+        scope_tree,
+        local_names,
+        frozen_string_literals,
+        corrections: vec![],
+    };
+    walk_mut(&mut corrector, node);
+    let mut corrections = corrector.corrections;
+    corrections.sort_by_key(|correction| correction.range.begin);
+    return corrections;
+}
+
+/** Replacements writing the outermost corrections in the source of the program */
+pub fn corrected_replacements(
+    node: &Node,
+    corrections: &[Correction],
+    input: &DecodedInput,
+) -> Vec<Replacement> {
+    let mut outermost: Vec<&Correction> = corrections.iter().collect();
+    outermost.sort_by_key(|correction| (correction.range.begin, usize::MAX - correction.range.end));
+    let mut replacements: Vec<Replacement> = vec![];
+    let mut covered_end = 0;
+    for correction in outermost {
+        if correction.range.begin < covered_end {
+            continue;
+        }
+        if let Some(corrected) = find_node(node, &correction.anchor) {
+            replacements.push(write_replacement(corrected, correction.range, input));
+            covered_end = correction.range.end;
+        }
+    }
+    return replacements;
+}
+
+/** First node (in pre-order) at a location */
+fn find_node<'a>(node: &'a Node, expression_l: &Loc) -> Option<&'a Node> {
+    if node.expression() == expression_l {
+        return Some(node);
+    }
+    let expression = node.expression();
+    if expression.begin > expression_l.begin || expression.end < expression_l.end {
+        // Parentheses added by a correction have no location
+        if expression.size() > 0 {
+            return None;
+        }
+    }
+    return children_with_steps(node)
+        .into_iter()
+        .find_map(|(_, child)| find_node(child, expression_l));
+}
+
+struct Corrector<'a> {
+    autocorrections: &'a [Autocorrection],
+    /** Scopes of the program before the corrections, located in the original source */
+    scope_tree: ScopeTree,
+    local_names: HashSet<String>,
+    frozen_string_literals: bool,
+    corrections: Vec<Correction>,
+}
+
+impl<'a> Corrector<'a> {
+    fn enabled(&self, autocorrection: Autocorrection) -> bool {
+        return self.autocorrections.contains(&autocorrection);
+    }
+
+    /** Record a correction, replacing the ones of the same kind it contains (`"a" + b + "c"`) */
+    fn record(&mut self, autocorrection: Autocorrection, range: Loc, anchor: Loc, node: &Node) {
+        self.corrections.retain(|correction| {
+            correction.autocorrection != autocorrection
+                || correction.range.begin < range.begin
+                || correction.range.end > range.end
+        });
+        self.corrections.push(Correction {
+            autocorrection,
+            range,
+            anchor,
+            code: code_to_string(node),
+        });
+    }
+
+    fn correct_hash_rockets(&mut self, node: &mut Node) {
+        let pairs = match node {
+            Node::Hash(hash) => &mut hash.pairs,
+            Node::Kwargs(kwargs) => &mut kwargs.pairs,
+            _ => return,
+        };
+        let mut has_rocket = false;
+        for pair in pairs.iter() {
+            match pair {
+                Node::Pair(pair) if pair.operator_l.size() >= 2 => {
+                    if !is_simple_symbol(&pair.key) {
+                        return;
+                    }
+                    has_rocket = true;
+                }
+                Node::Pair(_) | Node::Kwsplat(_) => {}
+                _ => return,
+            }
+        }
+        if !has_rocket {
+            return;
+        }
+        for pair in pairs.iter_mut() {
+            if let Node::Pair(pair) = pair {
+                pair.operator_l.end = pair.operator_l.begin + 1;
+            }
+        }
+        let expression_l = *node.expression();
+        self.record(
+            Autocorrection::HashRockets,
+            expression_l,
+            expression_l,
+            node,
+        );
+    }
+
+    fn correct_stabby_lambda(&mut self, node: &mut Node) {
+        let block = match node {
+            Node::Block(block) => block,
+            _ => return,
+        };
+        match block.call.as_ref() {
+            Node::Send(send)
+                if send.recv.is_none() && send.args.is_empty() && send.method_name == "lambda" =>
+            {
+                *block.call = Node::Lambda(Lambda {
+                    expression_l: send.expression_l,
+                });
+            }
+            _ => return,
+        }
+        let expression_l = block.expression_l;
+        self.record(
+            Autocorrection::StabbyLambda,
+            expression_l,
+            expression_l,
+            node,
+        );
+    }
+
+    fn correct_negated_if(&mut self, node: &mut Node) {
+        let (cond, if_true, if_false) = match node {
+            Node::If(if_kw) => (&mut if_kw.cond, &mut if_kw.if_true, &mut if_kw.if_false),
+            Node::IfMod(if_mod) => (&mut if_mod.cond, &mut if_mod.if_true, &mut if_mod.if_false),
+            _ => return,
+        };
+        if if_true.is_none() || if_false.is_some() {
+            return;
+        }
+        let negated = match cond.as_mut() {
+            Node::Send(send) if send.method_name == "!" && send.args.is_empty() => {
+                match send.recv.take() {
+                    // `if !!x` stays as is
+                    Some(recv) if !matches!(*recv, Node::Send(ref send) if send.method_name == "!") => {
+                        recv
+                    }
+                    recv => {
+                        send.recv = recv;
+                        return;
+                    }
+                }
+            }
+            _ => return,
+        };
+        *cond = negated;
+        *if_false = if_true.take();
+        let expression_l = *node.expression();
+        self.record(Autocorrection::NegatedIf, expression_l, expression_l, node);
+    }
+
+    fn correct_for_to_each(&mut self, node: &mut Node) {
+        let for_kw = match node {
+            Node::For(for_kw) => for_kw,
+            _ => return,
+        };
+        // `for` does not open a scope, variables assigned in its body are visible after it
+        if for_kw.body.as_deref().map_or(false, assigns_local_variable) {
+            return;
+        }
+        let iterators: Vec<&Node> = match for_kw.iterator.as_ref() {
+            Node::Mlhs(mlhs) => mlhs.items.iter().collect(),
+            iterator => vec![iterator],
+        };
+        let mut args: Vec<Node> = vec![];
+        for iterator in iterators {
+            match iterator {
+                Node::Lvasgn(asgn) if asgn.value.is_none() => {
+                    // A block argument does not exist outside the block
+                    if self.is_used_outside(asgn.expression_l, for_kw.expression_l) {
+                        return;
+                    }
+                    args.push(Node::Arg(Arg {
+                        name: asgn.name.clone(),
+                        expression_l: asgn.expression_l,
+                    }))
+                }
+                _ => return,
+            }
+        }
+        let iterator_l = *for_kw.iterator.expression();
+        let iteratee = *for_kw.iteratee.clone();
+        let recv = if is_primary(&iteratee) {
+            iteratee
+        } else {
+            b::parenthesize(iteratee)
+        };
+        let expression_l = for_kw.expression_l;
+        let each = Node::Send(Send {
+            recv: Some(Box::new(recv)),
+            method_name: String::from("each"),
+            args: vec![],
+            dot_l: Some(for_kw.keyword_l),
+            selector_l: Some(for_kw.keyword_l),
+            begin_l: None,
+            end_l: None,
+            operator_l: None,
+            expression_l: for_kw.iteratee.expression().join(&for_kw.keyword_l),
+        });
+        *node = Node::Block(Block {
+            call: Box::new(each),
+            args: Some(Box::new(Node::Args(Args {
+                args,
+                expression_l: iterator_l,
+                begin_l: Some(iterator_l),
+                end_l: Some(iterator_l),
+            }))),
+            body: for_kw.body.take(),
+            begin_l: Loc {
+                begin: for_kw.begin_l.begin,
+                end: for_kw.begin_l.begin + 2,
+            },
+            end_l: for_kw.end_l,
+            expression_l,
+        });
+        self.record(Autocorrection::ForToEach, expression_l, expression_l, node);
+    }
+
+    /** Whether the variable assigned at a location is declared or read outside of a range */
+    fn is_used_outside(&self, assignment_l: Loc, range: Loc) -> bool {
+        let variable_id = match self.scope_tree.variable_at(assignment_l.begin) {
+            Some(variable_id) => variable_id,
+            None => return true,
+        };
+        return self.scope_tree.variables[variable_id]
+            .occurrences
+            .iter()
+            .any(|occurrence| {
+                occurrence.expression_l.begin < range.begin
+                    || occurrence.expression_l.end > range.end
+            });
+    }
+
+    fn correct_nil_comparison(&mut self, node: &mut Node) {
+        let send = match node {
+            Node::Send(send) => send,
+            _ => return,
+        };
+        if send.method_name != "==" || send.args.len() != 1 || !matches!(send.args[0], Node::Nil(_))
+        {
+            return;
+        }
+        let recv = match send.recv.take() {
+            Some(recv) if is_primary(&recv) => recv,
+            Some(recv) => Box::new(b::parenthesize(*recv)),
+            None => return,
+        };
+        let expression_l = send.expression_l;
+        *node = located_send(Some(*recv), "nil?", expression_l);
+        self.record(
+            Autocorrection::NilComparison,
+            expression_l,
+            expression_l,
+            node,
+        );
+    }
+
+    fn correct_string_interpolation(&mut self, node: &mut Node) {
+        let operands = match concatenated_strings(node) {
+            Some(operands) => operands,
+            None => return,
+        };
+        let mut parts: Vec<Node> = vec![];
+        for operand in operands {
+            match operand {
+                Node::Str(str) => parts.push(Node::Str(lib_ruby_parser::nodes::Str {
+                    begin_l: None,
+                    end_l: None,
+                    ..str.clone()
+                })),
+                Node::Dstr(dstr) => parts.extend(dstr.parts.iter().cloned()),
+                operand => {
+                    let expression_l = *operand.expression();
+                    parts.push(Node::Begin(Begin {
+                        statements: vec![operand.clone()],
+                        begin_l: Some(expression_l),
+                        end_l: Some(expression_l),
+                        expression_l,
+                    }))
+                }
+            }
+        }
+        let expression_l = *node.expression();
+        *node = Node::Dstr(Dstr {
+            parts,
+            begin_l: Some(Loc {
+                begin: expression_l.begin,
+                end: expression_l.begin + 1,
+            }),
+            end_l: Some(Loc {
+                begin: expression_l.end - 1,
+                end: expression_l.end,
+            }),
+            expression_l,
+        });
+        self.record(
+            Autocorrection::StringInterpolation,
+            expression_l,
+            expression_l,
+            node,
+        );
+    }
+
+    fn correct_frozen_constant(&mut self, node: &mut Node) {
+        let asgn = match node {
+            Node::Casgn(asgn) => asgn,
+            _ => return,
+        };
+        let value = match asgn.value.as_mut() {
+            Some(value) => value,
+            None => return,
+        };
+        let mutable = match value.as_ref() {
+            Node::Array(array) => array.begin_l.is_some(),
+            Node::Hash(hash) => hash.begin_l.is_some(),
+            Node::Str(str) => str.begin_l.is_some() && !self.frozen_string_literals,
+            Node::Dstr(dstr) => dstr.begin_l.is_some(),
+            _ => false,
+        };
+        if !mutable {
+            return;
+        }
+        let expression_l = *value.expression();
+        let literal = std::mem::replace(
+            value.as_mut(),
+            Node::Nil(lib_ruby_parser::nodes::Nil { expression_l }),
+        );
+        **value = located_send(Some(literal), "freeze", expression_l);
+        let frozen = value.as_ref().clone();
+        self.record(
+            Autocorrection::FrozenConstants,
+            expression_l,
+            expression_l,
+            &frozen,
+        );
+    }
+
+    fn correct_redundant_return(&mut self, node: &mut Node) {
+        let body = match node {
+            Node::Def(def) => def.body.as_deref_mut(),
+            Node::Defs(defs) => defs.body.as_deref_mut(),
+            _ => return,
+        };
+        if let Some(body) = body {
+            self.remove_tail_return(body);
+        }
+    }
+
+    /** Drop the `return` of the statement giving the result of a method body */
+    fn remove_tail_return(&mut self, node: &mut Node) {
+        match node {
+            Node::Begin(begin) if begin.begin_l.is_none() => {
+                if let Some(last) = begin.statements.last_mut() {
+                    self.remove_tail_return(last);
+                }
+            }
+            Node::If(if_kw) => {
+                for branch in [&mut if_kw.if_true, &mut if_kw.if_false] {
+                    if let Some(branch) = branch {
+                        self.remove_tail_return(branch);
+                    }
+                }
+            }
+            Node::Case(case) => {
+                for when in case.when_bodies.iter_mut() {
+                    if let Node::When(when) = when {
+                        if let Some(body) = &mut when.body {
+                            self.remove_tail_return(body);
+                        }
+                    }
+                }
+                if let Some(else_body) = &mut case.else_body {
+                    self.remove_tail_return(else_body);
+                }
+            }
+            Node::Return(return_) => {
+                let range = return_.expression_l;
+                let value = match return_.args.len() {
+                    0 => return,
+                    1 => match &return_.args[0] {
+                        // `return a: 1` and `return *list` need the brackets of an array
+                        Node::Hash(hash) if hash.begin_l.is_none() => return,
+                        Node::Splat(_) => return,
+                        arg => arg.clone(),
+                    },
+                    _ => Node::Array(Array {
+                        elements: return_.args.clone(),
+                        begin_l: Some(return_.keyword_l),
+                        end_l: Some(return_.keyword_l),
+                        expression_l: range,
+                    }),
+                };
+                let anchor = *value.expression();
+                *node = value;
+                self.record(Autocorrection::RedundantReturn, range, anchor, node);
+            }
+            _ => {}
+        }
+    }
+
+    fn correct_redundant_self(&mut self, node: &mut Node) {
+        let send = match node {
+            Node::Send(send) => send,
+            _ => return,
+        };
+        if !matches!(send.recv.as_deref(), Some(Node::Self_(_)))
+            || send.dot_l.is_none()
+            || send.operator_l.is_some()
+            || send.method_name.ends_with('=')
+            || !send
+                .method_name
+                .starts_with(|c: char| c.is_lowercase() || c == '_')
+            || RUBY_KEYWORDS.contains(&send.method_name.as_str())
+            || self.local_names.contains(&send.method_name)
+        {
+            return;
+        }
+        send.recv = None;
+        send.dot_l = None;
+        let expression_l = send.expression_l;
+        self.record(
+            Autocorrection::RedundantSelf,
+            expression_l,
+            expression_l,
+            node,
+        );
+    }
+
+    fn correct_nested_ternary(&mut self, node: &mut Node, path: &[PathStep]) {
+        let ternary = match node {
+            Node::IfTernary(ternary) => ternary,
+            _ => return,
+        };
+        if !is_ternary(&ternary.if_true) && !is_ternary(&ternary.if_false) {
+            return;
+        }
+        // An `if` only reads well where a statement or an assigned value is expected
+        let statement_position = match path.last() {
+            None => true,
+            Some(step) => {
+                matches!(step.field, "statements" | "body")
+                    || (step.parent_type == "if" && matches!(step.field, "if_true" | "if_false"))
+                    || (step.field == "value" && step.parent_type.ends_with("asgn"))
+            }
+        };
+        if !statement_position {
+            return;
+        }
+        let expression_l = ternary.expression_l;
+        *node = ternary_to_if(node.clone());
+        self.record(
+            Autocorrection::NestedTernary,
+            expression_l,
+            expression_l,
+            node,
+        );
+    }
+}
+
+impl<'a> VisitorMut for Corrector<'a> {
+    fn leave(&mut self, node: &mut Node, path: &[PathStep]) {
+        if self.enabled(Autocorrection::HashRockets) {
+            self.correct_hash_rockets(node);
+        }
+        if self.enabled(Autocorrection::StabbyLambda) {
+            self.correct_stabby_lambda(node);
+        }
+        if self.enabled(Autocorrection::NegatedIf) {
+            self.correct_negated_if(node);
+        }
+        if self.enabled(Autocorrection::ForToEach) {
+            self.correct_for_to_each(node);
+        }
+        if self.enabled(Autocorrection::NilComparison) {
+            self.correct_nil_comparison(node);
+        }
+        if self.enabled(Autocorrection::StringInterpolation) {
+            self.correct_string_interpolation(node);
+        }
+        if self.enabled(Autocorrection::FrozenConstants) {
+            self.correct_frozen_constant(node);
+        }
+        if self.enabled(Autocorrection::RedundantReturn) {
+            self.correct_redundant_return(node);
+        }
+        if self.enabled(Autocorrection::RedundantSelf) {
+            self.correct_redundant_self(node);
+        }
+        if self.enabled(Autocorrection::NestedTernary) {
+            self.correct_nested_ternary(node, path);
+        }
+    }
+}
+
+/** Call built by the builder at the location of the corrected node, where `find_node` looks */
+fn located_send(recv: Option<Node>, name: &str, expression_l: Loc) -> Node {
+    match b::send(recv, name, vec![]) {
+        Node::Send(send) => {
+            return Node::Send(Send {
+                expression_l,
+                ..send
+            })
+        }
+        node => return node,
+    }
+}
+
+/** Symbol that can be written as a `key:` */
+fn is_simple_symbol(node: &Node) -> bool {
+    match node {
+        Node::Sym(sym) if sym.begin_l.is_some() && sym.end_l.is_none() => {
+            let name = sym.name.to_string_lossy();
+            !name.is_empty()
+                && name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn is_string_literal(node: &Node) -> bool {
+    match node {
+        Node::Str(str) => str.begin_l.map_or(false, |begin_l| begin_l.size() == 1),
+        Node::Dstr(dstr) => dstr.begin_l.map_or(false, |begin_l| begin_l.size() == 1),
+        _ => false,
+    }
+}
+
+/**
+ * Operands of a `+` chain building a string from a literal: `"a" + b + c` but not `a + b + "c"`
+ * where `a + b` is computed first (it is kept as one operand).
+ */
+fn concatenated_strings(node: &Node) -> Option<Vec<&Node>> {
+    let send = match node {
+        Node::Send(send) if send.method_name == "+" && send.args.len() == 1 => send,
+        _ => return None,
+    };
+    let recv = send.recv.as_deref()?;
+    let arg = &send.args[0];
+    let mut operands = match concatenated_strings(recv) {
+        Some(operands) => operands,
+        None if is_string_literal(recv) || is_string_literal(arg) => vec![recv],
+        None => return None,
+    };
+    operands.push(arg);
+    return Some(operands);
+}
+
+fn assigns_local_variable(node: &Node) -> bool {
+    return matches!(node, Node::Lvasgn(_))
+        || children_with_steps(node)
+            .into_iter()
+            .any(|(_, child)| assigns_local_variable(child));
+}
+
+fn is_ternary(node: &Node) -> bool {
+    match node {
+        Node::IfTernary(_) => true,
+        Node::Begin(begin) if begin.statements.len() == 1 => is_ternary(&begin.statements[0]),
+        _ => false,
+    }
+}
+
+/** Write a ternary (and the ternaries of its branches) as an `if` */
+fn ternary_to_if(node: Node) -> Node {
+    match node {
+        Node::IfTernary(ternary) => {
+            let expression_l = ternary.expression_l;
+            return Node::If(If {
+                cond: ternary.cond,
+                if_true: Some(Box::new(ternary_to_if(*ternary.if_true))),
+                if_false: Some(Box::new(ternary_to_if(*ternary.if_false))),
+                keyword_l: ternary.question_l,
+                begin_l: ternary.question_l,
+                else_l: Some(ternary.colon_l),
+                end_l: Some(expression_l),
+                expression_l,
+            });
+        }
+        Node::Begin(mut begin)
+            if begin.statements.len() == 1 && is_ternary(&begin.statements[0]) =>
+        {
+            return ternary_to_if(begin.statements.remove(0));
+        }
+        node => return node,
+    }
+}
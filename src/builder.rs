@@ -0,0 +1,173 @@
+use lib_ruby_parser::{
+    nodes::{Array, Begin, Cbase, Const, False, Float, Int, Module, Nil, Send, Str, Sym, True},
+    Bytes, Loc, Node,
+};
+
+/**
+ * Location of a token of some length. Generated nodes are located at the start of the file, the
+ * writer only looks at which optional locations are present and at their size (`do` or `{`, `=>` or
+ * `:`...).
+ */
+fn loc(size: usize) -> Loc {
+    return Loc {
+        begin: 0,
+        end: size,
+    };
+}
+
+pub fn nil() -> Node {
+    return Node::Nil(Nil {
+        expression_l: loc(3),
+    });
+}
+
+pub fn bool(value: bool) -> Node {
+    if value {
+        return Node::True(True {
+            expression_l: loc(4),
+        });
+    }
+    return Node::False(False {
+        expression_l: loc(5),
+    });
+}
+
+pub fn int(value: i64) -> Node {
+    return Node::Int(Int {
+        value: value.to_string(),
+        operator_l: None,
+        expression_l: loc(1),
+    });
+}
+
+/** Float, always written with a decimal part (`2.0`) */
+pub fn float(value: f64) -> Node {
+    let value = if value.is_finite() && value.fract() == 0.0 {
+        format!("{:.1}", value)
+    } else {
+        value.to_string()
+    };
+    return Node::Float(Float {
+        value,
+        operator_l: None,
+        expression_l: loc(1),
+    });
+}
+
+pub fn str(value: &str) -> Node {
+    return Node::Str(Str {
+        value: Bytes::new(value.as_bytes().to_vec()),
+        begin_l: Some(loc(1)),
+        end_l: Some(loc(1)),
+        expression_l: loc(1),
+    });
+}
+
+/** Symbol, quoted (`:"a-b"`) when the name is not an identifier */
+pub fn sym(name: &str) -> Node {
+    let quoted = name.is_empty()
+        || name.starts_with(|c: char| c.is_ascii_digit())
+        || !name
+            .trim_end_matches(|c| c == '?' || c == '!' || c == '=')
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_');
+    return Node::Sym(Sym {
+        name: Bytes::new(name.as_bytes().to_vec()),
+        begin_l: Some(loc(if quoted { 2 } else { 1 })),
+        end_l: if quoted { Some(loc(1)) } else { None },
+        expression_l: loc(1),
+    });
+}
+
+pub fn array(elements: Vec<Node>) -> Node {
+    return Node::Array(Array {
+        elements,
+        begin_l: Some(loc(1)),
+        end_l: Some(loc(1)),
+        expression_l: loc(1),
+    });
+}
+
+/** Constant from its path: `Foo::Bar`, `::Foo` */
+pub fn const_(path: &str) -> Node {
+    let mut scope: Option<Box<Node>> = None;
+    let mut double_colon_l: Option<Loc> = None;
+    let mut node = nil();
+    for (index, name) in path.split("::").enumerate() {
+        if name.is_empty() {
+            if index == 0 {
+                scope = Some(Box::new(Node::Cbase(Cbase {
+                    expression_l: loc(2),
+                })));
+                double_colon_l = Some(loc(2));
+            }
+            continue;
+        }
+        node = Node::Const(Const {
+            scope: scope.take(),
+            name: String::from(name),
+            double_colon_l: double_colon_l.take(),
+            name_l: loc(1),
+            expression_l: loc(1),
+        });
+        scope = Some(Box::new(node.clone()));
+        double_colon_l = Some(loc(2));
+    }
+    return node;
+}
+
+/**
+ * Method call: `recv.name(args)`, `name(args)` or `name` without arguments. Operators are written
+ * between the receiver and the argument (`a + b`), setters as an assignment (`a.b = c`).
+ */
+pub fn send(recv: Option<Node>, name: &str, args: Vec<Node>) -> Node {
+    let is_operator = !name.starts_with(|c: char| c.is_alphabetic() || c == '_');
+    let is_setter = !is_operator && name.ends_with('=') && recv.is_some() && args.len() == 1;
+    let parenthesized = !is_operator && !is_setter && !args.is_empty();
+    return Node::Send(Send {
+        dot_l: if recv.is_some() && !is_operator {
+            Some(loc(1))
+        } else {
+            None
+        },
+        recv: recv.map(Box::new),
+        method_name: String::from(name),
+        args,
+        selector_l: Some(loc(name.len())),
+        begin_l: if parenthesized { Some(loc(1)) } else { None },
+        end_l: if parenthesized { Some(loc(1)) } else { None },
+        operator_l: if is_setter { Some(loc(1)) } else { None },
+        expression_l: loc(1),
+    });
+}
+
+/** Statements written one after the other */
+pub fn begin(statements: Vec<Node>) -> Node {
+    return Node::Begin(Begin {
+        statements,
+        begin_l: None,
+        end_l: None,
+        expression_l: loc(1),
+    });
+}
+
+/** `(node)` */
+pub fn parenthesize(node: Node) -> Node {
+    return Node::Begin(Begin {
+        statements: vec![node],
+        begin_l: Some(loc(1)),
+        end_l: Some(loc(1)),
+        expression_l: loc(1),
+    });
+}
+
+/** `module Name body end`, the name being a constant path */
+pub fn module(name: &str, body: Option<Node>) -> Node {
+    return Node::Module(Module {
+        name: Box::new(const_(name)),
+        body: body.map(Box::new),
+        keyword_l: loc(6),
+        end_l: loc(3),
+        expression_l: loc(1),
+    });
+}
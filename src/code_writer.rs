@@ -1,12 +1,10 @@
 use std::{
+    collections::HashMap,
     io::{BufWriter, Write},
     rc::Rc,
 };
 
-use lib_ruby_parser::{
-    source::{Comment, DecodedInput},
-    Bytes, Node,
-};
+use lib_ruby_parser::{nodes::Send, Bytes, Node};
 
 use crate::{
     documentation_context::DocumentationContext, write_array, write_assign,
@@ -18,6 +16,10 @@ pub struct CodeWriterContext {
     pub parent_node_type: &'static str,
     pub indent: u32,
     documentation_context: Option<Rc<DocumentationContext>>,
+    /** Comments written after constant assignments, by beginning of the assignment */
+    trailing_comments: Option<Rc<HashMap<usize, String>>>,
+    /** Write the code on a single line, without optional spaces */
+    minified: bool,
 }
 
 impl CodeWriterContext {
@@ -26,22 +28,38 @@ impl CodeWriterContext {
             parent_node_type: "none",
             indent: 0,
             documentation_context: None,
+            trailing_comments: None,
+            minified: false,
+        };
+    }
+
+    pub fn new_with_trailing_comments(trailing_comments: HashMap<usize, String>) -> Self {
+        return CodeWriterContext {
+            parent_node_type: "none",
+            indent: 0,
+            documentation_context: None,
+            trailing_comments: Some(Rc::new(trailing_comments)),
+            minified: false,
         };
     }
 
-    pub fn new_with_documentation(
-        comments: Vec<Comment>,
-        input: DecodedInput,
-        exclude_method_body: bool,
-    ) -> Self {
+    pub fn new_minified() -> Self {
         return CodeWriterContext {
             parent_node_type: "none",
             indent: 0,
-            documentation_context: Some(Rc::new(DocumentationContext::new(
-                comments,
-                input,
-                exclude_method_body,
-            ))),
+            documentation_context: None,
+            trailing_comments: None,
+            minified: true,
+        };
+    }
+
+    pub fn new_with_documentation(documentation_context: DocumentationContext) -> Self {
+        return CodeWriterContext {
+            parent_node_type: "none",
+            indent: 0,
+            documentation_context: Some(Rc::new(documentation_context)),
+            trailing_comments: None,
+            minified: false,
         };
     }
 
@@ -50,6 +68,8 @@ impl CodeWriterContext {
             parent_node_type: node_type,
             indent: self.indent,
             documentation_context: self.documentation_context.clone(),
+            trailing_comments: self.trailing_comments.clone(),
+            minified: self.minified,
         };
     }
 
@@ -58,6 +78,8 @@ impl CodeWriterContext {
             parent_node_type: self.parent_node_type,
             indent: self.indent + 1,
             documentation_context: self.documentation_context.clone(),
+            trailing_comments: self.trailing_comments.clone(),
+            minified: self.minified,
         };
     }
 
@@ -66,6 +88,8 @@ impl CodeWriterContext {
             parent_node_type: self.parent_node_type,
             indent: self.indent - 1,
             documentation_context: self.documentation_context.clone(),
+            trailing_comments: self.trailing_comments.clone(),
+            minified: self.minified,
         };
     }
 }
@@ -78,6 +102,7 @@ pub fn write_code<W: Write>(
     let child_context = context.make_child(node.str_type());
     match node {
         Node::Alias(alias) => {
+            write_documentation!(alias, writer, context);
             writer.write(b"alias ")?;
             write_code(&alias.to, writer, &child_context)?;
             writer.write(b" ")?;
@@ -85,19 +110,29 @@ pub fn write_code<W: Write>(
         }
         Node::And(and) => {
             write_code(&and.lhs, writer, &child_context)?;
-            writer.write(b" && ")?;
+            write_spaced(writer, context, b" && ")?;
             write_code(&and.rhs, writer, &child_context)?;
         }
         Node::AndAsgn(asgn) => {
             write_code(&asgn.recv, writer, &child_context)?;
-            writer.write(b" &&= ")?;
+            write_spaced(writer, context, b" &&= ")?;
             write_code(&asgn.value, writer, &child_context)?;
         }
         Node::Arg(arg) => {
             writer.write(arg.name.as_bytes())?;
         }
         Node::Args(args) => {
-            write_code_with_separator(&args.args, writer, &child_context, b", ")?;
+            // Block local variables come last, after a `;` (`|item; tmp|`)
+            let mut shadowing = false;
+            for (i, arg) in args.args.iter().enumerate() {
+                if let (Node::Shadowarg(_), false) = (arg, shadowing) {
+                    write_spaced(writer, context, if i == 0 { b";" } else { b"; " })?;
+                    shadowing = true;
+                } else if i > 0 {
+                    write_spaced(writer, context, b", ")?;
+                }
+                write_code(arg, writer, &child_context)?;
+            }
         }
         Node::Array(arr) => {
             if let Some(begin) = &arr.begin_l {
@@ -146,20 +181,37 @@ pub fn write_code<W: Write>(
             match &block.args {
                 Some(args) => {
                     if let Node::Lambda(_) = block.call.as_ref() {
-                        writer.write(b" (")?;
+                        write_spaced(writer, context, b" (")?;
+                        write_code(&args, writer, &child_context)?;
+                        if do_block {
+                            writer.write(b") do")?;
+                            write_newline(writer, context)?;
+                        } else {
+                            write_spaced(writer, context, b") { ")?;
+                        }
+                    } else if do_block {
+                        writer.write(b" do |")?;
                         write_code(&args, writer, &child_context)?;
-                        writer.write(if do_block { b") do\n" } else { b") { " })?;
+                        writer.write(b"|")?;
+                        write_newline(writer, context)?;
                     } else {
-                        writer.write(if do_block { b" do |" } else { b" { |" })?;
+                        write_spaced(writer, context, b" { |")?;
                         write_code(&args, writer, &child_context)?;
-                        writer.write(if do_block { b"|\n" } else { b"| " })?;
+                        write_spaced(writer, context, b"| ")?;
                     }
                 }
                 None => {
-                    writer.write(if do_block { b" do\n" } else { b" {" })?;
+                    if do_block {
+                        writer.write(b" do")?;
+                        write_newline(writer, context)?;
+                    } else {
+                        write_spaced(writer, context, b" {")?;
+                    }
                 }
             }
-            if let Some(body) = &block.body {
+            let body_excluded =
+                method_body_excluded(context) && is_method_definition_block(&block.call);
+            if let (Some(body), false) = (&block.body, body_excluded) {
                 if do_block {
                     write_body!(body, writer, &child_context.indent());
                 } else {
@@ -167,10 +219,12 @@ pub fn write_code<W: Write>(
                 }
             }
             if do_block {
-                write_indent(writer, context.indent)?;
+                write_indent(writer, context)?;
                 writer.write(b"end")?;
+            } else if body_excluded && block.args.is_some() {
+                writer.write(b"}")?;
             } else {
-                writer.write(b" }")?;
+                write_spaced(writer, context, b" }")?;
             }
         }
         Node::BlockPass(pass) => {
@@ -194,7 +248,7 @@ pub fn write_code<W: Write>(
             match send.operator_l {
                 Some(_) => {
                     writer.write(send.method_name[0..(send.method_name.len() - 1)].as_bytes())?;
-                    writer.write(b" = ")?;
+                    write_spaced(writer, context, b" = ")?;
                     write_code_with_separator(&send.args, writer, &child_context, b", ")?;
                 }
                 None => {
@@ -214,41 +268,44 @@ pub fn write_code<W: Write>(
                 Some(expr) => {
                     writer.write(b"case ")?;
                     write_code(&expr, writer, &child_context)?;
-                    writer.write(b"\n")?;
+                    write_newline(writer, context)?;
                 }
                 None => {
-                    writer.write(b"case\n")?;
+                    writer.write(b"case")?;
+                    write_newline(writer, context)?;
                 }
             }
             for body in case.when_bodies.iter() {
-                write_indent(writer, context.indent)?;
+                write_indent(writer, context)?;
                 write_code(body, writer, &child_context)?;
             }
             match &case.else_body {
                 Some(else_body) => {
-                    write_indent(writer, context.indent)?;
-                    writer.write(b"else\n")?;
+                    write_indent(writer, context)?;
+                    writer.write(b"else")?;
+                    write_newline(writer, context)?;
                     write_body!(else_body, writer, &child_context.indent());
                 }
                 None => {}
             }
-            write_indent(writer, context.indent)?;
+            write_indent(writer, context)?;
             writer.write(b"end")?;
         }
         Node::CaseMatch(case) => {
             writer.write(b"case ")?;
             write_code(&case.expr, writer, &child_context)?;
-            writer.write(b"\n")?;
+            write_newline(writer, context)?;
             write_code_without_separator(&case.in_bodies, writer, &child_context.indent())?;
             match &case.else_body {
                 Some(else_body) => {
-                    writer.write(b"else\n")?;
+                    writer.write(b"else")?;
+                    write_newline(writer, context)?;
                     write_code(&else_body, writer, &child_context.indent())?;
-                    writer.write(b"\n")?;
+                    write_newline(writer, context)?;
                 }
                 None => {}
             }
-            write_indent(writer, context.indent)?;
+            write_indent(writer, context)?;
             writer.write(b"end")?;
         }
         Node::Casgn(asgn) => {
@@ -258,6 +315,14 @@ pub fn write_code<W: Write>(
                 writer.write(b"::")?;
             }
             write_assign!(asgn, writer, &child_context);
+            if let Some(comment) = context
+                .trailing_comments
+                .as_ref()
+                .and_then(|comments| comments.get(&asgn.expression_l.begin))
+            {
+                writer.write(b" # ")?;
+                writer.write(comment.as_bytes())?;
+            }
         }
         Node::Cbase(_) => {}
         Node::Class(class) => {
@@ -265,7 +330,7 @@ pub fn write_code<W: Write>(
             writer.write(b"class ")?;
             write_code(&class.name, writer, &child_context)?;
             if let Some(super_class) = &class.superclass {
-                writer.write(b" < ")?;
+                write_spaced(writer, context, b" < ")?;
                 write_code(&super_class, writer, &child_context)?;
             }
             write_body_with_end!(class, writer, child_context);
@@ -368,8 +433,9 @@ pub fn write_code<W: Write>(
                     }
                 }
             }
-            write_indent(writer, context.indent - 1)?; // <= Ensure is part of the body of something else
-            writer.write(b"ensure\n")?;
+            write_indent(writer, &context.outdent())?; // <= Ensure is part of the body of something else
+            writer.write(b"ensure")?;
+            write_newline(writer, context)?;
             if let Some(ensure) = &ensure.ensure {
                 write_body!(ensure, writer, child_context);
             }
@@ -424,22 +490,23 @@ pub fn write_code<W: Write>(
             if let Some(if_true) = &if_kw.if_true {
                 writer.write(b"if ")?;
                 write_code(&if_kw.cond, writer, &child_context)?;
-                writer.write(b"\n")?;
+                write_newline(writer, context)?;
                 write_body!(if_true, writer, indented_context);
                 if let Some(if_false) = &if_kw.if_false {
-                    write_indent(writer, context.indent)?;
-                    writer.write(b"else\n")?;
+                    write_indent(writer, context)?;
+                    writer.write(b"else")?;
+                    write_newline(writer, context)?;
                     write_body!(if_false, writer, indented_context);
                 }
             } else {
                 if let Some(if_false) = &if_kw.if_false {
                     writer.write(b"unless ")?;
                     write_code(&if_kw.cond, writer, &child_context)?;
-                    writer.write(b"\n")?;
+                    write_newline(writer, context)?;
                     write_body!(if_false, writer, indented_context);
                 }
             }
-            write_indent(writer, context.indent)?;
+            write_indent(writer, context)?;
             writer.write(b"end")?;
         }
         Node::IfGuard(guard) => {
@@ -471,7 +538,8 @@ pub fn write_code<W: Write>(
                 write_code(&guard, writer, &child_context)?;
             }
             if let Some(body) = &pat.body {
-                writer.write(b" then\n")?;
+                writer.write(b" then")?;
+                write_newline(writer, context)?;
                 write_body!(body, writer, child_context.indent());
             }
         }
@@ -488,7 +556,7 @@ pub fn write_code<W: Write>(
             writer.write(b"]")?;
             if let Some(value) = &asgn.value {
                 if context.parent_node_type != "mlhs" {
-                    writer.write(b" = ")?;
+                    write_spaced(writer, context, b" = ")?;
                 }
                 write_code(&value, writer, &child_context)?;
             }
@@ -507,7 +575,8 @@ pub fn write_code<W: Write>(
         }
         Node::KwBegin(kw_begin) => {
             if kw_begin.begin_l.is_some() {
-                writer.write(b"begin\n")?;
+                writer.write(b"begin")?;
+                write_newline(writer, context)?;
             }
             //write_code_without_separator(kw_begin.statements, writer, indent + 1)?;
             for body in kw_begin.statements.iter() {
@@ -521,7 +590,7 @@ pub fn write_code<W: Write>(
                 }
             }
             if kw_begin.end_l.is_some() {
-                write_indent(writer, context.indent)?;
+                write_indent(writer, context)?;
                 writer.write(b"end")?;
             }
         }
@@ -537,7 +606,11 @@ pub fn write_code<W: Write>(
         }
         Node::Kwoptarg(arg) => {
             writer.write(arg.name.as_bytes())?;
-            writer.write(b": ")?;
+            if starts_with_colon(&arg.default) {
+                writer.write(b": ")?;
+            } else {
+                write_spaced(writer, context, b": ")?;
+            }
             write_code(&arg.default, writer, &child_context)?;
         }
         Node::Kwrestarg(arg) => {
@@ -564,17 +637,17 @@ pub fn write_code<W: Write>(
         }
         Node::Masgn(asgn) => {
             write_code(&asgn.lhs, writer, &child_context)?;
-            writer.write(b" = ")?;
+            write_spaced(writer, context, b" = ")?;
             write_code(&asgn.rhs, writer, &child_context)?;
         }
         Node::MatchAlt(match_alt) => {
             write_code(&match_alt.lhs, writer, &child_context)?;
-            writer.write(b" | ")?;
+            write_spaced(writer, context, b" | ")?;
             write_code(&match_alt.rhs, writer, &child_context)?;
         }
         Node::MatchAs(match_as) => {
             write_code(&match_as.value, writer, &child_context)?;
-            writer.write(b" => ")?;
+            write_spaced(writer, context, b" => ")?;
             write_code(&match_as.as_, writer, &child_context)?;
         }
         Node::MatchCurrentLine(match_current_line) => {
@@ -636,38 +709,52 @@ pub fn write_code<W: Write>(
         }
         Node::Numblock(block) => {
             write_code(&block.call, writer, &child_context)?;
-            writer.write(b" { ")?;
-            writer.write_fmt(format_args!("_{}", block.numargs))?;
-            writer.write(b" }")?;
+            let body = &block.body;
+            if block.begin_l.size() == 2 {
+                writer.write(b" do")?;
+                write_newline(writer, context)?;
+                write_body!(body, writer, &child_context.indent());
+                write_indent(writer, context)?;
+                writer.write(b"end")?;
+            } else {
+                write_spaced(writer, context, b" { ")?;
+                write_code(body, writer, &child_context.indent())?;
+                write_spaced(writer, context, b" }")?;
+            }
         }
         Node::OpAsgn(asgn) => {
             write_code(&asgn.recv, writer, &child_context)?;
-            writer.write_fmt(format_args!(" {}", asgn.operator))?;
-            writer.write(b"= ")?;
+            write_spaced(writer, context, b" ")?;
+            writer.write(asgn.operator.as_bytes())?;
+            write_spaced(writer, context, b"= ")?;
             write_code(&asgn.value, writer, &child_context)?;
         }
         Node::Optarg(arg) => {
             writer.write(arg.name.as_bytes())?;
-            writer.write(b" = ")?;
+            write_spaced(writer, context, b" = ")?;
             write_code(&arg.default, writer, &child_context)?;
         }
         Node::Or(or) => {
             write_code(&or.lhs, writer, &child_context)?;
-            writer.write(b" || ")?;
+            write_spaced(writer, context, b" || ")?;
             write_code(&or.rhs, writer, &child_context)?;
         }
         Node::OrAsgn(asgn) => {
             write_code(&asgn.recv, writer, &child_context)?;
-            writer.write(b" ||= ")?;
+            write_spaced(writer, context, b" ||= ")?;
             write_code(&asgn.value, writer, &child_context)?;
         }
         Node::Pair(pair) => {
             if pair.operator_l.size() >= 2 {
                 write_code(&pair.key, writer, &child_context)?;
-                writer.write(b" => ")?;
+                write_spaced(writer, context, b" => ")?;
             } else {
                 write_code(&pair.key, writer, &context.make_child("pair_key"))?;
-                writer.write(b": ")?;
+                if starts_with_colon(&pair.value) {
+                    writer.write(b": ")?;
+                } else {
+                    write_spaced(writer, context, b": ")?;
+                }
             }
             write_code(&pair.value, writer, &child_context)?;
         }
@@ -731,12 +818,13 @@ pub fn write_code<W: Write>(
                 write_body!(body, writer, child_context);
             }
             for body in rescue.rescue_bodies.iter() {
-                write_indent(writer, context.indent - 1)?;
+                write_indent(writer, &context.outdent())?;
                 write_code(body, writer, &child_context.outdent())?;
             }
             if let Some(else_body) = &rescue.else_ {
-                write_indent(writer, context.indent - 1)?;
-                writer.write(b"else\n")?;
+                write_indent(writer, &context.outdent())?;
+                writer.write(b"else")?;
+                write_newline(writer, context)?;
                 write_body!(else_body, writer, child_context);
             }
         }
@@ -757,7 +845,7 @@ pub fn write_code<W: Write>(
                     write_code(body, writer, &child_context.indent())?;
                 }
             } else {
-                writer.write(b"\n")?;
+                write_newline(writer, context)?;
                 if let Some(body) = &rescue.body {
                     write_body!(body, writer, child_context.indent());
                 }
@@ -806,29 +894,28 @@ pub fn write_code<W: Write>(
                     write_code(&recv, writer, &child_context)?;
                     if send.dot_l.is_some() {
                         writer.write(b".")?;
-                    } else {
+                    } else if !context.minified {
                         writer.write(b" ")?;
                     }
                 }
                 if send.operator_l.is_some() || context.parent_node_type == "mlhs" {
                     writer.write(send.method_name[0..(send.method_name.len() - 1)].as_bytes())?;
                     if context.parent_node_type != "mlhs" {
-                        writer.write(b" = ")?;
+                        write_spaced(writer, context, b" = ")?;
                     }
                     if send.args.len() > 0 {
                         write_code_with_separator(&send.args, writer, &child_context, b", ")?;
                     }
                 } else {
-                    match send.method_name.as_str() {
-                        "attr_accessor" | "attr_reader" | "attr_writer" => {
-                            write_documentation!(send, writer, context);
-                        }
-                        _ => {}
+                    if is_definition_send(send) {
+                        write_documentation!(send, writer, context);
                     }
                     writer.write(send.method_name.as_bytes())?;
                     if send.begin_l.is_some() {
                         writer.write(b"(")?;
-                    } else if send.args.len() > 0 {
+                    } else if send.args.len() > 0
+                        && !(context.minified && is_operator(&send.method_name))
+                    {
                         writer.write(b" ")?;
                     }
                     write_code_with_separator(&send.args, writer, &child_context, b", ")?;
@@ -839,7 +926,6 @@ pub fn write_code<W: Write>(
             }
         }
         Node::Shadowarg(shadow) => {
-            writer.write(b";")?;
             writer.write(shadow.name.as_bytes())?;
         }
         Node::Splat(splat) => {
@@ -853,7 +939,7 @@ pub fn write_code<W: Write>(
             let string = get_string_to_write_from_string_bytes(str.value.as_raw(), &mut did_escape);
             if did_escape {
                 writer.write(b"\"")?;
-                writer.write(string.replace("\"", "\\\"").as_bytes())?;
+                writer.write(string.replace("\"", "\\\"").replace("#", "\\#").as_bytes())?;
                 writer.write(b"\"")?;
             } else {
                 writer.write(b"\'")?;
@@ -909,8 +995,8 @@ pub fn write_code<W: Write>(
         Node::When(when) => {
             writer.write(b"when ")?;
             write_code_with_separator(&when.patterns, writer, &child_context, b", ")?;
+            write_newline(writer, context)?;
             if let Some(body) = &when.body {
-                writer.write(b"\n")?;
                 write_body!(body, writer, child_context.indent());
             }
         }
@@ -962,7 +1048,7 @@ fn write_code_with_separator<W: Write>(
     for (i, node) in nodes.iter().enumerate() {
         write_code(node, writer, context)?;
         if i != last_index {
-            writer.write(separator)?;
+            write_spaced(writer, context, separator)?;
         }
     }
 
@@ -975,16 +1061,43 @@ fn write_code_without_separator<W: Write>(
     context: &CodeWriterContext,
 ) -> Result<(), std::io::Error> {
     for node in nodes.iter() {
-        write_indent(writer, context.indent)?;
+        write_indent(writer, context)?;
         write_code(node, writer, context)?;
-        writer.write(b"\n")?;
+        write_newline(writer, context)?;
     }
     return Ok(());
 }
 
-fn write_indent<W: Write>(writer: &mut BufWriter<W>, indent: u32) -> Result<(), std::io::Error> {
-    if indent > 0 {
-        writer.write(b" ".repeat((2 * indent).try_into().unwrap())[..].as_ref())?;
+fn write_indent<W: Write>(
+    writer: &mut BufWriter<W>,
+    context: &CodeWriterContext,
+) -> Result<(), std::io::Error> {
+    if context.indent > 0 && !context.minified {
+        writer.write(b" ".repeat((2 * context.indent).try_into().unwrap())[..].as_ref())?;
+    }
+    return Ok(());
+}
+
+/** End a statement, minified code separating statements with `;` instead of new lines */
+fn write_newline<W: Write>(
+    writer: &mut BufWriter<W>,
+    context: &CodeWriterContext,
+) -> Result<(), std::io::Error> {
+    writer.write(if context.minified { b";" } else { b"\n" })?;
+    return Ok(());
+}
+
+/** Write a token surrounded by optional spaces, dropped when minifying (a lone space is kept) */
+fn write_spaced<W: Write>(
+    writer: &mut BufWriter<W>,
+    context: &CodeWriterContext,
+    token: &[u8],
+) -> Result<(), std::io::Error> {
+    let minified_token: Vec<u8> = token.iter().copied().filter(|&byte| byte != b' ').collect();
+    if context.minified && !minified_token.is_empty() {
+        writer.write(&minified_token)?;
+    } else {
+        writer.write(token)?;
     }
     return Ok(());
 }
@@ -1085,9 +1198,80 @@ fn write_parts<W: Write>(
     return Ok(());
 }
 
+/** Tell if a receiverless call defines methods (and so should be documented) */
+fn is_definition_send(send: &Send) -> bool {
+    if send.recv.is_some() {
+        return false;
+    }
+    match send.method_name.as_str() {
+        "attr_accessor"
+        | "attr_reader"
+        | "attr_writer"
+        | "define_method"
+        | "define_singleton_method"
+        | "alias_method"
+        | "def_delegator"
+        | "def_delegators"
+        | "delegate" => true,
+        // `private def name`, `module_function def name`, etc.
+        "private"
+        | "protected"
+        | "public"
+        | "module_function"
+        | "private_class_method"
+        | "public_class_method" => send
+            .args
+            .iter()
+            .any(|arg| matches!(arg, Node::Def(_) | Node::Defs(_))),
+        _ => false,
+    }
+}
+
+/** Tell if the block given to a call is the body of a method (`define_method(:name) do ... end`) */
+fn is_method_definition_block(call: &Node) -> bool {
+    match call {
+        Node::Send(send) => {
+            send.recv.is_none()
+                && (send.method_name == "define_method"
+                    || send.method_name == "define_singleton_method")
+        }
+        _ => false,
+    }
+}
+
+/** Tell if a method name is an operator (`+`, `<=>`, `[]`...), written without spaces when minifying */
+fn is_operator(method_name: &str) -> bool {
+    return !method_name.starts_with(|c: char| c.is_alphabetic() || c == '_');
+}
+
+/** Tell if the code of a node starts with `:`, which must not follow another `:` */
+fn starts_with_colon(node: &Node) -> bool {
+    match node {
+        Node::Sym(sym) => sym.begin_l.is_some(),
+        Node::Dsym(sym) => sym.begin_l.is_some(),
+        Node::Const(constant) => match &constant.scope {
+            Some(scope) => matches!(scope.as_ref(), Node::Cbase(_)) || starts_with_colon(scope),
+            None => false,
+        },
+        _ => false,
+    }
+}
+
 fn method_body_excluded(context: &CodeWriterContext) -> bool {
     if let Some(documentation_context) = &context.documentation_context {
         return documentation_context.method_body_excluded();
     }
     return false;
 }
+
+/** Write a node to a string, mostly useful to display names and values */
+pub fn code_to_string(node: &Node) -> String {
+    let mut writer = BufWriter::new(Vec::new());
+    if write_code(node, &mut writer, &CodeWriterContext::new()).is_err() {
+        return String::new();
+    }
+    match writer.into_inner() {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        Err(_) => String::new(),
+    }
+}
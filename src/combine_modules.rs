@@ -1,96 +1,461 @@
 use std::collections::HashMap;
 
-use lib_ruby_parser::{
-    nodes::{Begin, Send},
-    Loc, Node,
+use lib_ruby_parser::{nodes::Begin, source::DecodedInput, Loc, Node};
+
+use crate::{
+    builder as b,
+    visitor::{walk, NodePath, Visitor},
 };
 
-type KnownModules<'a> = HashMap<String, &'a mut Node>;
+type KnownModules = HashMap<String, usize>;
+
+/** Class body calls that only declare things and can safely run earlier than written */
+const DECLARATIVE_METHODS: [&str; 16] = [
+    "attr",
+    "attr_reader",
+    "attr_writer",
+    "attr_accessor",
+    "private",
+    "protected",
+    "public",
+    "module_function",
+    "private_constant",
+    "public_constant",
+    "private_class_method",
+    "public_class_method",
+    "include",
+    "extend",
+    "prepend",
+    "alias_method",
+];
+
+/** Operators assumed to be free of side effects when both operands are */
+const PURE_OPERATORS: [&str; 18] = [
+    "+", "-", "*", "/", "%", "**", "<<", ">>", "&", "|", "^", "==", "!=", "<", ">", "<=", ">=",
+    "freeze",
+];
+
+/** Reason preventing the body of a reopening from being moved up into the first definition */
+#[derive(Debug, Clone)]
+pub enum UnsafeMergeReason {
+    /** A statement between both definitions has side effects that must run first */
+    SideEffectBetween(Loc),
+    /** The reopening uses a constant defined between both definitions */
+    ConstantDefinedBetween(String, Loc),
+    /** The reopening calls a method defined between both definitions */
+    MethodDefinedBetween(String, Loc),
+    /** The reopening has side effects and statements between both definitions define things */
+    SideEffectInReopening(Loc, Loc),
+}
+
+/** Reopening of a class or module that was not merged into its first definition */
+#[derive(Debug, Clone)]
+pub struct KeptSeparate {
+    pub module_name: String,
+    pub first_l: Loc,
+    pub reopening_l: Loc,
+    pub reason: UnsafeMergeReason,
+}
+
+/** Reopening of a class or module whose body was moved into its first definition */
+#[derive(Debug, Clone)]
+pub struct MergedReopening {
+    pub module_name: String,
+    pub first_l: Loc,
+    pub reopening_l: Loc,
+}
+
+/** Outcome of `combine_modules` */
+#[derive(Debug, Clone, Default)]
+pub struct CombinedModules {
+    pub merged: Vec<MergedReopening>,
+    pub kept_separate: Vec<KeptSeparate>,
+}
+
+impl KeptSeparate {
+    pub fn describe(&self, input: &DecodedInput) -> String {
+        let reason = match &self.reason {
+            UnsafeMergeReason::SideEffectBetween(loc) => {
+                format!("statement at line {} has side effects", line_of(loc, input))
+            }
+            UnsafeMergeReason::ConstantDefinedBetween(name, loc) => format!(
+                "it uses constant {} defined at line {}",
+                name,
+                line_of(loc, input)
+            ),
+            UnsafeMergeReason::MethodDefinedBetween(name, loc) => format!(
+                "it calls method {} defined at line {}",
+                name,
+                line_of(loc, input)
+            ),
+            UnsafeMergeReason::SideEffectInReopening(side_effect_l, definition_l) => format!(
+                "its statement at line {} has side effects and would run before the definition at line {}",
+                line_of(side_effect_l, input),
+                line_of(definition_l, input)
+            ),
+        };
+        return format!(
+            "kept {} at line {} separate from line {}: {}",
+            self.module_name,
+            line_of(&self.reopening_l, input),
+            line_of(&self.first_l, input),
+            reason
+        );
+    }
+}
+
+fn line_of(loc: &Loc, input: &DecodedInput) -> usize {
+    match input.line_col_for_pos(loc.begin) {
+        Some((line, _)) => line + 1,
+        None => 0,
+    }
+}
 
-pub fn combine_modules<'a>(node: &'a mut Node) -> () {
+/**
+ * Merge reopened classes and modules into their first definition.
+ *
+ * A reopening is only merged when moving its body up cannot change behavior, unless `force` is set.
+ * Reopenings that were kept separate are returned with the reason.
+ */
+pub fn combine_modules(node: &mut Node, force: bool) -> CombinedModules {
+    let mut combined = CombinedModules::default();
+    combine_modules_internal(node, force, &mut combined);
+    return combined;
+}
+
+fn combine_modules_internal(node: &mut Node, force: bool, combined: &mut CombinedModules) {
     match node {
         Node::Begin(begin) => {
-            let mut statements_count = begin.statements.len();
-            loop {
-                let mut known_modules: KnownModules = HashMap::new();
-                let mut valid_indexes: Vec<bool> = Vec::with_capacity(begin.statements.len());
-                for node in begin.statements.iter_mut() {
-                    match node {
-                        Node::Class(_) | Node::Module(_) => {
-                            let len_before = known_modules.len();
-                            combine_modules_internal(node, &mut known_modules);
-                            valid_indexes.push(len_before < known_modules.len())
-                        }
-                        _ => valid_indexes.push(true),
+            combine_statements(begin, force, combined);
+            for statement in begin.statements.iter_mut() {
+                match statement {
+                    Node::Class(_) | Node::Module(_) => {
+                        combine_modules_internal(statement, force, combined)
                     }
+                    _ => {}
                 }
-                begin.statements = begin
-                    .statements
-                    .iter()
-                    .enumerate()
-                    .filter(|(i, _)| valid_indexes[*i])
-                    .map(|(_, node)| node.to_owned())
-                    .collect();
-                if statements_count == begin.statements.len() {
-                    break;
-                }
-                statements_count = begin.statements.len();
             }
         }
         Node::Class(klass) => {
             normalize_children(&mut klass.body);
             if let Some(body) = klass.body.as_mut() {
-                combine_modules(body);
+                combine_modules_internal(body, force, combined);
             }
         }
         Node::Module(klass) => {
             normalize_children(&mut klass.body);
             if let Some(body) = klass.body.as_mut() {
-                combine_modules(body);
+                combine_modules_internal(body, force, combined);
             }
         }
         _ => {}
     }
 }
 
-fn combine_modules_internal<'a>(node: &'a mut Node, known_modules: &mut KnownModules<'a>) -> () {
-    match node {
-        Node::Class(klass) => {
-            let module_name = const_name_from_node(&klass.name);
-            normalize_children(&mut klass.body);
-            if known_modules.contains_key(&module_name) {
-                let existing_class = known_modules.get_mut(&module_name).expect("");
+fn combine_statements(begin: &mut Begin, force: bool, combined: &mut CombinedModules) {
+    let mut known_modules: KnownModules = HashMap::new();
+    let mut valid_indexes: Vec<bool> = vec![true; begin.statements.len()];
+    for index in 0..begin.statements.len() {
+        let module_name = match &begin.statements[index] {
+            Node::Class(klass) => const_name_from_node(&klass.name),
+            Node::Module(module) => const_name_from_node(&module.name),
+            _ => continue,
+        };
+        normalize_module_body(&mut begin.statements[index]);
+        let first_index = match known_modules.get(&module_name) {
+            Some(&first_index) => first_index,
+            None => {
+                known_modules.insert(module_name, index);
+                continue;
+            }
+        };
+        let reason = if force {
+            None
+        } else {
+            let intervening: Vec<&Node> = ((first_index + 1)..index)
+                .filter(|&i| valid_indexes[i])
+                .map(|i| &begin.statements[i])
+                .collect();
+            find_unsafe_merge_reason(&intervening, &begin.statements[index])
+        };
+        match reason {
+            Some(reason) => {
+                combined.kept_separate.push(KeptSeparate {
+                    module_name: module_name.clone(),
+                    first_l: begin.statements[first_index].expression().clone(),
+                    reopening_l: begin.statements[index].expression().clone(),
+                    reason,
+                });
+                known_modules.insert(module_name, index);
+            }
+            None => {
+                combined.merged.push(MergedReopening {
+                    module_name,
+                    first_l: begin.statements[first_index].expression().clone(),
+                    reopening_l: begin.statements[index].expression().clone(),
+                });
+                let (before, after) = begin.statements.split_at_mut(index);
                 combine_bodies(
-                    get_body_from_existing_module(existing_class),
-                    get_body_from_optional_children(&mut klass.body),
+                    get_body_from_existing_module(&mut before[first_index]),
+                    get_body_from_existing_module(&mut after[0]),
                 );
-            } else {
-                if let Some(body) = klass.body.as_mut() {
-                    combine_modules(body);
+                valid_indexes[index] = false;
+            }
+        }
+    }
+    begin.statements = begin
+        .statements
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| valid_indexes[*i])
+        .map(|(_, node)| node.to_owned())
+        .collect();
+}
+
+fn normalize_module_body(node: &mut Node) {
+    match node {
+        Node::Class(klass) => normalize_children(&mut klass.body),
+        Node::Module(module) => normalize_children(&mut module.body),
+        _ => {}
+    }
+}
+
+fn find_unsafe_merge_reason(
+    intervening: &Vec<&Node>,
+    reopening: &Node,
+) -> Option<UnsafeMergeReason> {
+    let mut defined_constants: Vec<(String, Loc)> = vec![];
+    let mut defined_methods: Vec<(String, Loc)> = vec![];
+    for statement in intervening.iter() {
+        if let Some(side_effect_l) = find_side_effect(statement) {
+            return Some(UnsafeMergeReason::SideEffectBetween(side_effect_l));
+        }
+        collect_definitions(statement, &mut defined_constants, &mut defined_methods);
+    }
+    if defined_constants.is_empty() && defined_methods.is_empty() {
+        return None;
+    }
+
+    let mut used_constants: Vec<String> = vec![];
+    let mut called_methods: Vec<String> = vec![];
+    match reopening {
+        Node::Class(klass) => {
+            if let Some(superclass) = &klass.superclass {
+                collect_references(superclass, &mut used_constants, &mut called_methods);
+            }
+            if let Some(body) = &klass.body {
+                collect_references(body, &mut used_constants, &mut called_methods);
+            }
+        }
+        Node::Module(module) => {
+            if let Some(body) = &module.body {
+                collect_references(body, &mut used_constants, &mut called_methods);
+            }
+        }
+        _ => {}
+    }
+    for (name, loc) in defined_constants.iter() {
+        if used_constants.contains(name) {
+            return Some(UnsafeMergeReason::ConstantDefinedBetween(
+                name.clone(),
+                loc.clone(),
+            ));
+        }
+    }
+    for (name, loc) in defined_methods.iter() {
+        if called_methods.contains(name) {
+            return Some(UnsafeMergeReason::MethodDefinedBetween(
+                name.clone(),
+                loc.clone(),
+            ));
+        }
+    }
+    // A side effect of the reopening could see the definitions made between the two bodies
+    let first_definition_l = defined_constants
+        .iter()
+        .chain(defined_methods.iter())
+        .map(|(_, loc)| loc.clone())
+        .next();
+    if let (Some(side_effect_l), Some(definition_l)) =
+        (find_side_effect(reopening), first_definition_l)
+    {
+        return Some(UnsafeMergeReason::SideEffectInReopening(
+            side_effect_l,
+            definition_l,
+        ));
+    }
+    return None;
+}
+
+/** Find the first expression of a statement that has side effects when the file is loaded */
+fn find_side_effect(node: &Node) -> Option<Loc> {
+    match node {
+        Node::Def(_) | Node::Defs(_) | Node::Alias(_) | Node::Undef(_) => None,
+        Node::Class(klass) => {
+            if let Some(superclass) = &klass.superclass {
+                if let Some(loc) = find_impure_expression(superclass) {
+                    return Some(loc);
                 }
-                known_modules.insert(module_name, node);
             }
+            return klass.body.as_deref().and_then(find_side_effect);
         }
-        Node::Module(klass) => {
-            let module_name = const_name_from_node(&klass.name);
-            normalize_children(&mut klass.body);
-            if known_modules.contains_key(&module_name) {
-                let existing_class = known_modules.get_mut(&module_name).expect("");
-                combine_bodies(
-                    get_body_from_existing_module(existing_class),
-                    get_body_from_optional_children(&mut klass.body),
-                );
-            } else {
-                if let Some(body) = klass.body.as_mut() {
-                    combine_modules(body);
+        Node::Module(module) => module.body.as_deref().and_then(find_side_effect),
+        Node::SClass(sclass) => sclass.body.as_deref().and_then(find_side_effect),
+        Node::Begin(begin) => begin.statements.iter().find_map(find_side_effect),
+        Node::Casgn(asgn) => asgn.value.as_deref().and_then(find_impure_expression),
+        Node::Lvasgn(asgn) => asgn.value.as_deref().and_then(find_impure_expression),
+        Node::Send(send) => {
+            if send.recv.is_none() && DECLARATIVE_METHODS.contains(&send.method_name.as_str()) {
+                return send.args.iter().find_map(|arg| match arg {
+                    Node::Def(_) | Node::Defs(_) => None,
+                    _ => find_impure_expression(arg),
+                });
+            }
+            return Some(send.expression_l.clone());
+        }
+        _ => find_impure_expression(node),
+    }
+}
+
+/** Find the first part of an expression that may have side effects */
+fn find_impure_expression(node: &Node) -> Option<Loc> {
+    match node {
+        Node::Int(_)
+        | Node::Float(_)
+        | Node::Rational(_)
+        | Node::Complex(_)
+        | Node::Str(_)
+        | Node::Sym(_)
+        | Node::Nil(_)
+        | Node::True(_)
+        | Node::False(_)
+        | Node::Self_(_)
+        | Node::Lvar(_)
+        | Node::Cbase(_)
+        | Node::Const(_)
+        | Node::File(_)
+        | Node::Line(_)
+        | Node::Encoding(_) => None,
+        Node::Dstr(str) => str.parts.iter().find_map(find_impure_expression),
+        Node::Dsym(sym) => sym.parts.iter().find_map(find_impure_expression),
+        Node::Array(array) => array.elements.iter().find_map(find_impure_expression),
+        Node::Hash(hash) => hash.pairs.iter().find_map(find_impure_expression),
+        Node::Pair(pair) => {
+            find_impure_expression(&pair.key).or_else(|| find_impure_expression(&pair.value))
+        }
+        Node::Splat(splat) => splat.value.as_deref().and_then(find_impure_expression),
+        Node::Irange(range) => range
+            .left
+            .as_deref()
+            .and_then(find_impure_expression)
+            .or_else(|| range.right.as_deref().and_then(find_impure_expression)),
+        Node::Erange(range) => range
+            .left
+            .as_deref()
+            .and_then(find_impure_expression)
+            .or_else(|| range.right.as_deref().and_then(find_impure_expression)),
+        Node::Begin(begin) => begin.statements.iter().find_map(find_impure_expression),
+        Node::And(and) => {
+            find_impure_expression(&and.lhs).or_else(|| find_impure_expression(&and.rhs))
+        }
+        Node::Or(or) => find_impure_expression(&or.lhs).or_else(|| find_impure_expression(&or.rhs)),
+        Node::Send(send) => {
+            if PURE_OPERATORS.contains(&send.method_name.as_str()) {
+                if let Some(recv) = &send.recv {
+                    return find_impure_expression(recv)
+                        .or_else(|| send.args.iter().find_map(find_impure_expression));
+                }
+            }
+            return Some(send.expression_l.clone());
+        }
+        _ => Some(node.expression().clone()),
+    }
+}
+
+/** Collect constants and methods defined by a statement */
+fn collect_definitions(
+    node: &Node,
+    constants: &mut Vec<(String, Loc)>,
+    methods: &mut Vec<(String, Loc)>,
+) {
+    match node {
+        Node::Class(klass) => {
+            constants.push((last_const_name(&klass.name), klass.expression_l.clone()));
+            if let Some(body) = &klass.body {
+                collect_nested_constants(body, constants);
+            }
+        }
+        Node::Module(module) => {
+            constants.push((last_const_name(&module.name), module.expression_l.clone()));
+            if let Some(body) = &module.body {
+                collect_nested_constants(body, constants);
+            }
+        }
+        Node::Casgn(asgn) => {
+            constants.push((asgn.name.clone(), asgn.expression_l.clone()));
+        }
+        Node::Def(def) => {
+            methods.push((def.name.clone(), def.expression_l.clone()));
+        }
+        Node::Begin(begin) => {
+            for statement in begin.statements.iter() {
+                collect_definitions(statement, constants, methods);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn collect_nested_constants(node: &Node, constants: &mut Vec<(String, Loc)>) {
+    let mut ignored_methods: Vec<(String, Loc)> = vec![];
+    match node {
+        Node::Begin(begin) => {
+            for statement in begin.statements.iter() {
+                match statement {
+                    Node::Class(_) | Node::Module(_) | Node::Casgn(_) => {
+                        collect_definitions(statement, constants, &mut ignored_methods)
+                    }
+                    _ => {}
                 }
-                known_modules.insert(module_name, node);
             }
         }
+        Node::Class(_) | Node::Module(_) | Node::Casgn(_) => {
+            collect_definitions(node, constants, &mut ignored_methods)
+        }
         _ => {}
     }
 }
 
+/** Collect constants and receiver-less calls used when a body is loaded (method bodies excluded) */
+fn collect_references(node: &Node, constants: &mut Vec<String>, methods: &mut Vec<String>) {
+    let mut collector = ReferenceCollector { constants, methods };
+    walk(&mut collector, node);
+}
+
+struct ReferenceCollector<'a> {
+    constants: &'a mut Vec<String>,
+    methods: &'a mut Vec<String>,
+}
+
+impl<'a, 'n> Visitor<'n> for ReferenceCollector<'a> {
+    fn enter(&mut self, node: &'n Node, _path: &NodePath<'n>) -> bool {
+        match node {
+            Node::Def(_) | Node::Defs(_) => return false,
+            Node::Const(constant) => self.constants.push(constant.name.clone()),
+            Node::Send(send) if send.recv.is_none() => self.methods.push(send.method_name.clone()),
+            _ => {}
+        }
+        return true;
+    }
+}
+
+fn last_const_name(node: &Node) -> String {
+    match node {
+        Node::Const(constant) => constant.name.clone(),
+        _ => const_name_from_node(node),
+    }
+}
+
 fn get_body_from_existing_module<'a>(node: &'a mut Node) -> &'a mut Begin {
     match node {
         Node::Class(klass) => return get_body_from_optional_children(&mut klass.body),
@@ -127,17 +492,7 @@ fn statements_contains_modifier(statements: &Vec<Node>) -> bool {
 }
 fn combine_bodies(existing: &mut Begin, new: &Begin) {
     if statements_contains_modifier(&existing.statements) {
-        existing.statements.push(Node::Send(Send {
-            recv: None,
-            method_name: String::from("public"),
-            args: vec![],
-            dot_l: None,
-            selector_l: None,
-            begin_l: None,
-            end_l: None,
-            operator_l: None,
-            expression_l: Loc { begin: 0, end: 1 },
-        }));
+        existing.statements.push(b::send(None, "public", vec![]));
     }
     existing
         .statements
@@ -149,22 +504,10 @@ fn normalize_children<'a>(children: &'a mut Option<Box<Node>>) -> () {
     if let Some(node) = children {
         match node.as_ref() {
             Node::Begin(_) => {}
-            something_else => {
-                *children = Some(Box::new(Node::Begin(Begin {
-                    statements: vec![something_else.to_owned()],
-                    begin_l: None,
-                    end_l: None,
-                    expression_l: Loc { begin: 0, end: 1 },
-                })))
-            }
+            something_else => *children = Some(Box::new(b::begin(vec![something_else.to_owned()]))),
         }
     } else {
-        *children = Some(Box::new(Node::Begin(Begin {
-            statements: vec![],
-            begin_l: None,
-            end_l: None,
-            expression_l: Loc { begin: 0, end: 1 },
-        })))
+        *children = Some(Box::new(b::begin(vec![])))
     }
 }
 
@@ -0,0 +1,245 @@
+use lib_ruby_parser::{source::DecodedInput, Loc, Node};
+
+use crate::{
+    downlevel::RubyVersion,
+    visitor::{walk, NodePath, Visitor},
+};
+
+/** Language feature some runtimes do not implement */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    PatternMatching,
+    OneLinePatternMatching,
+    Refinements,
+    ObjectSpace,
+    BeginEnd,
+    FlipFlop,
+    EncodingKeyword,
+    RequiredKeywordArguments,
+    NoKeywordArguments,
+    KeywordShorthand,
+    RationalLiteral,
+    ComplexLiteral,
+    EndlessMethod,
+    NumberedParameters,
+    ArgumentForwarding,
+}
+
+impl Feature {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Feature::PatternMatching => "pattern matching (case/in)",
+            Feature::OneLinePatternMatching => "one-line pattern matching (=> and in)",
+            Feature::Refinements => "refinements",
+            Feature::ObjectSpace => "ObjectSpace",
+            Feature::BeginEnd => "BEGIN/END blocks",
+            Feature::FlipFlop => "flip-flop",
+            Feature::EncodingKeyword => "__ENCODING__",
+            Feature::RequiredKeywordArguments => "required keyword argument",
+            Feature::NoKeywordArguments => "**nil argument",
+            Feature::KeywordShorthand => "keyword shorthand (x:)",
+            Feature::RationalLiteral => "Rational literal",
+            Feature::ComplexLiteral => "Complex literal",
+            Feature::EndlessMethod => "endless method",
+            Feature::NumberedParameters => "numbered block parameters",
+            Feature::ArgumentForwarding => "argument forwarding (...)",
+        }
+    }
+}
+
+const MRUBY_RULES: [(Feature, &str); 10] = [
+    (
+        Feature::PatternMatching,
+        "mruby does not implement pattern matching",
+    ),
+    (
+        Feature::OneLinePatternMatching,
+        "mruby does not implement pattern matching",
+    ),
+    (Feature::Refinements, "mruby does not implement refinements"),
+    (
+        Feature::ObjectSpace,
+        "ObjectSpace needs the mruby-objectspace gem",
+    ),
+    (Feature::BeginEnd, "mruby does not run BEGIN/END blocks"),
+    (Feature::FlipFlop, "mruby does not implement flip-flops"),
+    (Feature::EncodingKeyword, "mruby has no Encoding"),
+    (Feature::NoKeywordArguments, "mruby does not accept **nil"),
+    (
+        Feature::RationalLiteral,
+        "Rational needs the mruby-rational gem",
+    ),
+    (
+        Feature::ComplexLiteral,
+        "Complex needs the mruby-complex gem",
+    ),
+];
+
+const OPAL_RULES: [(Feature, &str); 3] = [
+    (
+        Feature::Refinements,
+        "Opal only partially implements refinements",
+    ),
+    (Feature::ObjectSpace, "Opal does not implement ObjectSpace"),
+    (Feature::BeginEnd, "Opal does not run BEGIN/END blocks"),
+];
+
+/** Version of Ruby that introduced each feature */
+const RUBY_RULES: [(Feature, RubyVersion); 11] = [
+    (Feature::PatternMatching, RubyVersion::new(2, 7)),
+    (Feature::OneLinePatternMatching, RubyVersion::new(3, 0)),
+    (Feature::Refinements, RubyVersion::new(2, 0)),
+    (Feature::RequiredKeywordArguments, RubyVersion::new(2, 1)),
+    (Feature::NoKeywordArguments, RubyVersion::new(2, 7)),
+    (Feature::KeywordShorthand, RubyVersion::new(3, 1)),
+    (Feature::RationalLiteral, RubyVersion::new(2, 1)),
+    (Feature::ComplexLiteral, RubyVersion::new(2, 1)),
+    (Feature::EndlessMethod, RubyVersion::new(3, 0)),
+    (Feature::NumberedParameters, RubyVersion::new(2, 7)),
+    (Feature::ArgumentForwarding, RubyVersion::new(2, 7)),
+];
+
+/** Runtime the code is checked against */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompatTarget {
+    Mruby,
+    Opal,
+    Ruby(RubyVersion),
+}
+
+impl CompatTarget {
+    /** Parse `mruby`, `opal`, `ruby-2.6` or `2.6` */
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "mruby" => return Some(CompatTarget::Mruby),
+            "opal" => return Some(CompatTarget::Opal),
+            _ => {
+                let version = name.strip_prefix("ruby").unwrap_or(name);
+                return RubyVersion::parse(version.trim_start_matches('-')).map(CompatTarget::Ruby);
+            }
+        }
+    }
+
+    /** Reason why the target cannot run a feature, None if it can */
+    pub fn incompatibility(&self, feature: Feature) -> Option<String> {
+        match self {
+            CompatTarget::Mruby => return find_rule(&MRUBY_RULES, feature),
+            CompatTarget::Opal => return find_rule(&OPAL_RULES, feature),
+            CompatTarget::Ruby(target) => {
+                let (_, since) = RUBY_RULES.iter().find(|(rule, _)| *rule == feature)?;
+                if target >= since {
+                    return None;
+                }
+                return Some(format!("needs Ruby {}", since));
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for CompatTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompatTarget::Mruby => write!(f, "mruby"),
+            CompatTarget::Opal => write!(f, "opal"),
+            CompatTarget::Ruby(version) => write!(f, "ruby-{}", version),
+        }
+    }
+}
+
+fn find_rule(rules: &[(Feature, &str)], feature: Feature) -> Option<String> {
+    return rules
+        .iter()
+        .find(|(rule, _)| *rule == feature)
+        .map(|(_, reason)| String::from(*reason));
+}
+
+/** Node using a feature the target does not have */
+pub struct Incompatibility {
+    pub feature: Feature,
+    pub reason: String,
+    pub expression_l: Loc,
+}
+
+impl Incompatibility {
+    pub fn describe(&self, file_name: &str, input: &DecodedInput) -> String {
+        let (line, column) = input
+            .line_col_for_pos(self.expression_l.begin)
+            .unwrap_or((0, 0));
+        return format!(
+            "{}:{}:{}: {} ({})",
+            file_name,
+            line + 1,
+            column + 1,
+            self.feature.description(),
+            self.reason
+        );
+    }
+}
+
+/** List the nodes of a program the target cannot run */
+pub fn check_compat(node: &Node, target: CompatTarget) -> Vec<Incompatibility> {
+    let mut checker = CompatChecker {
+        target,
+        incompatibilities: vec![],
+    };
+    walk(&mut checker, node);
+    return checker.incompatibilities;
+}
+
+struct CompatChecker {
+    target: CompatTarget,
+    incompatibilities: Vec<Incompatibility>,
+}
+
+impl<'a> Visitor<'a> for CompatChecker {
+    fn enter(&mut self, node: &'a Node, _path: &NodePath<'a>) -> bool {
+        if let Some(feature) = feature_of(node) {
+            if let Some(reason) = self.target.incompatibility(feature) {
+                self.incompatibilities.push(Incompatibility {
+                    feature,
+                    reason,
+                    expression_l: *node.expression(),
+                });
+            }
+        }
+        return true;
+    }
+}
+
+fn feature_of(node: &Node) -> Option<Feature> {
+    match node {
+        Node::CaseMatch(_) => return Some(Feature::PatternMatching),
+        Node::MatchPattern(_) | Node::MatchPatternP(_) => {
+            return Some(Feature::OneLinePatternMatching)
+        }
+        Node::Send(send)
+            if send.recv.is_none()
+                && send.args.len() == 1
+                && (send.method_name == "refine" || send.method_name == "using") =>
+        {
+            return Some(Feature::Refinements)
+        }
+        Node::Const(constant)
+            if constant.name == "ObjectSpace"
+                && matches!(constant.scope.as_deref(), None | Some(Node::Cbase(_))) =>
+        {
+            return Some(Feature::ObjectSpace)
+        }
+        Node::Preexe(_) | Node::Postexe(_) => return Some(Feature::BeginEnd),
+        Node::IFlipFlop(_) | Node::EFlipFlop(_) => return Some(Feature::FlipFlop),
+        Node::Encoding(_) => return Some(Feature::EncodingKeyword),
+        Node::Kwarg(_) => return Some(Feature::RequiredKeywordArguments),
+        Node::Kwnilarg(_) => return Some(Feature::NoKeywordArguments),
+        // The value of `x:` is read from the location of the key
+        Node::Pair(pair) if pair.value.expression().begin == pair.key.expression().begin => {
+            return Some(Feature::KeywordShorthand)
+        }
+        Node::Rational(_) => return Some(Feature::RationalLiteral),
+        Node::Complex(_) => return Some(Feature::ComplexLiteral),
+        Node::Def(def) if def.assignment_l.is_some() => return Some(Feature::EndlessMethod),
+        Node::Defs(defs) if defs.assignment_l.is_some() => return Some(Feature::EndlessMethod),
+        Node::Numblock(_) => return Some(Feature::NumberedParameters),
+        Node::ForwardArg(_) => return Some(Feature::ArgumentForwarding),
+        _ => return None,
+    }
+}
@@ -0,0 +1,502 @@
+use std::io::{BufWriter, Write};
+
+use lib_ruby_parser::Node;
+
+use crate::{
+    code_writer::code_to_string,
+    json::JsonValue,
+    tests::explore_constants::{ConstantHashMap, ConstantHashValue},
+};
+
+/** Output format of the explored constants */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstantFormat {
+    Json,
+    Rust,
+    C,
+    Ruby,
+}
+
+impl ConstantFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(ConstantFormat::Json),
+            "rust" => Some(ConstantFormat::Rust),
+            "c" => Some(ConstantFormat::C),
+            "ruby" => Some(ConstantFormat::Ruby),
+            _ => None,
+        }
+    }
+}
+
+/** Constant or module of the exported tree */
+enum ExportedEntry<'a> {
+    Constant(String, &'a Node),
+    Module(ExportedModule<'a>),
+}
+
+/** Module of the exported tree, `module A::B` being split into `A` containing `B` */
+struct ExportedModule<'a> {
+    name: String,
+    /** Written with `class`, reopening a class with `module` raises a TypeError */
+    is_class: bool,
+    entries: Vec<ExportedEntry<'a>>,
+}
+
+impl<'a> ExportedModule<'a> {
+    fn new(name: &str) -> Self {
+        return ExportedModule {
+            name: String::from(name),
+            is_class: false,
+            entries: vec![],
+        };
+    }
+
+    fn module_mut(&mut self, path: &[&str]) -> &mut ExportedModule<'a> {
+        let name = match path.first() {
+            Some(name) => *name,
+            None => return self,
+        };
+        let index =
+            match self.entries.iter().position(
+                |entry| matches!(entry, ExportedEntry::Module(module) if module.name == name),
+            ) {
+                Some(index) => index,
+                None => {
+                    self.entries
+                        .push(ExportedEntry::Module(ExportedModule::new(name)));
+                    self.entries.len() - 1
+                }
+            };
+        match &mut self.entries[index] {
+            ExportedEntry::Module(module) => return module.module_mut(&path[1..]),
+            ExportedEntry::Constant(..) => unreachable!(),
+        }
+    }
+
+    fn add_map(&mut self, map: &'a ConstantHashMap) {
+        for (key, value) in map.iter() {
+            // `::A` refers to the top level, it is exported where it was written to stay predictable
+            let path: Vec<&str> = key.split("::").filter(|name| !name.is_empty()).collect();
+            let (name, parents) = match path.split_last() {
+                Some(split) => split,
+                None => continue,
+            };
+            let parent = self.module_mut(parents);
+            match value {
+                ConstantHashValue::EndValue(node) => {
+                    let existing = parent.entries.iter_mut().find(
+                        |entry| matches!(entry, ExportedEntry::Constant(other, _) if other == name),
+                    );
+                    match existing {
+                        Some(constant) => {
+                            *constant = ExportedEntry::Constant(String::from(*name), node)
+                        }
+                        None => parent
+                            .entries
+                            .push(ExportedEntry::Constant(String::from(*name), node)),
+                    }
+                }
+                ConstantHashValue::ModuleValue(module) => {
+                    let child = parent.module_mut(&[name]);
+                    child.is_class |= module.is_class;
+                    child.add_map(module);
+                }
+            }
+        }
+    }
+}
+
+/** Write the explored constants in the given format, in definition order */
+pub fn write_constants<W: Write>(
+    constants: &ConstantHashMap,
+    format: ConstantFormat,
+    writer: &mut BufWriter<W>,
+) -> Result<(), std::io::Error> {
+    let mut top_level = ExportedModule::new("");
+    top_level.add_map(constants);
+    match format {
+        ConstantFormat::Json => {
+            module_to_json(&top_level).write(writer, 0)?;
+            writer.write_all(b"\n")?;
+        }
+        ConstantFormat::Rust => write_rust_module(&top_level, writer, 0)?,
+        ConstantFormat::C => {
+            writer.write_all(b"#pragma once\n\n#include <stdbool.h>\n#include <stdint.h>\n\n")?;
+            write_c_module(&top_level, "", writer)?;
+        }
+        ConstantFormat::Ruby => write_ruby_module(&top_level, writer, 0)?,
+    }
+    return Ok(());
+}
+
+fn module_to_json(module: &ExportedModule) -> JsonValue {
+    return JsonValue::Object(
+        module
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                ExportedEntry::Constant(name, value) => (name.clone(), value_to_json(value)),
+                ExportedEntry::Module(child) => (child.name.clone(), module_to_json(child)),
+            })
+            .collect(),
+    );
+}
+
+fn value_to_json(value: &Node) -> JsonValue {
+    match value {
+        Node::Int(int) => JsonValue::number(&int.value),
+        Node::Float(_) => match float_literal(value) {
+            Some(float) => JsonValue::Number(float),
+            None => JsonValue::Null,
+        },
+        Node::Str(str) => JsonValue::string(str.value.to_string_lossy()),
+        Node::Sym(sym) => JsonValue::string(sym.name.to_string_lossy()),
+        Node::True(_) => JsonValue::Bool(true),
+        Node::False(_) => JsonValue::Bool(false),
+        Node::Array(array) => JsonValue::Array(array.elements.iter().map(value_to_json).collect()),
+        Node::Hash(_) => {
+            let pairs = hash_pairs(value);
+            if pairs.iter().all(|(key, _)| key_name(key).is_some()) {
+                return JsonValue::Object(
+                    pairs
+                        .iter()
+                        .map(|(key, value)| (key_name(key).unwrap(), value_to_json(value)))
+                        .collect(),
+                );
+            }
+            return JsonValue::Array(
+                pairs
+                    .iter()
+                    .map(|(key, value)| {
+                        JsonValue::Array(vec![value_to_json(key), value_to_json(value)])
+                    })
+                    .collect(),
+            );
+        }
+        Node::Irange(_) | Node::Erange(_) => {
+            let (left, right, exclude_end) = range_bounds(value);
+            JsonValue::object(vec![
+                ("begin", left.map_or(JsonValue::Null, value_to_json)),
+                ("end", right.map_or(JsonValue::Null, value_to_json)),
+                ("exclude_end", JsonValue::Bool(exclude_end)),
+            ])
+        }
+        _ => JsonValue::Null,
+    }
+}
+
+fn write_rust_module<W: Write>(
+    module: &ExportedModule,
+    writer: &mut BufWriter<W>,
+    indent: u32,
+) -> Result<(), std::io::Error> {
+    for entry in module.entries.iter() {
+        let (name, value) = match entry {
+            ExportedEntry::Constant(name, value) => (name, value),
+            ExportedEntry::Module(child) => {
+                write_export_indent(writer, indent, "    ")?;
+                writeln!(writer, "pub mod {} {{", snake_case(&child.name))?;
+                write_rust_module(child, writer, indent + 1)?;
+                write_export_indent(writer, indent, "    ")?;
+                writer.write_all(b"}\n")?;
+                continue;
+            }
+        };
+        write_export_indent(writer, indent, "    ")?;
+        match rust_type_and_value(value) {
+            Some((rust_type, rust_value)) => writeln!(
+                writer,
+                "pub const {}: {} = {};",
+                screaming_snake_case(name),
+                rust_type,
+                rust_value
+            )?,
+            None => writeln!(
+                writer,
+                "// {} = {} has no Rust equivalent",
+                name,
+                code_to_string(value)
+            )?,
+        }
+    }
+    return Ok(());
+}
+
+fn rust_type_and_value(value: &Node) -> Option<(String, String)> {
+    match value {
+        Node::Int(int) => Some((String::from("i64"), int.value.clone())),
+        Node::Float(_) => Some((String::from("f64"), float_literal(value)?)),
+        Node::Str(str) => Some((
+            String::from("&str"),
+            format!("{:?}", str.value.to_string_lossy()),
+        )),
+        Node::Sym(sym) => Some((
+            String::from("&str"),
+            format!("{:?}", sym.name.to_string_lossy()),
+        )),
+        Node::True(_) => Some((String::from("bool"), String::from("true"))),
+        Node::False(_) => Some((String::from("bool"), String::from("false"))),
+        Node::Array(array) => {
+            let elements: Vec<(String, String)> = array
+                .elements
+                .iter()
+                .map(rust_type_and_value)
+                .collect::<Option<Vec<(String, String)>>>()?;
+            let element_type = common_type(&elements)?;
+            let values: Vec<String> = elements.into_iter().map(|(_, value)| value).collect();
+            Some((
+                format!("&[{}]", element_type),
+                format!("&[{}]", values.join(", ")),
+            ))
+        }
+        Node::Hash(_) => {
+            let mut keys: Vec<(String, String)> = vec![];
+            let mut values: Vec<(String, String)> = vec![];
+            for (key, value) in hash_pairs(value) {
+                keys.push(rust_type_and_value(key)?);
+                values.push(rust_type_and_value(value)?);
+            }
+            let key_type = common_type(&keys)?;
+            let value_type = common_type(&values)?;
+            let pairs: Vec<String> = keys
+                .iter()
+                .zip(values.iter())
+                .map(|((_, key), (_, value))| format!("({}, {})", key, value))
+                .collect();
+            Some((
+                format!("&[({}, {})]", key_type, value_type),
+                format!("&[{}]", pairs.join(", ")),
+            ))
+        }
+        Node::Irange(_) | Node::Erange(_) => {
+            let (left, right, exclude_end) = range_bounds(value);
+            let (left_type, left) = rust_type_and_value(left?)?;
+            let (right_type, right) = rust_type_and_value(right?)?;
+            if left_type != "i64" || right_type != "i64" {
+                return None;
+            }
+            if exclude_end {
+                return Some((
+                    String::from("core::ops::Range<i64>"),
+                    format!("{}..{}", left, right),
+                ));
+            }
+            Some((
+                String::from("core::ops::RangeInclusive<i64>"),
+                format!("{}..={}", left, right),
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn write_c_module<W: Write>(
+    module: &ExportedModule,
+    prefix: &str,
+    writer: &mut BufWriter<W>,
+) -> Result<(), std::io::Error> {
+    for entry in module.entries.iter() {
+        let (name, value) = match entry {
+            ExportedEntry::Constant(name, value) => (name, value),
+            ExportedEntry::Module(child) => {
+                let child_prefix = format!("{}{}_", prefix, screaming_snake_case(&child.name));
+                write_c_module(child, &child_prefix, writer)?;
+                continue;
+            }
+        };
+        let c_name = format!("{}{}", prefix, screaming_snake_case(name));
+        match value {
+            Node::Array(array) => {
+                let elements: Option<Vec<(String, String)>> =
+                    array.elements.iter().map(c_type_and_value).collect();
+                match elements.as_ref().and_then(|elements| {
+                    common_type(elements).map(|element_type| (element_type, elements))
+                }) {
+                    Some((element_type, elements)) => {
+                        let values: Vec<&str> =
+                            elements.iter().map(|(_, value)| value.as_str()).collect();
+                        // `const char *` elements need the pointer itself to be const
+                        let declaration = if element_type.ends_with('*') {
+                            format!("{}const {}", element_type, c_name)
+                        } else {
+                            format!("const {} {}", element_type, c_name)
+                        };
+                        writeln!(
+                            writer,
+                            "static {}[] = {{ {} }};",
+                            declaration,
+                            values.join(", ")
+                        )?;
+                    }
+                    None => write_c_unsupported(writer, name, value)?,
+                }
+            }
+            _ => match c_type_and_value(value) {
+                Some((_, c_value)) => writeln!(writer, "#define {} {}", c_name, c_value)?,
+                None => write_c_unsupported(writer, name, value)?,
+            },
+        }
+    }
+    return Ok(());
+}
+
+fn write_c_unsupported<W: Write>(
+    writer: &mut BufWriter<W>,
+    name: &str,
+    value: &Node,
+) -> Result<(), std::io::Error> {
+    return writeln!(
+        writer,
+        "/* {} = {} has no C equivalent */",
+        name,
+        code_to_string(value).replace("*/", "* /")
+    );
+}
+
+fn c_type_and_value(value: &Node) -> Option<(String, String)> {
+    match value {
+        Node::Int(int) => Some((String::from("int64_t"), format!("INT64_C({})", int.value))),
+        Node::Float(_) => Some((String::from("double"), float_literal(value)?)),
+        Node::Str(str) => Some((
+            String::from("const char *"),
+            c_string(&str.value.to_string_lossy()),
+        )),
+        Node::Sym(sym) => Some((
+            String::from("const char *"),
+            c_string(&sym.name.to_string_lossy()),
+        )),
+        Node::True(_) => Some((String::from("bool"), String::from("true"))),
+        Node::False(_) => Some((String::from("bool"), String::from("false"))),
+        _ => None,
+    }
+}
+
+fn write_ruby_module<W: Write>(
+    module: &ExportedModule,
+    writer: &mut BufWriter<W>,
+    indent: u32,
+) -> Result<(), std::io::Error> {
+    for entry in module.entries.iter() {
+        write_export_indent(writer, indent, "  ")?;
+        match entry {
+            ExportedEntry::Constant(name, value) => {
+                writeln!(writer, "{} = {}", name, code_to_string(value))?;
+            }
+            ExportedEntry::Module(child) => {
+                let keyword = if child.is_class { "class" } else { "module" };
+                writeln!(writer, "{} {}", keyword, child.name)?;
+                write_ruby_module(child, writer, indent + 1)?;
+                write_export_indent(writer, indent, "  ")?;
+                writer.write_all(b"end\n")?;
+            }
+        }
+    }
+    return Ok(());
+}
+
+fn write_export_indent<W: Write>(
+    writer: &mut BufWriter<W>,
+    indent: u32,
+    unit: &str,
+) -> Result<(), std::io::Error> {
+    for _ in 0..indent {
+        writer.write_all(unit.as_bytes())?;
+    }
+    return Ok(());
+}
+
+/** Type shared by all the elements, None if they differ (or there's no element) */
+fn common_type(elements: &Vec<(String, String)>) -> Option<String> {
+    let first_type = &elements.first()?.0;
+    if elements
+        .iter()
+        .all(|(element_type, _)| element_type == first_type)
+    {
+        return Some(first_type.clone());
+    }
+    return None;
+}
+
+fn hash_pairs(hash: &Node) -> Vec<(&Node, &Node)> {
+    match hash {
+        Node::Hash(hash) => hash
+            .pairs
+            .iter()
+            .filter_map(|pair| match pair {
+                Node::Pair(pair) => Some((pair.key.as_ref(), pair.value.as_ref())),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn key_name(key: &Node) -> Option<String> {
+    match key {
+        Node::Str(str) => Some(str.value.to_string_lossy()),
+        Node::Sym(sym) => Some(sym.name.to_string_lossy()),
+        _ => None,
+    }
+}
+
+fn range_bounds(range: &Node) -> (Option<&Node>, Option<&Node>, bool) {
+    match range {
+        Node::Irange(range) => (range.left.as_deref(), range.right.as_deref(), false),
+        Node::Erange(range) => (range.left.as_deref(), range.right.as_deref(), true),
+        _ => (None, None, false),
+    }
+}
+
+/** Float literal valid in JSON, Rust and C (no `_`, always a decimal point or an exponent) */
+fn float_literal(float: &Node) -> Option<String> {
+    let value = match float {
+        Node::Float(float) => float.value.replace('_', "").parse::<f64>().ok()?,
+        _ => return None,
+    };
+    if !value.is_finite() {
+        return None;
+    }
+    let literal = format!("{:?}", value);
+    return Some(literal);
+}
+
+fn c_string(value: &str) -> String {
+    let mut literal = String::from("\"");
+    for character in value.chars() {
+        match character {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    return literal;
+}
+
+/** `GameData` => `game_data`, `MAX_HP` => `max_hp` */
+fn snake_case(name: &str) -> String {
+    let mut result = String::new();
+    let characters: Vec<char> = name.chars().collect();
+    for (i, character) in characters.iter().enumerate() {
+        if character.is_uppercase() && i > 0 {
+            let previous = characters[i - 1];
+            let next_is_lowercase = characters.get(i + 1).map_or(false, |c| c.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_is_lowercase)
+            {
+                result.push('_');
+            }
+        }
+        result.extend(character.to_lowercase());
+    }
+    return result;
+}
+
+fn screaming_snake_case(name: &str) -> String {
+    return snake_case(name).to_uppercase();
+}
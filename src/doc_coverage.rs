@@ -0,0 +1,191 @@
+use std::io::{BufWriter, Write};
+
+use crate::{
+    documentation_tree::{DocumentedKind, DocumentedObject},
+    json::JsonValue,
+    visibility_filter::Visibility,
+};
+
+/** Public definition without documentation */
+pub struct UndocumentedObject {
+    pub kind: DocumentedKind,
+    pub path: String,
+    pub line: usize,
+}
+
+/** Documentation coverage of the public definitions of a file */
+pub struct FileCoverage {
+    pub file_name: String,
+    pub total: usize,
+    pub documented: usize,
+    pub undocumented: Vec<UndocumentedObject>,
+}
+
+impl FileCoverage {
+    pub fn new(file_name: String, objects: &Vec<DocumentedObject>) -> Self {
+        let mut coverage = FileCoverage {
+            file_name,
+            total: 0,
+            documented: 0,
+            undocumented: vec![],
+        };
+        coverage.add_objects(objects);
+        return coverage;
+    }
+
+    fn add_objects(&mut self, objects: &Vec<DocumentedObject>) {
+        for object in objects.iter() {
+            // Private definitions are not part of the API, neither is anything inside a private namespace
+            if object.visibility != Visibility::Public {
+                continue;
+            }
+            // Methods defined at the top level (`#name`, `.name`) are private methods of Object
+            if object.path.starts_with('#') || object.path.starts_with('.') {
+                continue;
+            }
+            self.total += 1;
+            if is_documented(object) {
+                self.documented += 1;
+            } else {
+                self.undocumented.push(UndocumentedObject {
+                    kind: object.kind,
+                    path: object.path.clone(),
+                    line: object.line,
+                });
+            }
+            self.add_objects(&object.children);
+        }
+    }
+
+    pub fn percentage(&self) -> f64 {
+        return coverage_percentage(self.documented, self.total);
+    }
+
+    fn to_json(&self) -> JsonValue {
+        return JsonValue::object(vec![
+            ("file", JsonValue::string(self.file_name.as_str())),
+            ("total", JsonValue::number(self.total)),
+            ("documented", JsonValue::number(self.documented)),
+            (
+                "percentage",
+                JsonValue::number(format_percentage(self.percentage())),
+            ),
+            (
+                "undocumented",
+                JsonValue::Array(
+                    self.undocumented
+                        .iter()
+                        .map(|object| {
+                            JsonValue::object(vec![
+                                ("kind", JsonValue::string(object.kind.as_str())),
+                                ("path", JsonValue::string(object.path.as_str())),
+                                ("line", JsonValue::number(object.line)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+        ]);
+    }
+}
+
+/** Documentation coverage of a set of files */
+pub struct CoverageReport {
+    pub files: Vec<FileCoverage>,
+    /** Minimum total percentage expected */
+    pub threshold: Option<f64>,
+}
+
+impl CoverageReport {
+    pub fn total(&self) -> usize {
+        return self.files.iter().map(|file| file.total).sum();
+    }
+
+    pub fn documented(&self) -> usize {
+        return self.files.iter().map(|file| file.documented).sum();
+    }
+
+    pub fn percentage(&self) -> f64 {
+        return coverage_percentage(self.documented(), self.total());
+    }
+
+    pub fn is_below_threshold(&self) -> bool {
+        match self.threshold {
+            Some(threshold) => self.percentage() < threshold,
+            None => false,
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut entries = vec![
+            (
+                "files",
+                JsonValue::Array(self.files.iter().map(|file| file.to_json()).collect()),
+            ),
+            ("total", JsonValue::number(self.total())),
+            ("documented", JsonValue::number(self.documented())),
+            (
+                "percentage",
+                JsonValue::number(format_percentage(self.percentage())),
+            ),
+        ];
+        if let Some(threshold) = self.threshold {
+            entries.push(("threshold", JsonValue::number(threshold)));
+            entries.push(("passed", JsonValue::Bool(!self.is_below_threshold())));
+        }
+        return JsonValue::object(entries);
+    }
+
+    pub fn write_text<W: Write>(&self, writer: &mut BufWriter<W>) -> Result<(), std::io::Error> {
+        for file in self.files.iter() {
+            writeln!(
+                writer,
+                "{}: {}/{} documented ({}%)",
+                file.file_name,
+                file.documented,
+                file.total,
+                format_percentage(file.percentage())
+            )?;
+            for object in file.undocumented.iter() {
+                writeln!(
+                    writer,
+                    "  line {}: {} {}",
+                    object.line,
+                    object.kind.as_str().replace('_', " "),
+                    object.path
+                )?;
+            }
+        }
+        writeln!(
+            writer,
+            "Total: {}/{} documented ({}%)",
+            self.documented(),
+            self.total(),
+            format_percentage(self.percentage())
+        )?;
+        return Ok(());
+    }
+}
+
+fn is_documented(object: &DocumentedObject) -> bool {
+    match &object.docstring {
+        Some(docstring) => {
+            !docstring.summary.is_empty()
+                || !docstring.description.is_empty()
+                || !docstring.tags.is_empty()
+        }
+        None => false,
+    }
+}
+
+/** Nothing to document counts as fully documented */
+fn coverage_percentage(documented: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 100.0;
+    }
+    return (documented as f64) * 100.0 / (total as f64);
+}
+
+fn format_percentage(percentage: f64) -> String {
+    return format!("{:.2}", percentage);
+}
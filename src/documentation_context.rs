@@ -1,20 +1,132 @@
-use std::io::{BufWriter, Write};
+use std::{
+    collections::HashMap,
+    io::{BufWriter, Write},
+    ops::RangeInclusive,
+};
 
-use lib_ruby_parser::source::{Comment, DecodedInput};
+use lib_ruby_parser::source::{Comment, CommentType, DecodedInput};
+
+use crate::{
+    combine_modules::MergedReopening,
+    yard::{comment_lines, parse_yard_comment, YardDocstring},
+};
+
+/** How the documentation of the reopenings merged by `combine_modules` is combined */
+#[derive(Debug, Clone)]
+pub enum DocumentationMergeStrategy {
+    /** Keep every documentation, separated by the given comment line */
+    Concatenate(String),
+    /** Keep the longest documentation */
+    PreferLongest,
+    /** Keep the first documentation and warn when reopenings are documented differently */
+    WarnOnConflict,
+}
 
 pub struct DocumentationContext {
     comments: Vec<Comment>,
     input: DecodedInput,
     exclude_method_body: bool,
+    /** Index of the comment blocks by the (0-based) line of the node they document */
+    comment_blocks: HashMap<usize, RangeInclusive<usize>>,
+    /** Documentation lines of the first definitions of merged reopenings, by node expression begin */
+    merged_documentation: HashMap<usize, Vec<String>>,
 }
 
 impl DocumentationContext {
-    pub fn new(comments: Vec<Comment>, input: DecodedInput, exclude_method_body: bool) -> Self {
-        return DocumentationContext {
+    /**
+     * Create the documentation context of a parsed file.
+     * When `blank_line_breaks_association` is false, a single blank line may separate a comment block from the node it documents.
+     */
+    pub fn new(
+        comments: Vec<Comment>,
+        input: DecodedInput,
+        exclude_method_body: bool,
+        blank_line_breaks_association: bool,
+    ) -> Self {
+        let mut context = DocumentationContext {
             comments,
             input,
             exclude_method_body,
+            comment_blocks: HashMap::new(),
+            merged_documentation: HashMap::new(),
         };
+        context.index_comment_blocks(blank_line_breaks_association);
+        return context;
+    }
+
+    /** Group the comments standing on their own lines into contiguous blocks and index them by the line they document */
+    fn index_comment_blocks(&mut self, blank_line_breaks_association: bool) {
+        let mut current_block: Option<(RangeInclusive<usize>, usize)> = None;
+        let mut blocks: Vec<(RangeInclusive<usize>, usize)> = vec![];
+        for (index, comment) in self.comments.iter().enumerate() {
+            let first_line = self.line_index(comment.location.begin);
+            let last_line =
+                self.line_index(comment.location.end.max(comment.location.begin + 1) - 1);
+            if !self.is_blank(self.input.lines[first_line].start, comment.location.begin) {
+                // Comment at the end of a line of code, it does not document anything
+                if let Some(block) = current_block.take() {
+                    blocks.push(block);
+                }
+                continue;
+            }
+            current_block = match current_block.take() {
+                Some((range, block_last_line))
+                    if block_last_line + 1 == first_line && comment.kind == CommentType::Inline =>
+                {
+                    Some((*range.start()..=index, last_line))
+                }
+                Some(block) => {
+                    blocks.push(block);
+                    Some((index..=index, last_line))
+                }
+                None => Some((index..=index, last_line)),
+            };
+        }
+        if let Some(block) = current_block {
+            blocks.push(block);
+        }
+
+        for (range, last_line) in blocks {
+            let documented_line = last_line + 1;
+            if !blank_line_breaks_association && self.is_blank_line(documented_line) {
+                self.comment_blocks
+                    .insert(documented_line + 1, range.clone());
+            }
+            self.comment_blocks.insert(documented_line, range);
+        }
+    }
+
+    /** Get the block of comments right above the node */
+    pub fn documentation_comments(&self, node_expression_begin: usize) -> &[Comment] {
+        let line = self.line_index(node_expression_begin);
+        match self.comment_blocks.get(&line) {
+            // Only the first node of a line is documented (`private def x` documents `private`)
+            Some(range) if self.is_blank(self.input.lines[line].start, node_expression_begin) => {
+                &self.comments[range.clone()]
+            }
+            _ => &[],
+        }
+    }
+
+    /** Get the lines of line comments documenting the node */
+    pub fn documentation_lines(&self, node_expression_begin: usize) -> Vec<String> {
+        if let Some(lines) = self.merged_documentation.get(&node_expression_begin) {
+            return lines.clone();
+        }
+        let mut lines: Vec<String> = vec![];
+        for comment in self.documentation_comments(node_expression_begin) {
+            let text = self.comment_text(comment);
+            match comment.kind {
+                // `=begin` must start the line, the block is turned into line comments to follow the indentation
+                CommentType::Document => lines.extend(
+                    comment_lines(&text)
+                        .iter()
+                        .map(|line| format!("# {}", line).trim_end().to_string()),
+                ),
+                _ => lines.push(String::from(text.trim_start())),
+            }
+        }
+        return lines;
     }
 
     pub fn write_documentation<W: Write>(
@@ -24,45 +136,154 @@ impl DocumentationContext {
         node_expression_begin: usize,
     ) -> Result<(), std::io::Error> {
         let indent_offset = (indent * 2) as usize;
-        let last_comment_expression_end: usize = node_expression_begin - indent_offset;
-        let last_comment = self
-            .comments
-            .iter()
-            .find(|c| c.location.end == last_comment_expression_end);
-        if let Some(last_comment) = last_comment {
-            let mut first_comment = last_comment;
-            let mut last_comment_expression_end = first_comment.location.begin - indent_offset;
-            while let Some(previous_comment) = self
-                .comments
-                .iter()
-                .find(|c| c.location.end == last_comment_expression_end)
+        for line in self.documentation_lines(node_expression_begin) {
+            writer.write_all(line.as_bytes())?;
+            writer.write_all(b"\n")?;
+            if indent > 0 {
+                writer.write_all(b" ".repeat(indent_offset)[..].as_ref())?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    /** Get the text of the comments documenting the node */
+    pub fn documentation_text(&self, node_expression_begin: usize) -> Option<String> {
+        let lines = self.documentation_lines(node_expression_begin);
+        if lines.is_empty() {
+            return None;
+        }
+        let mut text = lines.join("\n");
+        text.push('\n');
+        return Some(text);
+    }
+
+    /**
+     * Combine the documentation of the reopenings merged by `combine_modules` into the documentation of their first definition.
+     * Returns the conflicts found with `DocumentationMergeStrategy::WarnOnConflict`.
+     */
+    pub fn merge_reopened_documentation(
+        &mut self,
+        merged: &Vec<MergedReopening>,
+        strategy: &DocumentationMergeStrategy,
+    ) -> Vec<String> {
+        let mut warnings: Vec<String> = vec![];
+        let mut reopenings_by_definition: Vec<(&MergedReopening, Vec<&MergedReopening>)> = vec![];
+        for reopening in merged.iter() {
+            match reopenings_by_definition
+                .iter_mut()
+                .find(|(first, _)| first.first_l == reopening.first_l)
             {
-                first_comment = previous_comment;
-                last_comment_expression_end = first_comment.location.begin - indent_offset;
+                Some((_, reopenings)) => reopenings.push(reopening),
+                None => reopenings_by_definition.push((reopening, vec![reopening])),
             }
-            let first_index = self
-                .comments
-                .iter()
-                .position(|c| c == first_comment)
-                .unwrap();
-            let last_index = self
-                .comments
-                .iter()
-                .position(|c| c == last_comment)
-                .unwrap();
-            for index in first_index..=last_index {
-                let comment = self.comments.get(index).unwrap();
-                writer.write(&self.input.bytes[comment.location.begin..comment.location.end])?;
-                if indent > 0 {
-                    writer.write(b" ".repeat(indent_offset)[..].as_ref())?;
+        }
+
+        for (first, reopenings) in reopenings_by_definition {
+            let mut documentations: Vec<(usize, Vec<String>)> = vec![];
+            for begin in std::iter::once(first.first_l.begin).chain(
+                reopenings
+                    .iter()
+                    .map(|reopening| reopening.reopening_l.begin),
+            ) {
+                let lines = self.documentation_lines(begin);
+                if !lines.is_empty() && !documentations.iter().any(|(_, other)| *other == lines) {
+                    documentations.push((begin, lines));
                 }
             }
+            let lines: Vec<String> = match strategy {
+                DocumentationMergeStrategy::Concatenate(separator) => {
+                    let mut lines: Vec<String> = vec![];
+                    for (i, (_, documentation)) in documentations.iter().enumerate() {
+                        if i > 0 {
+                            lines.push(separator.clone());
+                        }
+                        lines.extend(documentation.iter().cloned());
+                    }
+                    lines
+                }
+                DocumentationMergeStrategy::PreferLongest => documentations
+                    .iter()
+                    .map(|(_, documentation)| documentation)
+                    .fold(vec![], |longest: Vec<String>, documentation| {
+                        if documentation.join("\n").len() > longest.join("\n").len() {
+                            documentation.clone()
+                        } else {
+                            longest
+                        }
+                    }),
+                DocumentationMergeStrategy::WarnOnConflict => {
+                    for (begin, _) in documentations.iter().skip(1) {
+                        warnings.push(format!(
+                            "{} at line {} is documented differently than at line {}, the documentation of line {} is kept",
+                            first.module_name,
+                            self.line_of(*begin),
+                            self.line_of(documentations[0].0),
+                            self.line_of(documentations[0].0)
+                        ));
+                    }
+                    documentations
+                        .first()
+                        .map(|(_, documentation)| documentation.clone())
+                        .unwrap_or_default()
+                }
+            };
+            self.merged_documentation.insert(first.first_l.begin, lines);
         }
+        return warnings;
+    }
 
-        return Ok(());
+    /** Get the YARD docstring documenting the node */
+    pub fn yard_docstring(&self, node_expression_begin: usize) -> Option<YardDocstring> {
+        return self
+            .documentation_text(node_expression_begin)
+            .map(|text| parse_yard_comment(&text));
+    }
+
+    /** Get the 1-based line of an offset in the documented source */
+    pub fn line_of(&self, offset: usize) -> usize {
+        if self.input.lines.is_empty() {
+            return 0;
+        }
+        return self.line_index(offset) + 1;
     }
 
     pub fn method_body_excluded(&self) -> bool {
         self.exclude_method_body
     }
+
+    /** Text of a comment without its line terminators and trailing spaces */
+    fn comment_text(&self, comment: &Comment) -> String {
+        let text = String::from_utf8_lossy(
+            &self.input.bytes[comment.location.begin..comment.location.end],
+        );
+        return text
+            .lines()
+            .map(|line| line.trim_end())
+            .collect::<Vec<&str>>()
+            .join("\n");
+    }
+
+    /** Get the 0-based line of an offset (binary search over the lines of the input) */
+    fn line_index(&self, offset: usize) -> usize {
+        return self
+            .input
+            .lines
+            .partition_point(|line| line.start <= offset)
+            .max(1)
+            - 1;
+    }
+
+    fn is_blank(&self, begin: usize, end: usize) -> bool {
+        return self.input.bytes[begin..end]
+            .iter()
+            .all(|byte| byte.is_ascii_whitespace());
+    }
+
+    fn is_blank_line(&self, line: usize) -> bool {
+        match self.input.lines.get(line) {
+            Some(source_line) => self.is_blank(source_line.start, source_line.end),
+            None => false,
+        }
+    }
 }
@@ -0,0 +1,661 @@
+use lib_ruby_parser::{
+    nodes::{Args, Casgn, Send},
+    Loc, Node,
+};
+
+use crate::{
+    code_writer::code_to_string, documentation_context::DocumentationContext, json::JsonValue,
+    tests::constant_resolver::ConstantResolver, visibility_filter::Visibility, yard::YardDocstring,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentedKind {
+    Module,
+    Class,
+    InstanceMethod,
+    ClassMethod,
+    Attribute,
+    Constant,
+}
+
+impl DocumentedKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DocumentedKind::Module => "module",
+            DocumentedKind::Class => "class",
+            DocumentedKind::InstanceMethod => "instance_method",
+            DocumentedKind::ClassMethod => "class_method",
+            DocumentedKind::Attribute => "attribute",
+            DocumentedKind::Constant => "constant",
+        }
+    }
+
+    pub fn is_namespace(&self) -> bool {
+        return *self == DocumentedKind::Module || *self == DocumentedKind::Class;
+    }
+}
+
+/** `include`, `extend` or `prepend` statement of a class or module */
+#[derive(Debug, Clone)]
+pub struct Mixin {
+    pub method_name: String,
+    pub module_name: String,
+}
+
+/** Definition found in the code with the documentation attached to it */
+#[derive(Debug, Clone)]
+pub struct DocumentedObject {
+    pub kind: DocumentedKind,
+    pub name: String,
+    /** Full path of the object (`A::B`, `A::B#method`, `A::B.class_method`, `A::B::CONSTANT`) */
+    pub path: String,
+    pub location: Loc,
+    pub line: usize,
+    pub visibility: Visibility,
+    pub docstring: Option<YardDocstring>,
+    pub superclass: Option<String>,
+    pub mixins: Vec<Mixin>,
+    /** Arguments of a method */
+    pub args: Option<Args>,
+    /** Access of an attribute (`attr_reader`, `attr_writer` or `attr_accessor`) */
+    pub attribute_access: Option<String>,
+    /** Value of a constant */
+    pub value: Option<Node>,
+    pub children: Vec<DocumentedObject>,
+}
+
+impl DocumentedObject {
+    fn new(kind: DocumentedKind, name: String, path: String, location: Loc) -> Self {
+        return DocumentedObject {
+            kind,
+            name,
+            path,
+            location,
+            line: 0,
+            visibility: Visibility::Public,
+            docstring: None,
+            superclass: None,
+            mixins: vec![],
+            args: None,
+            attribute_access: None,
+            value: None,
+            children: vec![],
+        };
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut entries = vec![
+            ("kind", JsonValue::string(self.kind.as_str())),
+            ("name", JsonValue::string(self.name.as_str())),
+            ("path", JsonValue::string(self.path.as_str())),
+            ("line", JsonValue::number(self.line)),
+            ("visibility", JsonValue::string(self.visibility.as_str())),
+        ];
+        if self.kind == DocumentedKind::Class {
+            entries.push(("superclass", JsonValue::optional_string(&self.superclass)));
+        }
+        if self.kind.is_namespace() {
+            entries.push((
+                "mixins",
+                JsonValue::Array(
+                    self.mixins
+                        .iter()
+                        .map(|mixin| {
+                            JsonValue::object(vec![
+                                ("kind", JsonValue::string(mixin.method_name.as_str())),
+                                ("module", JsonValue::string(mixin.module_name.as_str())),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ));
+        }
+        if let Some(args) = &self.args {
+            entries.push((
+                "parameters",
+                JsonValue::Array(
+                    args.args
+                        .iter()
+                        .map(|arg| JsonValue::string(code_to_string(arg)))
+                        .collect(),
+                ),
+            ));
+        }
+        if let Some(access) = &self.attribute_access {
+            entries.push(("access", JsonValue::string(access.as_str())));
+        }
+        if let Some(value) = &self.value {
+            entries.push(("value", JsonValue::string(code_to_string(value))));
+        }
+        entries.push((
+            "docstring",
+            match &self.docstring {
+                Some(docstring) => docstring.to_json(),
+                None => JsonValue::Null,
+            },
+        ));
+        if self.kind.is_namespace() {
+            entries.push((
+                "children",
+                JsonValue::Array(self.children.iter().map(|child| child.to_json()).collect()),
+            ));
+        }
+        return JsonValue::object(entries);
+    }
+}
+
+/** State of the body of a namespace while its statements are walked */
+struct NamespaceState {
+    mixins: Vec<Mixin>,
+    /** Visibility given to the next methods by a bare `private`, `protected` or `public` */
+    default_visibility: Visibility,
+    /** Whether the next methods are module functions (bare `module_function`) */
+    module_function: bool,
+}
+
+impl NamespaceState {
+    fn new() -> Self {
+        return NamespaceState {
+            mixins: vec![],
+            default_visibility: Visibility::Public,
+            module_function: false,
+        };
+    }
+
+    /** Visibility of a method defined at this point of the body */
+    fn method_visibility(&self) -> Visibility {
+        // Module functions are called on the module, they are part of its API
+        if self.module_function {
+            return Visibility::Public;
+        }
+        return self.default_visibility;
+    }
+}
+
+/** Position of the walker in the code */
+struct DocumentationScope<'a> {
+    path: &'a str,
+    singleton: bool,
+    /** Resolution of the receivers of the singleton methods (`def Foo.bar`) */
+    resolver: &'a ConstantResolver,
+}
+
+/** Collect every documentable definition (with its documentation) of a program */
+pub fn build_documentation_tree(
+    node: &Node,
+    context: &DocumentationContext,
+) -> Vec<DocumentedObject> {
+    let mut objects: Vec<DocumentedObject> = vec![];
+    let mut state = NamespaceState::new();
+    let resolver = ConstantResolver::new(node);
+    let scope = DocumentationScope {
+        path: "",
+        singleton: false,
+        resolver: &resolver,
+    };
+    collect_documented_objects(node, context, &scope, &mut objects, &mut state);
+    return objects;
+}
+
+pub fn documentation_tree_to_json(objects: &Vec<DocumentedObject>) -> JsonValue {
+    return JsonValue::Array(objects.iter().map(|object| object.to_json()).collect());
+}
+
+fn collect_documented_objects(
+    node: &Node,
+    context: &DocumentationContext,
+    scope: &DocumentationScope,
+    objects: &mut Vec<DocumentedObject>,
+    state: &mut NamespaceState,
+) {
+    match node {
+        Node::Begin(begin) => {
+            for statement in begin.statements.iter() {
+                collect_documented_objects(statement, context, scope, objects, state);
+            }
+        }
+        Node::Module(module) => {
+            let mut object = make_namespace(
+                DocumentedKind::Module,
+                &module.name,
+                &module.body,
+                module.expression_l,
+                context,
+                scope,
+            );
+            object.docstring = context.yard_docstring(module.expression_l.begin);
+            objects.push(object);
+        }
+        Node::Class(class) => {
+            let mut object = make_namespace(
+                DocumentedKind::Class,
+                &class.name,
+                &class.body,
+                class.expression_l,
+                context,
+                scope,
+            );
+            object.superclass = class.superclass.as_deref().map(code_to_string);
+            object.docstring = context.yard_docstring(class.expression_l.begin);
+            objects.push(object);
+        }
+        Node::SClass(sclass) => {
+            if let (Node::Self_(_), Some(body)) = (sclass.expr.as_ref(), &sclass.body) {
+                let singleton_scope = DocumentationScope {
+                    path: scope.path,
+                    singleton: true,
+                    resolver: scope.resolver,
+                };
+                let mut singleton_state = NamespaceState::new();
+                collect_documented_objects(
+                    body,
+                    context,
+                    &singleton_scope,
+                    objects,
+                    &mut singleton_state,
+                );
+            }
+        }
+        Node::Def(def) => {
+            let object = make_method(&def.name, &def.args, def.expression_l, scope, state);
+            objects.push(attach_documentation(object, context));
+        }
+        Node::Block(block) => {
+            let send = match block.call.as_ref() {
+                Node::Send(send) if send.recv.is_none() => send,
+                _ => return,
+            };
+            let name = match send.args.first().and_then(symbol_name) {
+                Some(name) => name,
+                None => return,
+            };
+            match send.method_name.as_str() {
+                "define_method" => {
+                    let object = make_method(&name, &block.args, block.expression_l, scope, state);
+                    objects.push(attach_documentation(object, context));
+                }
+                "define_singleton_method" => {
+                    let mut object = DocumentedObject::new(
+                        DocumentedKind::ClassMethod,
+                        name.clone(),
+                        method_path(scope.path, DocumentedKind::ClassMethod, &name),
+                        block.expression_l,
+                    );
+                    object.args = method_args(&block.args);
+                    objects.push(attach_documentation(object, context));
+                }
+                _ => {}
+            }
+        }
+        Node::Alias(alias) => {
+            if let Some(name) = symbol_name(&alias.to) {
+                let object = make_method(&name, &None, alias.expression_l, scope, state);
+                objects.push(attach_documentation(object, context));
+            }
+        }
+        Node::Defs(def) => {
+            // Methods defined on a single object (`def obj.name`) are not documented
+            let namespace_path = match receiver_path(&def.definee, scope) {
+                Some(namespace_path) => namespace_path,
+                None => return,
+            };
+            let mut object = DocumentedObject::new(
+                DocumentedKind::ClassMethod,
+                def.name.clone(),
+                method_path(&namespace_path, DocumentedKind::ClassMethod, &def.name),
+                def.expression_l,
+            );
+            object.args = method_args(&def.args);
+            objects.push(attach_documentation(object, context));
+        }
+        Node::Casgn(asgn) => {
+            let name = match &asgn.scope {
+                Some(const_scope) => format!("{}::{}", code_to_string(const_scope), asgn.name),
+                None => asgn.name.clone(),
+            };
+            if let Some(object) = make_struct(&name, asgn, context, scope) {
+                objects.push(attach_documentation(object, context));
+                return;
+            }
+            let mut object = DocumentedObject::new(
+                DocumentedKind::Constant,
+                name.clone(),
+                join_path(scope.path, &name),
+                asgn.expression_l,
+            );
+            object.value = asgn.value.as_deref().cloned();
+            objects.push(attach_documentation(object, context));
+        }
+        Node::Send(send) => {
+            if send.recv.is_some() {
+                return;
+            }
+            match send.method_name.as_str() {
+                "attr_accessor" | "attr_reader" | "attr_writer" => {
+                    for arg in send.args.iter() {
+                        if let Some(name) = symbol_name(arg) {
+                            let mut object = DocumentedObject::new(
+                                DocumentedKind::Attribute,
+                                name.clone(),
+                                method_path(scope.path, DocumentedKind::InstanceMethod, &name),
+                                send.expression_l,
+                            );
+                            object.attribute_access = Some(send.method_name.clone());
+                            object.visibility = state.method_visibility();
+                            objects.push(attach_documentation(object, context));
+                        }
+                    }
+                }
+                "define_method" | "alias_method" => {
+                    // `define_method(:name, method)`, the block form is handled with `Node::Block`
+                    if let Some(name) = send.args.first().and_then(symbol_name) {
+                        let object = make_method(&name, &None, send.expression_l, scope, state);
+                        objects.push(attach_documentation(object, context));
+                    }
+                }
+                "def_delegators" | "def_delegator" | "delegate" => {
+                    let names: Vec<String> = match send.method_name.as_str() {
+                        // def_delegators :@target, :name, :other_name
+                        "def_delegators" => {
+                            send.args.iter().skip(1).filter_map(symbol_name).collect()
+                        }
+                        // def_delegator :@target, :method, :alias
+                        "def_delegator" => send
+                            .args
+                            .iter()
+                            .skip(1)
+                            .filter_map(symbol_name)
+                            .last()
+                            .into_iter()
+                            .collect(),
+                        // delegate :name, :other_name, to: :target
+                        _ => send.args.iter().filter_map(symbol_name).collect(),
+                    };
+                    for name in names {
+                        let object = make_method(&name, &None, send.expression_l, scope, state);
+                        objects.push(attach_documentation(object, context));
+                    }
+                }
+                "module_function" => {
+                    if send.args.is_empty() {
+                        state.module_function = true;
+                    }
+                    // `module_function :name` or `module_function def name`
+                    let kinds = [DocumentedKind::InstanceMethod];
+                    for arg in send.args.iter() {
+                        apply_visibility(
+                            send,
+                            arg,
+                            Visibility::Public,
+                            &kinds,
+                            context,
+                            scope,
+                            objects,
+                            state,
+                        );
+                    }
+                }
+                "include" | "extend" | "prepend" => {
+                    for arg in send.args.iter() {
+                        state.mixins.push(Mixin {
+                            method_name: send.method_name.clone(),
+                            module_name: code_to_string(arg),
+                        });
+                    }
+                }
+                "private" | "protected" | "public" => {
+                    let visibility = Visibility::from_method_name(&send.method_name).unwrap();
+                    if send.args.is_empty() {
+                        state.default_visibility = visibility;
+                        state.module_function = false;
+                    }
+                    let kinds = [DocumentedKind::InstanceMethod, DocumentedKind::Attribute];
+                    for arg in send.args.iter() {
+                        apply_visibility(
+                            send, arg, visibility, &kinds, context, scope, objects, state,
+                        );
+                    }
+                }
+                "private_class_method" | "public_class_method" => {
+                    let visibility = Visibility::from_method_name(&send.method_name).unwrap();
+                    for arg in send.args.iter() {
+                        let kinds = [DocumentedKind::ClassMethod];
+                        apply_visibility(
+                            send, arg, visibility, &kinds, context, scope, objects, state,
+                        );
+                    }
+                }
+                "private_constant" | "public_constant" => {
+                    let visibility = Visibility::from_method_name(&send.method_name).unwrap();
+                    let kinds = [
+                        DocumentedKind::Constant,
+                        DocumentedKind::Module,
+                        DocumentedKind::Class,
+                    ];
+                    for arg in send.args.iter() {
+                        apply_visibility(
+                            send, arg, visibility, &kinds, context, scope, objects, state,
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+/**
+ * Apply the visibility of a `private`/`protected`/`public` like call to one of its argument,
+ * either a symbol naming definitions made earlier or an inline definition (`private def x`)
+ */
+fn apply_visibility(
+    send: &Send,
+    arg: &Node,
+    visibility: Visibility,
+    kinds: &[DocumentedKind],
+    context: &DocumentationContext,
+    scope: &DocumentationScope,
+    objects: &mut Vec<DocumentedObject>,
+    state: &mut NamespaceState,
+) {
+    if let Some(name) = symbol_name(arg) {
+        for object in objects.iter_mut() {
+            if object.name == name && kinds.contains(&object.kind) {
+                object.visibility = visibility;
+            }
+        }
+        return;
+    }
+    let first_index = objects.len();
+    collect_documented_objects(arg, context, scope, objects, state);
+    for object in objects[first_index..].iter_mut() {
+        object.visibility = visibility;
+    }
+    attach_call_documentation(&mut objects[first_index..], send, context);
+}
+
+/** Give the documentation of a call (`private def name`) to the definitions made in its arguments */
+fn attach_call_documentation(
+    objects: &mut [DocumentedObject],
+    send: &Send,
+    context: &DocumentationContext,
+) {
+    for object in objects.iter_mut() {
+        if object.docstring.is_none() {
+            object.docstring = context.yard_docstring(send.expression_l.begin);
+            object.line = context.line_of(send.expression_l.begin);
+        }
+    }
+}
+
+fn make_method(
+    name: &str,
+    args: &Option<Box<Node>>,
+    location: Loc,
+    scope: &DocumentationScope,
+    state: &NamespaceState,
+) -> DocumentedObject {
+    let kind = if scope.singleton {
+        DocumentedKind::ClassMethod
+    } else {
+        DocumentedKind::InstanceMethod
+    };
+    let mut object = DocumentedObject::new(
+        kind,
+        String::from(name),
+        method_path(scope.path, kind, name),
+        location,
+    );
+    object.args = method_args(args);
+    object.visibility = state.method_visibility();
+    return object;
+}
+
+/** Document `Name = Struct.new(:member, ...) do ... end` as a class with an attribute per member */
+fn make_struct(
+    name: &str,
+    asgn: &Casgn,
+    context: &DocumentationContext,
+    scope: &DocumentationScope,
+) -> Option<DocumentedObject> {
+    let (send, body) = match asgn.value.as_deref() {
+        Some(Node::Send(send)) => (send, None),
+        Some(Node::Block(block)) => match block.call.as_ref() {
+            Node::Send(send) => (send, block.body.as_deref()),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let is_struct = send.method_name == "new"
+        && matches!(send.recv.as_deref(), Some(Node::Const(constant)) if constant.name == "Struct");
+    if !is_struct {
+        return None;
+    }
+    let mut object = DocumentedObject::new(
+        DocumentedKind::Class,
+        String::from(name),
+        join_path(scope.path, name),
+        asgn.expression_l,
+    );
+    object.superclass = Some(String::from("Struct"));
+    for member in send.args.iter().filter_map(symbol_name) {
+        let mut attribute = DocumentedObject::new(
+            DocumentedKind::Attribute,
+            member.clone(),
+            method_path(&object.path, DocumentedKind::InstanceMethod, &member),
+            send.expression_l,
+        );
+        attribute.attribute_access = Some(String::from("attr_accessor"));
+        attribute.line = context.line_of(send.expression_l.begin);
+        object.children.push(attribute);
+    }
+    if let Some(body) = body {
+        let body_scope = DocumentationScope {
+            path: object.path.as_str(),
+            singleton: false,
+            resolver: scope.resolver,
+        };
+        let mut children: Vec<DocumentedObject> = vec![];
+        let mut state = NamespaceState::new();
+        collect_documented_objects(body, context, &body_scope, &mut children, &mut state);
+        object.children.extend(children);
+        object.mixins = state.mixins;
+    }
+    return Some(object);
+}
+
+fn make_namespace(
+    kind: DocumentedKind,
+    name: &Node,
+    body: &Option<Box<Node>>,
+    location: Loc,
+    context: &DocumentationContext,
+    scope: &DocumentationScope,
+) -> DocumentedObject {
+    let name = code_to_string(name);
+    let path = match name.strip_prefix("::") {
+        Some(absolute_name) => String::from(absolute_name),
+        None => join_path(scope.path, &name),
+    };
+    let name = String::from(name.trim_start_matches("::"));
+    let mut object = DocumentedObject::new(kind, name, path, location);
+    object.line = context.line_of(location.begin);
+    if let Some(body) = body {
+        let body_scope = DocumentationScope {
+            path: object.path.as_str(),
+            singleton: false,
+            resolver: scope.resolver,
+        };
+        let mut children: Vec<DocumentedObject> = vec![];
+        let mut state = NamespaceState::new();
+        collect_documented_objects(body, context, &body_scope, &mut children, &mut state);
+        object.children = children;
+        object.mixins = state.mixins;
+    }
+    return object;
+}
+
+fn attach_documentation(
+    mut object: DocumentedObject,
+    context: &DocumentationContext,
+) -> DocumentedObject {
+    object.line = context.line_of(object.location.begin);
+    object.docstring = context.yard_docstring(object.location.begin);
+    return object;
+}
+
+fn method_args(args: &Option<Box<Node>>) -> Option<Args> {
+    match args.as_deref() {
+        Some(Node::Args(args)) => Some(args.clone()),
+        _ => Some(Args {
+            args: vec![],
+            expression_l: Loc { begin: 0, end: 0 },
+            begin_l: None,
+            end_l: None,
+        }),
+    }
+}
+
+fn symbol_name(node: &Node) -> Option<String> {
+    match node {
+        Node::Sym(sym) => Some(sym.name.to_string_lossy()),
+        Node::Str(str) => Some(str.value.to_string_lossy()),
+        _ => None,
+    }
+}
+
+fn join_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        return String::from(name);
+    }
+    return format!("{}::{}", parent, name);
+}
+
+/** Full path of the namespace a singleton method is defined on: `self` or a constant */
+fn receiver_path(definee: &Node, scope: &DocumentationScope) -> Option<String> {
+    match definee {
+        Node::Self_(_) => return Some(String::from(scope.path)),
+        Node::Const(_) => {}
+        _ => return None,
+    }
+    let resolved = scope
+        .resolver
+        .references
+        .iter()
+        .find(|reference| reference.expression_l == *definee.expression())
+        .and_then(|reference| scope.resolver.resolve(reference));
+    // Constants defined in other files are looked up from the top level
+    return Some(
+        resolved.unwrap_or_else(|| String::from(code_to_string(definee).trim_start_matches("::"))),
+    );
+}
+
+fn method_path(parent: &str, kind: DocumentedKind, name: &str) -> String {
+    let separator = if kind == DocumentedKind::ClassMethod {
+        "."
+    } else {
+        "#"
+    };
+    return format!("{}{}{}", parent, separator, name);
+}
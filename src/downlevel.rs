@@ -0,0 +1,718 @@
+use lib_ruby_parser::{
+    nodes::{Arg, Args, Block, BlockPass, Blockarg, If, Lvar, Nil, Restarg, Splat},
+    source::DecodedInput,
+    Loc, Node, Parser, ParserOptions,
+};
+
+use crate::{
+    code_writer::code_to_string,
+    search_replace::is_primary,
+    visitor::{children_with_steps, walk, walk_mut, NodePath, PathStep, Visitor, VisitorMut},
+};
+
+/** Names given to the arguments of `def foo(...)` and `def foo(&)` */
+const FORWARDED_ARGS: &str = "__forwarded_args";
+const FORWARDED_BLOCK: &str = "__forwarded_block";
+
+/** Version of Ruby the code has to run on */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RubyVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl RubyVersion {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        return RubyVersion { major, minor };
+    }
+
+    /** Parse `2.5` (a patch number is ignored) */
+    pub fn parse(version: &str) -> Option<Self> {
+        let mut numbers = version.trim().split('.');
+        let major = numbers.next()?.parse::<u32>().ok()?;
+        let minor = numbers.next().unwrap_or("0").parse::<u32>().ok()?;
+        return Some(RubyVersion::new(major, minor));
+    }
+}
+
+impl std::fmt::Display for RubyVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/** Construct that cannot be written for the target version */
+#[derive(Debug, Clone)]
+pub struct DownlevelError {
+    pub message: String,
+    pub expression_l: Loc,
+}
+
+impl DownlevelError {
+    pub fn describe(&self, file_name: &str, input: &DecodedInput) -> String {
+        let (line, column) = input
+            .line_col_for_pos(self.expression_l.begin)
+            .unwrap_or((0, 0));
+        return format!(
+            "{}:{}:{}: {}",
+            file_name,
+            line + 1,
+            column + 1,
+            self.message
+        );
+    }
+}
+
+/**
+ * Rewrite the syntax newer than the target version with older constructs: endless methods,
+ * numbered block parameters, argument forwarding, anonymous block arguments and pattern matching
+ * (`case/in`, `=>` and `in`). Hash shorthands (`{ x:, y: }`) need nothing, the writer always
+ * writes the values. Endless and beginless ranges cannot be lowered and are reported.
+ */
+pub fn downlevel(node: &mut Node, target: RubyVersion) -> Result<(), Vec<DownlevelError>> {
+    let mut lowerer = Lowerer {
+        target,
+        errors: vec![],
+        temporaries: 0,
+    };
+    walk_mut(&mut lowerer, node);
+    if !lowerer.errors.is_empty() {
+        return Err(lowerer.errors);
+    }
+    return Ok(());
+}
+
+struct Lowerer {
+    target: RubyVersion,
+    errors: Vec<DownlevelError>,
+    /** Number of temporary variables created for pattern matching */
+    temporaries: usize,
+}
+
+impl Lowerer {
+    fn supports(&self, major: u32, minor: u32) -> bool {
+        return self.target >= RubyVersion::new(major, minor);
+    }
+
+    fn error(&mut self, message: String, expression_l: Loc) {
+        self.errors.push(DownlevelError {
+            message,
+            expression_l,
+        });
+    }
+
+    fn lower_endless_def(&mut self, node: &mut Node) {
+        let (assignment_l, end_l, expression_l) = match node {
+            Node::Def(def) => (&mut def.assignment_l, &mut def.end_l, def.expression_l),
+            Node::Defs(defs) => (&mut defs.assignment_l, &mut defs.end_l, defs.expression_l),
+            _ => return,
+        };
+        if assignment_l.is_some() && !self.supports(3, 0) {
+            *assignment_l = None;
+            *end_l = Some(Loc {
+                begin: expression_l.end,
+                end: expression_l.end,
+            });
+        }
+    }
+
+    /** `def foo(...)` => `def foo(*args, &block)`, `def foo(&)` => `def foo(&block)` */
+    fn lower_forwarding(&mut self, node: &mut Node) {
+        let (args, body) = match node {
+            Node::Def(def) => (&mut def.args, &mut def.body),
+            Node::Defs(defs) => (&mut defs.args, &mut defs.body),
+            _ => return,
+        };
+        let args = match args.as_deref_mut() {
+            Some(Node::Args(args)) => args,
+            _ => return,
+        };
+        let forwards_all = args
+            .args
+            .iter()
+            .any(|arg| matches!(arg, Node::ForwardArg(_)));
+        // `...` alone came with 2.7, after other arguments with 3.0
+        let forwarding =
+            forwards_all && (!self.supports(2, 7) || (args.args.len() > 1 && !self.supports(3, 0)));
+        let anonymous_block = !self.supports(3, 1)
+            && args
+                .args
+                .iter()
+                .any(|arg| matches!(arg, Node::Blockarg(blockarg) if blockarg.name.is_none()));
+        if !forwarding && !anonymous_block {
+            return;
+        }
+        let mut lowered_args: Vec<Node> = vec![];
+        for arg in args.args.drain(..) {
+            match arg {
+                Node::ForwardArg(forward) if forwarding => {
+                    lowered_args.push(Node::Restarg(Restarg {
+                        name: Some(String::from(FORWARDED_ARGS)),
+                        operator_l: forward.expression_l,
+                        name_l: Some(forward.expression_l),
+                        expression_l: forward.expression_l,
+                    }));
+                    lowered_args.push(Node::Blockarg(Blockarg {
+                        name: Some(String::from(FORWARDED_BLOCK)),
+                        operator_l: forward.expression_l,
+                        name_l: Some(forward.expression_l),
+                        expression_l: forward.expression_l,
+                    }));
+                }
+                Node::Blockarg(mut blockarg) if blockarg.name.is_none() => {
+                    blockarg.name = Some(String::from(FORWARDED_BLOCK));
+                    lowered_args.push(Node::Blockarg(blockarg));
+                }
+                arg => lowered_args.push(arg),
+            }
+        }
+        args.args = lowered_args;
+        if let Some(body) = body {
+            walk_mut(&mut ForwardedArgsLowerer, body.as_mut());
+        }
+    }
+
+    /** `{ _1 + _2 }` => `{ |_1, _2| _1 + _2 }` */
+    fn lower_numblock(&mut self, node: &mut Node) {
+        if self.supports(2, 7) {
+            return;
+        }
+        let numblock = match node {
+            Node::Numblock(numblock) => numblock,
+            _ => return,
+        };
+        let args_l = numblock.begin_l;
+        let args = (1..=numblock.numargs)
+            .map(|index| {
+                Node::Arg(Arg {
+                    name: format!("_{}", index),
+                    expression_l: args_l,
+                })
+            })
+            .collect();
+        *node = Node::Block(Block {
+            call: numblock.call.clone(),
+            args: Some(Box::new(Node::Args(Args {
+                args,
+                expression_l: args_l,
+                begin_l: Some(args_l),
+                end_l: Some(args_l),
+            }))),
+            body: Some(numblock.body.clone()),
+            begin_l: numblock.begin_l,
+            end_l: numblock.end_l,
+            expression_l: numblock.expression_l,
+        });
+    }
+
+    fn check_range(&mut self, node: &Node) {
+        let (left, right, expression_l) = match node {
+            Node::Irange(range) => (&range.left, &range.right, range.expression_l),
+            Node::Erange(range) => (&range.left, &range.right, range.expression_l),
+            _ => return,
+        };
+        if right.is_none() && !self.supports(2, 6) {
+            self.error(
+                format!("endless ranges need Ruby 2.6 (target is {})", self.target),
+                expression_l,
+            );
+        } else if left.is_none() && !self.supports(2, 7) {
+            self.error(
+                format!("beginless ranges need Ruby 2.7 (target is {})", self.target),
+                expression_l,
+            );
+        }
+    }
+
+    fn lower_pattern_matching(&mut self, node: &mut Node) {
+        let required = match node {
+            Node::CaseMatch(_) => pattern_version(node).max(RubyVersion::new(2, 7)),
+            Node::MatchPattern(_) | Node::MatchPatternP(_) => {
+                pattern_version(node).max(RubyVersion::new(3, 0))
+            }
+            _ => return,
+        };
+        if self.target >= required {
+            return;
+        }
+        let expression_l = *node.expression();
+        match self.lowered_pattern_matching(node) {
+            Ok(lowered) => *node = lowered,
+            Err(message) => self.error(message, expression_l),
+        }
+    }
+
+    fn lowered_pattern_matching(&mut self, node: &Node) -> Result<Node, String> {
+        match node {
+            Node::CaseMatch(case) => {
+                let (subject, prelude) = self.subject(&case.expr);
+                let mut else_body: Node = match case.else_body.as_deref() {
+                    Some(Node::EmptyElse(empty)) => Node::Nil(Nil {
+                        expression_l: empty.expression_l,
+                    }),
+                    Some(else_body) => else_body.clone(),
+                    None => parse_expression(&self.no_match_error(&subject))?,
+                };
+                let mut conditions: Vec<(String, Node)> = vec![];
+                for in_body in case.in_bodies.iter() {
+                    let in_pattern = match in_body {
+                        Node::InPattern(in_pattern) => in_pattern,
+                        _ => continue,
+                    };
+                    let mut condition = self.condition(&in_pattern.pattern, &subject)?;
+                    match in_pattern.guard.as_deref() {
+                        Some(Node::IfGuard(guard)) => {
+                            condition =
+                                format!("{} && ({})", condition, code_to_string(&guard.cond))
+                        }
+                        Some(Node::UnlessGuard(guard)) => {
+                            condition =
+                                format!("{} && !({})", condition, code_to_string(&guard.cond))
+                        }
+                        _ => {}
+                    }
+                    let body = match in_pattern.body.as_deref() {
+                        Some(body) => body.clone(),
+                        None => Node::Nil(Nil {
+                            expression_l: in_pattern.expression_l,
+                        }),
+                    };
+                    conditions.push((condition, body));
+                }
+                if let (Some(prelude), Some(first)) = (prelude, conditions.first_mut()) {
+                    first.0 = format!("({}; {})", prelude, first.0);
+                }
+                for (condition, body) in conditions.into_iter().rev() {
+                    else_body = Node::If(If {
+                        cond: Box::new(parse_expression(&condition)?),
+                        if_true: Some(Box::new(body)),
+                        if_false: Some(Box::new(else_body)),
+                        keyword_l: case.keyword_l,
+                        begin_l: case.keyword_l,
+                        else_l: case.else_l,
+                        end_l: Some(case.end_l),
+                        expression_l: case.expression_l,
+                    });
+                }
+                return Ok(else_body);
+            }
+            Node::MatchPattern(pattern) => {
+                let (subject, prelude) = self.subject(&pattern.value);
+                let condition = self.condition(&pattern.pattern, &subject)?;
+                return parse_expression(&format!(
+                    "({}{}) || {}",
+                    prelude.map_or(String::new(), |prelude| format!("{}; ", prelude)),
+                    condition,
+                    self.no_match_error(&subject)
+                ));
+            }
+            Node::MatchPatternP(pattern) => {
+                let (subject, prelude) = self.subject(&pattern.value);
+                let condition = self.condition(&pattern.pattern, &subject)?;
+                return parse_expression(&format!(
+                    "({}{})",
+                    prelude.map_or(String::new(), |prelude| format!("{}; ", prelude)),
+                    condition
+                ));
+            }
+            _ => return Err(format!("{} is not pattern matching", node.str_type())),
+        }
+    }
+
+    /** Code reading the matched value, with the assignment of a temporary variable if needed */
+    fn subject(&mut self, value: &Node) -> (String, Option<String>) {
+        match value {
+            Node::Lvar(_) | Node::Ivar(_) | Node::Gvar(_) | Node::Cvar(_) | Node::Self_(_) => {
+                return (code_to_string(value), None)
+            }
+            _ => {
+                let temporary = self.temporary();
+                let prelude = format!("{} = {}", temporary, code_to_string(value));
+                return (temporary, Some(prelude));
+            }
+        }
+    }
+
+    fn no_match_error(&self, subject: &str) -> String {
+        if self.supports(2, 7) {
+            return format!("raise(NoMatchingPatternError, {}.inspect)", subject);
+        }
+        return format!("raise(\"no pattern matches #{{{}.inspect}}\")", subject);
+    }
+
+    fn temporary(&mut self) -> String {
+        self.temporaries += 1;
+        return format!("__pattern_{}", self.temporaries);
+    }
+
+    /** Ruby condition telling if a value matches a pattern, assigning its variables on the way */
+    fn condition(&mut self, pattern: &Node, subject: &str) -> Result<String, String> {
+        match pattern {
+            Node::MatchVar(var) if var.name == "_" => return Ok(String::from("true")),
+            Node::MatchVar(var) => return Ok(format!("({} = {}; true)", var.name, subject)),
+            Node::MatchAs(match_as) => {
+                let condition = self.condition(&match_as.value, subject)?;
+                let binding = self.condition(&match_as.as_, subject)?;
+                return Ok(format!("{} && {}", condition, binding));
+            }
+            Node::MatchAlt(alt) => {
+                let lhs = self.condition(&alt.lhs, subject)?;
+                let rhs = self.condition(&alt.rhs, subject)?;
+                return Ok(format!("({} || {})", lhs, rhs));
+            }
+            Node::MatchNilPattern(_) => return Ok(format!("{}.nil?", subject)),
+            Node::Pin(pin) => return Ok(format!("{} === {}", value_code(&pin.var), subject)),
+            Node::ConstPattern(const_pattern) => {
+                let condition = self.condition(&const_pattern.pattern, subject)?;
+                return Ok(format!(
+                    "{} === {} && {}",
+                    code_to_string(&const_pattern.const_),
+                    subject,
+                    condition
+                ));
+            }
+            Node::ArrayPattern(array) => {
+                return self.array_condition(&array.elements, false, subject)
+            }
+            Node::ArrayPatternWithTail(array) => {
+                return self.array_condition(&array.elements, true, subject)
+            }
+            Node::FindPattern(find) => return self.find_condition(&find.elements, subject),
+            Node::HashPattern(hash) => return self.hash_condition(&hash.elements, subject),
+            Node::MatchRest(_) | Node::Pair(_) => {
+                return Err(format!(
+                    "unexpected {} outside of an array or hash pattern",
+                    pattern.str_type()
+                ))
+            }
+            value => return Ok(format!("{} === {}", value_code(value), subject)),
+        }
+    }
+
+    /**
+     * Conditions assigning the deconstructed subject to a variable. Arrays and hashes only respond
+     * to `deconstruct` and `deconstruct_keys` since Ruby 2.7, older targets use them as is.
+     */
+    fn deconstruction(
+        &self,
+        variable: &str,
+        subject: &str,
+        class: &str,
+        call: &str,
+    ) -> Vec<String> {
+        let method = call.trim_end_matches("(nil)");
+        if self.supports(2, 7) {
+            return vec![
+                format!("{}.respond_to?(:{})", subject, method),
+                format!("({} = {}.{}; true)", variable, subject, call),
+            ];
+        }
+        return vec![
+            format!(
+                "({} === {} || {}.respond_to?(:{}))",
+                class, subject, subject, method
+            ),
+            format!(
+                "({} = {} === {} ? {} : {}.{}; true)",
+                variable, class, subject, subject, subject, call
+            ),
+        ];
+    }
+
+    /** `[a, *rest, b]`, `[a, ]` having an anonymous rest at the end */
+    fn array_condition(
+        &mut self,
+        elements: &[Node],
+        with_tail: bool,
+        subject: &str,
+    ) -> Result<String, String> {
+        let array = self.temporary();
+        let mut conditions = self.deconstruction(&array, subject, "Array", "deconstruct");
+        let rest_index = elements
+            .iter()
+            .position(|element| matches!(element, Node::MatchRest(_)));
+        let (before, rest, after) = match rest_index {
+            Some(index) => (
+                &elements[..index],
+                Some(&elements[index]),
+                &elements[(index + 1)..],
+            ),
+            None => (elements, None, &elements[elements.len()..]),
+        };
+        if rest.is_some() || with_tail {
+            conditions.push(format!(
+                "{}.length >= {}",
+                array,
+                before.len() + after.len()
+            ));
+        } else {
+            conditions.push(format!("{}.length == {}", array, before.len()));
+        }
+        for (index, element) in before.iter().enumerate() {
+            conditions.push(self.condition(element, &format!("{}[{}]", array, index))?);
+        }
+        if let Some(Node::MatchRest(rest)) = rest {
+            if let Some(name) = &rest.name {
+                let value = format!(
+                    "{}[{}...({}.length - {})]",
+                    array,
+                    before.len(),
+                    array,
+                    after.len()
+                );
+                conditions.push(self.condition(name, &value)?);
+            }
+        }
+        for (index, element) in after.iter().enumerate() {
+            let value = format!("{}[-{}]", array, after.len() - index);
+            conditions.push(self.condition(element, &value)?);
+        }
+        return Ok(all_of(conditions));
+    }
+
+    /** `[*, x, *]`: the variables are declared first so the block assigns them */
+    fn find_condition(&mut self, elements: &[Node], subject: &str) -> Result<String, String> {
+        let (pre, middle, post) = match elements {
+            [pre @ Node::MatchRest(_), middle @ .., post @ Node::MatchRest(_)]
+                if !middle.is_empty() =>
+            {
+                (pre, middle, post)
+            }
+            _ => return Err(String::from("malformed find pattern")),
+        };
+        let array = self.temporary();
+        let index = self.temporary();
+        let mut conditions = self.deconstruction(&array, subject, "Array", "deconstruct");
+        let mut variables: Vec<String> = vec![];
+        for element in elements.iter() {
+            variables.extend(pattern_variables(element));
+        }
+        if !variables.is_empty() {
+            conditions.push(format!("({} = nil; true)", variables.join(" = ")));
+        }
+        let mut window: Vec<String> = vec![];
+        for (offset, element) in middle.iter().enumerate() {
+            let value = format!("{}[{} + {}]", array, index, offset);
+            window.push(self.condition(element, &value)?);
+        }
+        if let Node::MatchRest(rest) = pre {
+            if let Some(name) = &rest.name {
+                window.push(self.condition(name, &format!("{}[0...{}]", array, index))?);
+            }
+        }
+        if let Node::MatchRest(rest) = post {
+            if let Some(name) = &rest.name {
+                let value = format!("{}[({} + {})..-1]", array, index, middle.len());
+                window.push(self.condition(name, &value)?);
+            }
+        }
+        conditions.push(format!(
+            "(0..({}.length - {})).any? {{ |{}| {} }}",
+            array,
+            middle.len(),
+            index,
+            all_of(window)
+        ));
+        return Ok(all_of(conditions));
+    }
+
+    /** `{ name: String => name, age:, **rest }` */
+    fn hash_condition(&mut self, elements: &[Node], subject: &str) -> Result<String, String> {
+        let hash = self.temporary();
+        let mut conditions = self.deconstruction(&hash, subject, "Hash", "deconstruct_keys(nil)");
+        if elements.is_empty() {
+            conditions.push(format!("{}.empty?", hash));
+        }
+        let mut keys: Vec<String> = vec![];
+        for element in elements.iter() {
+            match element {
+                Node::Pair(pair) => {
+                    let key = match pair.key.as_ref() {
+                        Node::Sym(sym) => symbol_code(&sym.name.to_string_lossy()),
+                        key => {
+                            return Err(format!("unsupported hash pattern key {}", key.str_type()))
+                        }
+                    };
+                    conditions.push(format!("{}.key?({})", hash, key));
+                    conditions.push(self.condition(&pair.value, &format!("{}[{}]", hash, key))?);
+                    keys.push(key);
+                }
+                Node::MatchVar(var) => {
+                    let key = symbol_code(&var.name);
+                    conditions.push(format!("{}.key?({})", hash, key));
+                    conditions.push(self.condition(element, &format!("{}[{}]", hash, key))?);
+                    keys.push(key);
+                }
+                Node::MatchRest(rest) => {
+                    if let Some(name) = &rest.name {
+                        let key = self.temporary();
+                        let value = format!(
+                            "{}.reject {{ |{}, _| [{}].include?({}) }}",
+                            hash,
+                            key,
+                            keys.join(", "),
+                            key
+                        );
+                        conditions.push(self.condition(name, &value)?);
+                    }
+                }
+                Node::MatchNilPattern(_) => {
+                    conditions.push(format!("{}.length == {}", hash, keys.len()));
+                }
+                element => {
+                    return Err(format!(
+                        "unsupported hash pattern element {}",
+                        element.str_type()
+                    ))
+                }
+            }
+        }
+        return Ok(all_of(conditions));
+    }
+}
+
+impl VisitorMut for Lowerer {
+    fn leave(&mut self, node: &mut Node, _path: &[PathStep]) {
+        self.lower_endless_def(node);
+        self.lower_forwarding(node);
+        self.lower_numblock(node);
+        self.check_range(node);
+        self.lower_pattern_matching(node);
+    }
+}
+
+/** Pass `*args, &block` where a method body forwards `...` or `&` */
+struct ForwardedArgsLowerer;
+
+impl VisitorMut for ForwardedArgsLowerer {
+    fn enter(&mut self, node: &mut Node, _path: &[PathStep]) -> bool {
+        let args = match node {
+            Node::Send(send) => &mut send.args,
+            Node::CSend(send) => &mut send.args,
+            Node::Super(node) => &mut node.args,
+            // Nested methods have their own arguments
+            Node::Def(_) | Node::Defs(_) => return false,
+            _ => return true,
+        };
+        let mut lowered_args: Vec<Node> = vec![];
+        for arg in args.drain(..) {
+            match arg {
+                Node::ForwardedArgs(forwarded) => {
+                    let expression_l = forwarded.expression_l;
+                    lowered_args.push(Node::Splat(Splat {
+                        value: Some(Box::new(local_variable(FORWARDED_ARGS, expression_l))),
+                        operator_l: expression_l,
+                        expression_l,
+                    }));
+                    lowered_args.push(Node::BlockPass(BlockPass {
+                        value: Some(Box::new(local_variable(FORWARDED_BLOCK, expression_l))),
+                        operator_l: expression_l,
+                        expression_l,
+                    }));
+                }
+                Node::BlockPass(mut pass) if pass.value.is_none() => {
+                    pass.value = Some(Box::new(local_variable(FORWARDED_BLOCK, pass.expression_l)));
+                    lowered_args.push(Node::BlockPass(pass));
+                }
+                arg => lowered_args.push(arg),
+            }
+        }
+        *args = lowered_args;
+        return true;
+    }
+}
+
+fn local_variable(name: &str, expression_l: Loc) -> Node {
+    return Node::Lvar(Lvar {
+        name: String::from(name),
+        expression_l,
+    });
+}
+
+/** Oldest version reading the patterns of a pattern matching node */
+fn pattern_version(node: &Node) -> RubyVersion {
+    let mut version = RubyVersion::new(2, 7);
+    for (_, child) in children_with_steps(node) {
+        let child_version = match child {
+            Node::FindPattern(_) => RubyVersion::new(3, 0),
+            // Pinned expressions and instance variables came with 3.1
+            Node::Pin(pin) if !matches!(pin.var.as_ref(), Node::Lvar(_)) => RubyVersion::new(3, 1),
+            _ => pattern_version(child),
+        };
+        version = version.max(child_version);
+    }
+    return version;
+}
+
+/** Variables assigned by a pattern */
+fn pattern_variables(pattern: &Node) -> Vec<String> {
+    let mut collector = PatternVariables { names: vec![] };
+    walk(&mut collector, pattern);
+    return collector.names;
+}
+
+struct PatternVariables {
+    names: Vec<String>,
+}
+
+impl<'a> Visitor<'a> for PatternVariables {
+    fn enter(&mut self, node: &'a Node, _path: &NodePath<'a>) -> bool {
+        if let Node::MatchVar(var) = node {
+            if var.name != "_" && !self.names.contains(&var.name) {
+                self.names.push(var.name.clone());
+            }
+        }
+        return true;
+    }
+}
+
+/** Join conditions with `&&`, leaving out the ones that always match */
+fn all_of(conditions: Vec<String>) -> String {
+    let conditions: Vec<String> = conditions
+        .into_iter()
+        .filter(|condition| condition != "true")
+        .collect();
+    if conditions.is_empty() {
+        return String::from("true");
+    }
+    return conditions.join(" && ");
+}
+
+/** Code of a value compared with `===`, parenthesized unless it is a single term */
+fn value_code(node: &Node) -> String {
+    let code = code_to_string(node);
+    if is_primary(node) {
+        return code;
+    }
+    return format!("({})", code);
+}
+
+fn symbol_code(name: &str) -> String {
+    if name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+    {
+        return format!(":{}", name);
+    }
+    return format!(":{:?}", name);
+}
+
+/** Parse code generated by the lowering */
+fn parse_expression(code: &str) -> Result<Node, String> {
+    let options = ParserOptions {
+        buffer_name: String::from("(downlevel)"),
+        record_tokens: false,
+        ..Default::default()
+    };
+    let result = Parser::new(code, options).do_parse();
+    if result
+        .diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.is_error())
+    {
+        return Err(format!("failed to lower into `{}`", code));
+    }
+    match result.ast {
+        Some(node) => return Ok(*node),
+        None => return Err(format!("failed to lower into `{}`", code)),
+    }
+}
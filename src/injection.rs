@@ -0,0 +1,361 @@
+use lib_ruby_parser::{source::DecodedInput, Loc, Node, Parser, ParserOptions};
+
+use crate::{
+    builder as b, code_writer::code_to_string, rename::Replacement,
+    search_replace::line_indentation, tests::constant_resolver::written_path,
+    visibility_filter::Visibility,
+};
+
+/** Where the injected code goes in the body of the namespace */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InjectPosition {
+    Top,
+    Bottom,
+    /** After a member: `name` (method), `self.name` (singleton method), `NAME` (constant or namespace) */
+    After(String),
+}
+
+/** Code to add to a class or module */
+pub struct Injection {
+    /** Path of the namespace (`Foo::Bar`), its missing parts are created as modules, empty for the top level */
+    pub target: String,
+    pub position: InjectPosition,
+    /** Visibility the code is written under, the one in effect at the position is restored after it */
+    pub visibility: Visibility,
+    pub statements: Vec<Node>,
+}
+
+impl Injection {
+    /** Injection of a snippet of Ruby code */
+    pub fn from_source(
+        target: &str,
+        position: InjectPosition,
+        visibility: Visibility,
+        source: &str,
+    ) -> Result<Self, String> {
+        let statements = parse_snippet(source)?;
+        if statements.is_empty() {
+            return Err(String::from("the snippet has no code"));
+        }
+        return Ok(Injection {
+            target: String::from(target),
+            position,
+            visibility,
+            statements,
+        });
+    }
+}
+
+/** Statements of the top level or of the body of a class or module */
+struct Body<'a> {
+    statements: Vec<&'a Node>,
+    namespace: Option<&'a Node>,
+}
+
+/**
+ * Insertion of the statements of an injection in the source of the first class or module matching
+ * the target path, the rest of the file being left as written. Compact definitions (`class Foo::Bar`)
+ * are followed and the missing namespaces are created at the bottom of the closest existing one.
+ */
+pub fn inject(
+    node: &Node,
+    injection: &Injection,
+    input: &DecodedInput,
+) -> Result<Replacement, String> {
+    let path: Vec<String> = injection
+        .target
+        .split("::")
+        .filter(|segment| !segment.is_empty())
+        .map(String::from)
+        .collect();
+    let body = Body {
+        statements: statements_of(node)?,
+        namespace: None,
+    };
+    return inject_into(body, &path, injection, input);
+}
+
+fn inject_into(
+    body: Body<'_>,
+    path: &[String],
+    injection: &Injection,
+    input: &DecodedInput,
+) -> Result<Replacement, String> {
+    if path.is_empty() {
+        return insert(body, injection, input);
+    }
+    for statement in body.statements.iter() {
+        let (name, namespace_body) = match statement {
+            Node::Class(class) => (written_path(&class.name), &class.body),
+            Node::Module(module) => (written_path(&module.name), &module.body),
+            _ => continue,
+        };
+        let segments: Vec<String> = name.split("::").map(String::from).collect();
+        if segments.len() <= path.len() && segments[..] == path[..segments.len()] {
+            let namespace_body = Body {
+                statements: match namespace_body.as_deref() {
+                    Some(namespace_body) => statements_of(namespace_body)?,
+                    None => vec![],
+                },
+                namespace: Some(statement),
+            };
+            return inject_into(namespace_body, &path[segments.len()..], injection, input);
+        }
+    }
+    // A new module starts public
+    let mut created = b::begin(with_visibility(
+        injection,
+        Visibility::Public,
+        injection.visibility,
+    ));
+    for name in path.iter().rev() {
+        created = b::module(name, Some(created));
+    }
+    let index = body.statements.len();
+    return Ok(splice(&body, index, vec![created], input));
+}
+
+fn insert(
+    body: Body<'_>,
+    injection: &Injection,
+    input: &DecodedInput,
+) -> Result<Replacement, String> {
+    let statements = &body.statements;
+    let index = match &injection.position {
+        InjectPosition::Top => 0,
+        InjectPosition::Bottom => statements.len(),
+        InjectPosition::After(name) => {
+            match statements
+                .iter()
+                .position(|statement| member_name(statement).as_ref() == Some(name))
+            {
+                Some(index) => index + 1,
+                None => return Err(format!("{} has no member named {}", injection.target, name)),
+            }
+        }
+    };
+    let current_visibility = visibility_at(&statements[..index]);
+    // Nothing to restore when the next statement sets its own visibility
+    let restored_visibility = match statements.get(index) {
+        Some(next) if modifier_visibility(next).is_none() => current_visibility,
+        _ => injection.visibility,
+    };
+    let inserted = with_visibility(injection, current_visibility, restored_visibility);
+    return Ok(splice(&body, index, inserted, input));
+}
+
+/** Statements of the injection, preceded and followed by the modifiers setting their visibility */
+fn with_visibility(
+    injection: &Injection,
+    current_visibility: Visibility,
+    restored_visibility: Visibility,
+) -> Vec<Node> {
+    let mut inserted: Vec<Node> = vec![];
+    if injection.visibility != current_visibility {
+        inserted.push(b::send(None, injection.visibility.as_str(), vec![]));
+    }
+    inserted.extend(injection.statements.iter().cloned());
+    if injection.visibility != restored_visibility {
+        inserted.push(b::send(None, restored_visibility.as_str(), vec![]));
+    }
+    return inserted;
+}
+
+/**
+ * Text inserting statements before the statement at an index of a body, on their own lines and
+ * indented like the other statements of the body.
+ */
+fn splice(body: &Body<'_>, index: usize, inserted: Vec<Node>, input: &DecodedInput) -> Replacement {
+    let bytes = &input.bytes;
+    let namespace_indentation = body.namespace.map_or("", |namespace| {
+        line_indentation(input, namespace.expression().begin)
+    });
+    let indentation = match body.statements.first() {
+        Some(statement) => String::from(line_indentation(input, statement.expression().begin)),
+        None if body.namespace.is_some() => format!("{}  ", namespace_indentation),
+        None => String::new(),
+    };
+    let code = code_to_string(&b::begin(inserted))
+        .trim_end()
+        .lines()
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", indentation, line)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    let (after, end_l) = match body.namespace {
+        Some(Node::Class(class)) => (
+            class
+                .superclass
+                .as_deref()
+                .unwrap_or(&class.name)
+                .expression()
+                .end,
+            Some(class.end_l),
+        ),
+        Some(Node::Module(module)) => (module.name.expression().end, Some(module.end_l)),
+        _ => (0, None),
+    };
+    let after = match index {
+        0 => after,
+        _ => body.statements[index - 1].expression().end,
+    };
+    let before = match body.statements.get(index) {
+        Some(statement) => Some(statement.expression().begin),
+        None => end_l.map(|end_l| end_l.begin),
+    };
+    if index == 0 && body.namespace.is_none() {
+        // Above the first statement and the comments documenting it, below the magic comments
+        let mut begin = line_start(bytes, before.unwrap_or(0));
+        while begin > 0 {
+            let previous_line = line_start(bytes, begin - 1);
+            if !String::from_utf8_lossy(&bytes[previous_line..begin])
+                .trim_start()
+                .starts_with('#')
+            {
+                break;
+            }
+            begin = previous_line;
+        }
+        return Replacement {
+            range: Loc { begin, end: begin },
+            text: format!("{}\n", code),
+        };
+    }
+    let line_end = line_end(bytes, after);
+    match before {
+        // `class Foo; end` or `a; b`, the code between them is replaced by a line break
+        Some(before) if before <= line_end => {
+            let next_indentation = if index < body.statements.len() {
+                indentation.as_str()
+            } else {
+                namespace_indentation
+            };
+            return Replacement {
+                range: Loc {
+                    begin: after,
+                    end: before,
+                },
+                text: format!("\n{}\n{}", code, next_indentation),
+            };
+        }
+        _ => {
+            return Replacement {
+                range: Loc {
+                    begin: line_end,
+                    end: line_end,
+                },
+                text: format!("\n{}", code),
+            }
+        }
+    }
+}
+
+/** Start of the line holding a position */
+fn line_start(bytes: &[u8], position: usize) -> usize {
+    return bytes[..position]
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+}
+
+/** End of the line holding a position, before its line break */
+fn line_end(bytes: &[u8], position: usize) -> usize {
+    let end = bytes[position..]
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .map_or(bytes.len(), |newline| position + newline);
+    if end > position && bytes[end - 1] == b'\r' {
+        return end - 1;
+    }
+    return end;
+}
+
+/** Statements of a body, a single statement being a list of one */
+fn statements_of(node: &Node) -> Result<Vec<&Node>, String> {
+    match node {
+        Node::Rescue(_) | Node::Ensure(_) => {
+            return Err(String::from(
+                "cannot inject into a body having rescue or ensure clauses",
+            ))
+        }
+        Node::Begin(begin) if begin.begin_l.is_none() => {
+            return Ok(begin.statements.iter().collect())
+        }
+        _ => return Ok(vec![node]),
+    }
+}
+
+/** Visibility of the methods defined after some statements */
+fn visibility_at(statements: &[&Node]) -> Visibility {
+    return statements
+        .iter()
+        .filter_map(|statement| modifier_visibility(statement))
+        .last()
+        .unwrap_or(Visibility::Public);
+}
+
+/** Visibility set by a bare `private`, `protected` or `public` */
+fn modifier_visibility(node: &Node) -> Option<Visibility> {
+    match node {
+        Node::Send(send) if send.recv.is_none() && send.args.is_empty() => {
+            match send.method_name.as_str() {
+                "private" | "protected" | "public" => {
+                    return Visibility::from_method_name(&send.method_name)
+                }
+                _ => return None,
+            }
+        }
+        _ => return None,
+    }
+}
+
+fn member_name(node: &Node) -> Option<String> {
+    match node {
+        Node::Def(def) => return Some(def.name.clone()),
+        Node::Defs(defs) if matches!(defs.definee.as_ref(), Node::Self_(_)) => {
+            return Some(format!("self.{}", defs.name))
+        }
+        Node::Casgn(asgn) if asgn.scope.is_none() => return Some(asgn.name.clone()),
+        Node::Class(class) => return Some(written_path(&class.name)),
+        Node::Module(module) => return Some(written_path(&module.name)),
+        // `private def name`
+        Node::Send(send) if send.recv.is_none() && send.args.len() == 1 => {
+            match Visibility::from_method_name(&send.method_name) {
+                Some(_) => return member_name(&send.args[0]),
+                None => return None,
+            }
+        }
+        _ => return None,
+    }
+}
+
+/** Parse Ruby code into the list of its statements */
+pub fn parse_snippet(source: &str) -> Result<Vec<Node>, String> {
+    let options = ParserOptions {
+        buffer_name: String::from("(snippet)"),
+        record_tokens: false,
+        ..Default::default()
+    };
+    let result = Parser::new(source, options).do_parse();
+    if let Some(error) = result
+        .diagnostics
+        .iter()
+        .find(|diagnostic| diagnostic.is_error())
+    {
+        return Err(format!(
+            "invalid snippet `{}`: {}",
+            source.trim(),
+            error.render_message()
+        ));
+    }
+    match result.ast.map(|node| *node) {
+        Some(Node::Begin(begin)) if begin.begin_l.is_none() => return Ok(begin.statements),
+        Some(node) => return Ok(vec![node]),
+        None => return Ok(vec![]),
+    }
+}
@@ -0,0 +1,112 @@
+use std::io::{BufWriter, Write};
+
+/** Minimal JSON document used by the machine readable outputs */
+#[derive(Debug, Clone)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    pub fn string<S: Into<String>>(value: S) -> Self {
+        return JsonValue::String(value.into());
+    }
+
+    pub fn number<N: ToString>(value: N) -> Self {
+        return JsonValue::Number(value.to_string());
+    }
+
+    pub fn optional_string(value: &Option<String>) -> Self {
+        match value {
+            Some(value) => JsonValue::String(value.clone()),
+            None => JsonValue::Null,
+        }
+    }
+
+    pub fn object(entries: Vec<(&str, JsonValue)>) -> Self {
+        return JsonValue::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| (String::from(key), value))
+                .collect(),
+        );
+    }
+
+    pub fn write<W: Write>(
+        &self,
+        writer: &mut BufWriter<W>,
+        indent: u32,
+    ) -> Result<(), std::io::Error> {
+        match self {
+            JsonValue::Null => writer.write_all(b"null")?,
+            JsonValue::Bool(value) => writer.write_all(if *value { b"true" } else { b"false" })?,
+            JsonValue::Number(value) => writer.write_all(value.as_bytes())?,
+            JsonValue::String(value) => write_json_string(writer, value)?,
+            JsonValue::Array(values) => {
+                if values.is_empty() {
+                    writer.write_all(b"[]")?;
+                    return Ok(());
+                }
+                writer.write_all(b"[\n")?;
+                for (i, value) in values.iter().enumerate() {
+                    write_json_indent(writer, indent + 1)?;
+                    value.write(writer, indent + 1)?;
+                    writer.write_all(if i + 1 < values.len() { b",\n" } else { b"\n" })?;
+                }
+                write_json_indent(writer, indent)?;
+                writer.write_all(b"]")?;
+            }
+            JsonValue::Object(entries) => {
+                if entries.is_empty() {
+                    writer.write_all(b"{}")?;
+                    return Ok(());
+                }
+                writer.write_all(b"{\n")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    write_json_indent(writer, indent + 1)?;
+                    write_json_string(writer, key)?;
+                    writer.write_all(b": ")?;
+                    value.write(writer, indent + 1)?;
+                    writer.write_all(if i + 1 < entries.len() { b",\n" } else { b"\n" })?;
+                }
+                write_json_indent(writer, indent)?;
+                writer.write_all(b"}")?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+fn write_json_indent<W: Write>(
+    writer: &mut BufWriter<W>,
+    indent: u32,
+) -> Result<(), std::io::Error> {
+    for _ in 0..indent {
+        writer.write_all(b"  ")?;
+    }
+    return Ok(());
+}
+
+fn write_json_string<W: Write>(
+    writer: &mut BufWriter<W>,
+    value: &str,
+) -> Result<(), std::io::Error> {
+    writer.write_all(b"\"")?;
+    for character in value.chars() {
+        match character {
+            '"' => writer.write_all(b"\\\"")?,
+            '\\' => writer.write_all(b"\\\\")?,
+            '\n' => writer.write_all(b"\\n")?,
+            '\r' => writer.write_all(b"\\r")?,
+            '\t' => writer.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => writer.write_fmt(format_args!("\\u{:04x}", c as u32))?,
+            c => writer.write_fmt(format_args!("{}", c))?,
+        }
+    }
+    writer.write_all(b"\"")?;
+    return Ok(());
+}
@@ -4,9 +4,9 @@ macro_rules! write_body {
         if is_node_begin_block(&$body) {
             write_code(&$body, $writer, &$context)?;
         } else {
-            write_indent($writer, $context.indent)?;
+            write_indent($writer, &$context)?;
             write_code(&$body, $writer, &$context)?;
-            $writer.write(b"\n")?;
+            write_newline($writer, &$context)?;
         }
     };
 }
@@ -52,11 +52,10 @@ macro_rules! write_def_name_arg_and_body {
         if $def.assignment_l.is_some() {
             $writer.write(b" = ")?;
             if let Some(body) = &$def.body {
-                $writer.write(b"\n")?;
                 write_code(&body, $writer, &$context)?;
             }
         } else {
-            $writer.write(b"\n")?;
+            write_newline($writer, &$context)?;
             if let Some(body) = &$def.body {
                 if !method_body_excluded(&$context) {
                     match body.as_ref() {
@@ -69,7 +68,7 @@ macro_rules! write_def_name_arg_and_body {
                     }
                 }
             }
-            write_indent($writer, $context.indent)?;
+            write_indent($writer, &$context)?;
             $writer.write(b"end")?;
         }
     };
@@ -80,7 +79,7 @@ macro_rules! write_assign {
     ($asgn: ident, $writer: ident, $context: expr) => {
         $writer.write($asgn.name.as_bytes())?;
         if let Some(value) = &$asgn.value {
-            $writer.write(b" = ")?;
+            write_spaced($writer, &$context, b" = ")?;
             write_code(&value, $writer, &$context)?;
         }
     };
@@ -92,9 +91,9 @@ macro_rules! write_exe {
         $writer.write($keyword_with_bracket)?;
         if let Some(body) = &$exe.body {
             if is_node_begin_block(&body) {
-                $writer.write(b"\n")?;
+                write_newline($writer, &$context)?;
                 write_code(&body, $writer, &$context.indent())?;
-                $writer.write(b"\n")?;
+                write_newline($writer, &$context)?;
             } else {
                 write_code(&body, $writer, &$context.indent())?;
             }
@@ -110,10 +109,10 @@ macro_rules! write_until_while {
             $writer.write($keyword_with_space)?;
             write_code(&$control.cond, $writer, &$context)?;
             if let Some(body) = &$control.body {
-                $writer.write(b"\n")?;
+                write_newline($writer, &$context)?;
                 write_body!(body, $writer, $context.indent());
             }
-            write_indent($writer, $context.indent)?;
+            write_indent($writer, &$context)?;
             $writer.write(b"end")?;
         } else {
             if let Some(body) = &$control.body {
@@ -145,11 +144,11 @@ macro_rules! write_block_control_operator {
 #[macro_export]
 macro_rules! write_body_with_end {
     ($node: ident, $writer: ident, $context: expr) => {
-        $writer.write(b"\n")?;
+        write_newline($writer, &$context)?;
         if let Some(body) = &$node.body {
             write_body!(body, $writer, $context.indent());
         }
-        write_indent($writer, $context.indent)?;
+        write_indent($writer, &$context)?;
         $writer.write(b"end")?;
     };
 }
@@ -1,104 +1,764 @@
 use std::{
     env, fs,
     io::{BufWriter, Write},
+    path::{Path, PathBuf},
 };
 
-use code_writer::CodeWriterContext;
-use combine_modules::combine_modules;
-use lib_ruby_parser::{nodes::Int, Loc, Node, Parser, ParserOptions};
+use api_reference::{write_api_reference, ReferenceFormat};
+use autocorrect::{autocorrect, corrected_replacements, Autocorrection};
+use code_writer::{code_to_string, CodeWriterContext};
+use combine_modules::{combine_modules, KeptSeparate};
+use compat_checker::{check_compat, CompatTarget};
+use constant_exporter::{write_constants, ConstantFormat};
+use doc_coverage::{CoverageReport, FileCoverage};
+use documentation_context::{DocumentationContext, DocumentationMergeStrategy};
+use documentation_tree::{build_documentation_tree, documentation_tree_to_json};
+use downlevel::{downlevel, RubyVersion};
+use injection::{inject, InjectPosition, Injection};
+use lib_ruby_parser::{
+    nodes::Send,
+    source::{Comment, DecodedInput},
+    Node, Parser, ParserOptions, ParserResult,
+};
+use minifier::minify;
+use rbs_writer::write_rbs;
+use rename::{rename, RenameTarget};
+use scope_analysis::ScopeTree;
+use search_replace::{rules_from_file, search_replace, FileRewrite, Rule};
 use tests::{
+    constant_folding::{fold_constants, ConstantFoldingOptions},
+    constant_resolver::{offset_of_position, ConstantResolver},
     edit_methods::edit_methods,
-    explore_constants::{explore_constants, make_constant_hash_map},
+    explore_constants::{explore_constants, find_constant_value, make_constant_hash_map},
     insert_test_in_module::mutate_module,
+    send_rules::SendRules,
 };
+use visibility_filter::{filter_by_visibility, Visibility, VisibilityFilter};
 
 use crate::code_writer::write_code;
+mod api_reference;
+mod autocorrect;
+mod builder;
 mod code_writer;
 mod combine_modules;
+mod compat_checker;
+mod constant_exporter;
+mod doc_coverage;
 mod documentation_context;
+mod documentation_tree;
+mod downlevel;
+mod injection;
+mod json;
 mod macros;
+mod minifier;
+mod rbs_writer;
+mod rename;
+mod scope_analysis;
+mod search_replace;
 mod tests;
+mod visibility_filter;
+mod visitor;
+mod yard;
+
+const INSTRUCTIONS: &str = "write, edit_method, explore_constants, resolve_const, fold_constants, scopes, minify, rename, search_replace, autocorrect, check_compat, inject, combine_modules, documentation, documentation_with_method_body, rbs, api_reference, doc_coverage";
 
 fn main() -> Result<(), std::io::Error> {
     let ruby_filename = env::args().nth(1).expect("Ruby Filename is expected");
     let instruction = env::args()
         .nth(2)
-        .expect("instruction is expected (write, edit_method, explore_constants, combine_modules, documentation, documentation_with_method_body)");
-    let ruby_file_content = fs::read_to_string(ruby_filename.clone())
-        .expect(format!("Failed to read ruby file: {}", ruby_filename).as_str());
-    let options = ParserOptions {
-        record_tokens: false,
-        ..Default::default()
-    };
-    let parser = Parser::new(ruby_file_content, options);
-    let result = parser.do_parse();
+        .unwrap_or_else(|| panic!("instruction is expected ({})", INSTRUCTIONS));
+    if instruction == "doc_coverage" {
+        return doc_coverage(Path::new(&ruby_filename));
+    }
+    if instruction == "rename" {
+        return rename_in_project(Path::new(&ruby_filename));
+    }
+    if instruction == "search_replace" {
+        return search_replace_in_project(Path::new(&ruby_filename));
+    }
+    if instruction == "autocorrect" {
+        return autocorrect_project(Path::new(&ruby_filename));
+    }
+    if instruction == "check_compat" {
+        return check_compat_project(Path::new(&ruby_filename));
+    }
+    let result = parse_ruby_file(Path::new(&ruby_filename));
     let mut node = result.ast.expect("Failed to read AST from ParserResult");
 
     match instruction.as_str() {
         "write" => {
             let mut writer = BufWriter::new(std::io::stdout());
-            mutate_module(&mut node);
+            match option_value("--target-ruby") {
+                // The lowered output must be the program as written
+                Some(version) => {
+                    let target = RubyVersion::parse(&version)
+                        .expect("--target-ruby expects a version such as 2.5");
+                    if let Err(errors) = downlevel(&mut node, target) {
+                        for error in errors.iter() {
+                            eprintln!("write: {}", error.describe(&ruby_filename, &result.input));
+                        }
+                        std::process::exit(1);
+                    }
+                }
+                None => mutate_module(&mut node),
+            }
             write_code(node.as_ref(), &mut writer, &CodeWriterContext::new())?;
             writer.flush()?;
         }
         "explore_constants" => {
             let mut constants = make_constant_hash_map();
-            explore_constants(&mut constants, &node, &|send| {
-                if send.recv.is_none() {
-                    if send.method_name.eq("gen") && send.args.len() == 2 {
-                        if let Node::Int(arg1) = &send.args[0] {
-                            if let Node::Int(arg2) = &send.args[1] {
-                                return Some(Box::new(Node::Int(Int {
-                                    value: (384
-                                        + (arg1.value.parse::<i32>().unwrap())
-                                        + ((arg2.value.parse::<i32>().unwrap()) * 8))
-                                        .to_string(),
-                                    operator_l: None,
-                                    expression_l: Loc { begin: 0, end: 0 },
-                                })));
-                            }
+            let send_rules = send_rules();
+            for constant in
+                explore_constants(&mut constants, &node, &|send| send_rules.handle(send))
+            {
+                eprintln!("explore_constants: {}", constant.describe(&result.input));
+            }
+            match option_value("--format") {
+                Some(format) => {
+                    let format = ConstantFormat::from_name(&format)
+                        .expect("--format expects json, rust, c or ruby");
+                    let mut writer = BufWriter::new(std::io::stdout());
+                    write_constants(&constants, format, &mut writer)?;
+                    writer.flush()?;
+                }
+                None => println!("{:?}", constants),
+            }
+        }
+        "fold_constants" => {
+            let mut constants = make_constant_hash_map();
+            let send_rules = send_rules();
+            let send_handler = |send: &Send| send_rules.handle(send);
+            explore_constants(&mut constants, &node, &send_handler);
+            let mut options = ConstantFoldingOptions::new();
+            options.keep_expression = has_option("--keep-expression");
+            options.inline = has_option("--inline");
+            options.only = option_value("--only").map(|paths| split_paths(&paths));
+            options.except = option_value("--except")
+                .map(|paths| split_paths(&paths))
+                .unwrap_or_default();
+            let folded = fold_constants(&mut node, &constants, &send_handler, &options);
+            for path in folded.folded.iter() {
+                eprintln!("fold_constants: folded {}", path);
+            }
+            if options.inline {
+                eprintln!(
+                    "fold_constants: inlined {} constant references",
+                    folded.inlined
+                );
+            }
+            let mut writer = BufWriter::new(std::io::stdout());
+            write_code(
+                node.as_ref(),
+                &mut writer,
+                &CodeWriterContext::new_with_trailing_comments(folded.trailing_comments),
+            )?;
+            writer.flush()?;
+        }
+        "resolve_const" => {
+            let mut constants = make_constant_hash_map();
+            let send_rules = send_rules();
+            explore_constants(&mut constants, &node, &|send| send_rules.handle(send));
+            let resolver = ConstantResolver::new(&node);
+            let mut writer = BufWriter::new(std::io::stdout());
+            let position = env::args().nth(3).filter(|arg| !arg.starts_with("--"));
+            let references = match position {
+                Some(position) => {
+                    let offset = offset_of_position(&result.input, &position)
+                        .expect("resolve_const expects a LINE:COL position");
+                    match resolver.reference_at(offset) {
+                        Some(reference) => vec![reference],
+                        None => {
+                            eprintln!("resolve_const: there is no constant at {}", position);
+                            std::process::exit(1);
                         }
                     }
                 }
-                return None;
-            });
-            println!("{:?}", constants);
+                None => resolver.references.iter().collect(),
+            };
+            for reference in references {
+                let (line, column) = result
+                    .input
+                    .line_col_for_pos(reference.expression_l.begin)
+                    .unwrap_or((0, 0));
+                let path = match resolver.resolve(reference) {
+                    Some(path) => path,
+                    None => {
+                        eprintln!(
+                            "resolve_const: {} at {}:{} cannot be resolved",
+                            code_to_string(&reference.node),
+                            line + 1,
+                            column + 1
+                        );
+                        continue;
+                    }
+                };
+                writeln!(writer, "{}:{}: {}", line + 1, column + 1, path)?;
+                for definition in resolver.definitions_of(&path) {
+                    let (line, column) = result
+                        .input
+                        .line_col_for_pos(definition.name_l.begin)
+                        .unwrap_or((0, 0));
+                    writeln!(
+                        writer,
+                        "  {} defined at {}:{}:{}",
+                        definition.kind.as_str(),
+                        ruby_filename,
+                        line + 1,
+                        column + 1
+                    )?;
+                }
+                if let Some(value) = find_constant_value(&constants, &path) {
+                    writeln!(writer, "  value: {}", code_to_string(value))?;
+                }
+            }
+            writer.flush()?;
+        }
+        "scopes" => {
+            let tree = ScopeTree::new(&node);
+            let position =
+                env::args()
+                    .nth(3)
+                    .filter(|arg| !arg.starts_with("--"))
+                    .map(|position| {
+                        offset_of_position(&result.input, &position)
+                            .expect("scopes expects a LINE:COL position")
+                    });
+            let mut writer = BufWriter::new(std::io::stdout());
+            tree.write(&result.input, position, &mut writer)?;
+            writer.flush()?;
         }
         "edit_method" => {
             let mut writer = BufWriter::new(std::io::stdout());
-            edit_methods(&mut node);
+            for unsafe_construct in edit_methods(&mut node) {
+                let line = result
+                    .input
+                    .line_col_for_pos(unsafe_construct.expression_l.begin)
+                    .map_or(0, |(line, _)| line + 1);
+                eprintln!(
+                    "edit_method: local variables kept because of the {} at line {}",
+                    unsafe_construct.description, line
+                );
+            }
             write_code(node.as_ref(), &mut writer, &CodeWriterContext::new())?;
             writer.flush()?;
         }
+        "minify" => match minify(&mut node, &result.comments, &result.input) {
+            Ok(minified) => {
+                for unsafe_construct in minified.unsafe_constructs.iter() {
+                    let line = result
+                        .input
+                        .line_col_for_pos(unsafe_construct.expression_l.begin)
+                        .map_or(0, |(line, _)| line + 1);
+                    eprintln!(
+                        "minify: local variables kept because of the {} at line {}",
+                        unsafe_construct.description, line
+                    );
+                }
+                let mut writer = BufWriter::new(std::io::stdout());
+                writer.write_all(&minified.code)?;
+                writer.flush()?;
+            }
+            Err(error) => {
+                eprintln!("minify: {}", error.describe());
+                std::process::exit(1);
+            }
+        },
+        "inject" => match injection_from_options()
+            .and_then(|injection| inject(node.as_ref(), &injection, &result.input))
+        {
+            Ok(replacement) => {
+                let rewrite = FileRewrite {
+                    path: PathBuf::from(&ruby_filename),
+                    replacements: vec![replacement],
+                };
+                let mut writer = BufWriter::new(std::io::stdout());
+                writer.write_all(&rewrite.rewritten_source(&result.input))?;
+                writer.flush()?;
+            }
+            Err(error) => {
+                eprintln!("inject: {}", error);
+                std::process::exit(1);
+            }
+        },
         "combine_modules" => {
             let mut writer = BufWriter::new(std::io::stdout());
-            combine_modules(&mut node);
+            report_kept_separate(
+                &combine_modules(&mut node, has_option("--force")).kept_separate,
+                &result.input,
+            );
             write_code(node.as_ref(), &mut writer, &CodeWriterContext::new())?;
             writer.flush()?;
         }
         "documentation" => {
             let mut writer = BufWriter::new(std::io::stdout());
-            combine_modules(&mut node);
+            let documentation_context = documentation_context(
+                &mut node,
+                result.comments,
+                result.input,
+                true,
+                has_option("--force"),
+            );
+            if has_option("--json") {
+                let objects = build_documentation_tree(node.as_ref(), &documentation_context);
+                documentation_tree_to_json(&objects).write(&mut writer, 0)?;
+                writer.write_all(b"\n")?;
+                writer.flush()?;
+                return Ok(());
+            }
+            filter_by_visibility(&mut node, &visibility_filter(), &documentation_context);
             write_code(
                 node.as_ref(),
                 &mut writer,
-                &CodeWriterContext::new_with_documentation(result.comments, result.input, true),
+                &CodeWriterContext::new_with_documentation(documentation_context),
             )?;
             writer.flush()?;
         }
         "documentation_with_method_body" => {
             let mut writer = BufWriter::new(std::io::stdout());
-            combine_modules(&mut node);
+            let documentation_context = documentation_context(
+                &mut node,
+                result.comments,
+                result.input,
+                false,
+                has_option("--force"),
+            );
+            filter_by_visibility(&mut node, &visibility_filter(), &documentation_context);
             write_code(
                 node.as_ref(),
                 &mut writer,
-                &CodeWriterContext::new_with_documentation(result.comments, result.input, false),
+                &CodeWriterContext::new_with_documentation(documentation_context),
             )?;
             writer.flush()?;
         }
+        "api_reference" => {
+            let documentation_context = documentation_context(
+                &mut node,
+                result.comments,
+                result.input,
+                true,
+                has_option("--force"),
+            );
+            let mut constants = make_constant_hash_map();
+            let send_rules = send_rules();
+            explore_constants(&mut constants, &node, &|send| send_rules.handle(send));
+            let objects = build_documentation_tree(node.as_ref(), &documentation_context);
+            let output_directory = option_value("--output").unwrap_or(String::from("doc"));
+            let format = if has_option("--html") {
+                ReferenceFormat::Html
+            } else {
+                ReferenceFormat::Markdown
+            };
+            write_api_reference(&objects, &constants, Path::new(&output_directory), format)?;
+        }
+        "rbs" => {
+            let mut writer = BufWriter::new(std::io::stdout());
+            let documentation_context = documentation_context(
+                &mut node,
+                result.comments,
+                result.input,
+                true,
+                has_option("--force"),
+            );
+            let objects = build_documentation_tree(node.as_ref(), &documentation_context);
+            write_rbs(&objects, &mut writer)?;
+            writer.flush()?;
+        }
         _ => {
-            println!("Unknown instruction, use write, edit_method or explore_constants")
+            println!("Unknown instruction, use {}", INSTRUCTIONS)
+        }
+    }
+    return Ok(());
+}
+
+fn parse_ruby_file(ruby_filename: &Path) -> ParserResult {
+    let ruby_file_content = fs::read_to_string(ruby_filename)
+        .expect(format!("Failed to read ruby file: {}", ruby_filename.display()).as_str());
+    let options = ParserOptions {
+        buffer_name: ruby_filename.display().to_string(),
+        record_tokens: false,
+        ..Default::default()
+    };
+    let parser = Parser::new(ruby_file_content, options);
+    return parser.do_parse();
+}
+
+/** Collect the ruby files of a directory (recursively), sorted by path */
+fn collect_ruby_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), std::io::Error> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<PathBuf>, std::io::Error>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            collect_ruby_files(&entry, files)?;
+        } else if entry
+            .extension()
+            .map_or(false, |extension| extension == "rb")
+        {
+            files.push(entry);
         }
     }
     return Ok(());
 }
+
+fn doc_coverage(path: &Path) -> Result<(), std::io::Error> {
+    let mut ruby_files: Vec<PathBuf> = vec![];
+    collect_ruby_files(path, &mut ruby_files)?;
+    let mut files: Vec<FileCoverage> = vec![];
+    for ruby_file in ruby_files.iter() {
+        let result = parse_ruby_file(ruby_file);
+        let objects = match result.ast {
+            Some(mut node) => {
+                let documentation_context =
+                    documentation_context(&mut node, result.comments, result.input, true, false);
+                build_documentation_tree(node.as_ref(), &documentation_context)
+            }
+            None => vec![],
+        };
+        files.push(FileCoverage::new(ruby_file.display().to_string(), &objects));
+    }
+    let threshold = option_value("--threshold").map(|threshold| {
+        threshold
+            .trim_end_matches('%')
+            .parse::<f64>()
+            .expect("--threshold expects a percentage")
+    });
+    let report = CoverageReport { files, threshold };
+
+    let mut writer = BufWriter::new(std::io::stdout());
+    if has_option("--json") {
+        report.to_json().write(&mut writer, 0)?;
+        writer.write_all(b"\n")?;
+    } else {
+        report.write_text(&mut writer)?;
+    }
+    writer.flush()?;
+    if report.is_below_threshold() {
+        eprintln!(
+            "doc_coverage: {:.2}% of the definitions are documented, expected at least {}%",
+            report.percentage(),
+            report.threshold.unwrap()
+        );
+        std::process::exit(1);
+    }
+    return Ok(());
+}
+
+/** Rename a constant or method in the ruby files of a path, writing them only with `--apply` */
+fn rename_in_project(path: &Path) -> Result<(), std::io::Error> {
+    let names: Vec<String> = env::args()
+        .skip(3)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    if names.len() != 2 {
+        eprintln!(
+            "rename: expected the old and new names (Foo::Bar Baz, Foo#bar baz, Foo.bar baz)"
+        );
+        std::process::exit(1);
+    }
+    let target = match RenameTarget::parse(&names[0], &names[1]) {
+        Ok(target) => target,
+        Err(error) => {
+            eprintln!("rename: {}", error);
+            std::process::exit(1);
+        }
+    };
+    let mut ruby_files: Vec<PathBuf> = vec![];
+    collect_ruby_files(path, &mut ruby_files)?;
+    let programs: Vec<(PathBuf, ParserResult)> = ruby_files
+        .into_iter()
+        .map(|ruby_file| {
+            let result = parse_ruby_file(&ruby_file);
+            (ruby_file, result)
+        })
+        .collect();
+    let renames = match rename(&target, &programs, has_option("--move-file")) {
+        Ok(renames) => renames,
+        Err(error) => {
+            eprintln!("rename: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let apply = has_option("--apply");
+    let mut updated_files = 0;
+    let mut writer = BufWriter::new(std::io::stdout());
+    for ((_, result), file_rename) in programs.iter().zip(renames.iter()) {
+        for refused in file_rename.refused.iter() {
+            let (line, column) = result
+                .input
+                .line_col_for_pos(refused.expression_l.begin)
+                .unwrap_or((0, 0));
+            eprintln!(
+                "rename: refused {} at {}:{}:{}",
+                refused.description,
+                file_rename.path.display(),
+                line + 1,
+                column + 1
+            );
+        }
+        if !file_rename.is_changed() {
+            continue;
+        }
+        file_rename.write_diff(&result.input, &mut writer)?;
+        if apply {
+            let new_path = file_rename.new_path.as_ref().unwrap_or(&file_rename.path);
+            fs::write(new_path, file_rename.renamed_source(&result.input))?;
+            if *new_path != file_rename.path {
+                fs::remove_file(&file_rename.path)?;
+            }
+            updated_files += 1;
+        }
+    }
+    writer.flush()?;
+    if apply {
+        eprintln!("rename: updated {} files", updated_files);
+    }
+    return Ok(());
+}
+
+/** Rewrite the files of a project with `--rules FILE` or `--search PATTERN --replace CODE` */
+fn search_replace_in_project(path: &Path) -> Result<(), std::io::Error> {
+    let rules = match (
+        option_value("--rules"),
+        option_value("--search"),
+        option_value("--replace"),
+    ) {
+        (Some(rules_path), None, None) => rules_from_file(Path::new(&rules_path)),
+        (None, Some(pattern), Some(replacement)) => Rule::new(&pattern, &replacement)
+            .map(|rule| vec![rule])
+            .map_err(|error| vec![error]),
+        _ => Err(vec![String::from(
+            "expected --rules FILE or --search PATTERN --replace CODE",
+        )]),
+    };
+    let rules = match rules {
+        Ok(rules) => rules,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("search_replace: {}", error);
+            }
+            std::process::exit(1);
+        }
+    };
+    let mut ruby_files: Vec<PathBuf> = vec![];
+    collect_ruby_files(path, &mut ruby_files)?;
+
+    let apply = has_option("--apply");
+    let mut updated_files = 0;
+    let mut writer = BufWriter::new(std::io::stdout());
+    for ruby_file in ruby_files {
+        let result = parse_ruby_file(&ruby_file);
+        let file_rewrite = search_replace(&rules, &ruby_file, &result);
+        if file_rewrite.replacements.is_empty() {
+            continue;
+        }
+        file_rewrite.write_diff(&result.input, &mut writer)?;
+        if apply {
+            fs::write(&ruby_file, file_rewrite.rewritten_source(&result.input))?;
+            updated_files += 1;
+        }
+    }
+    writer.flush()?;
+    if apply {
+        eprintln!("search_replace: updated {} files", updated_files);
+    }
+    return Ok(());
+}
+
+/** Apply the style corrections listed by `--enable` (`all` or names separated by commas) */
+fn autocorrect_project(path: &Path) -> Result<(), std::io::Error> {
+    let names = Autocorrection::ALL
+        .iter()
+        .map(|correction| correction.name())
+        .collect::<Vec<&str>>()
+        .join(", ");
+    let autocorrections =
+        match option_value("--enable").map(|list| Autocorrection::parse_list(&list)) {
+            Some(Ok(autocorrections)) if !autocorrections.is_empty() => autocorrections,
+            Some(Err(error)) => {
+                eprintln!("autocorrect: {} (available: {})", error, names);
+                std::process::exit(1);
+            }
+            _ => {
+                eprintln!("autocorrect: expected --enable all or some of: {}", names);
+                std::process::exit(1);
+            }
+        };
+    let mut ruby_files: Vec<PathBuf> = vec![];
+    collect_ruby_files(path, &mut ruby_files)?;
+
+    let apply = has_option("--apply");
+    let mut updated_files = 0;
+    let mut writer = BufWriter::new(std::io::stdout());
+    for ruby_file in ruby_files {
+        let result = parse_ruby_file(&ruby_file);
+        let mut node = match result.ast {
+            Some(node) => node,
+            None => continue,
+        };
+        let corrections = autocorrect(&mut node, &autocorrections, &result.comments, &result.input);
+        for correction in corrections.iter() {
+            eprintln!(
+                "autocorrect: {} {}",
+                ruby_file.display(),
+                correction.describe(&result.input)
+            );
+        }
+        let file_rewrite = FileRewrite {
+            path: ruby_file.clone(),
+            replacements: corrected_replacements(&node, &corrections, &result.input),
+        };
+        if file_rewrite.replacements.is_empty() {
+            continue;
+        }
+        file_rewrite.write_diff(&result.input, &mut writer)?;
+        if apply {
+            fs::write(&ruby_file, file_rewrite.rewritten_source(&result.input))?;
+            updated_files += 1;
+        }
+    }
+    writer.flush()?;
+    if apply {
+        eprintln!("autocorrect: updated {} files", updated_files);
+    }
+    return Ok(());
+}
+
+/** Combine the reopened modules of a program and make the context documenting it */
+fn documentation_context(
+    node: &mut Node,
+    comments: Vec<Comment>,
+    input: DecodedInput,
+    exclude_method_body: bool,
+    force: bool,
+) -> DocumentationContext {
+    let combined = combine_modules(node, force);
+    report_kept_separate(&combined.kept_separate, &input);
+    let mut documentation_context = DocumentationContext::new(
+        comments,
+        input,
+        exclude_method_body,
+        !has_option("--allow-blank-line"),
+    );
+    let strategy = match option_value("--merge-documentation").as_deref() {
+        Some("longest") => DocumentationMergeStrategy::PreferLongest,
+        Some("warn") => DocumentationMergeStrategy::WarnOnConflict,
+        Some("concatenate") | None => DocumentationMergeStrategy::Concatenate(
+            option_value("--documentation-separator").unwrap_or(String::from("#")),
+        ),
+        Some(strategy) => panic!(
+            "Unknown documentation merge strategy {}, use concatenate, longest or warn",
+            strategy
+        ),
+    };
+    for warning in documentation_context.merge_reopened_documentation(&combined.merged, &strategy) {
+        eprintln!("documentation: {}", warning);
+    }
+    return documentation_context;
+}
+
+fn visibility_filter() -> VisibilityFilter {
+    let mut filter = VisibilityFilter::new();
+    filter.include_private = has_option("--include-private");
+    filter.include_protected = filter.include_private || has_option("--include-protected");
+    filter.honor_yard_tags = has_option("--honor-yard-tags");
+    return filter;
+}
+
+/** `check_compat --target mruby|opal|2.6|ruby-2.6`, with an underscore like the other instructions */
+fn check_compat_project(path: &Path) -> Result<(), std::io::Error> {
+    let target = match option_value("--target").and_then(|target| CompatTarget::parse(&target)) {
+        Some(target) => target,
+        None => {
+            eprintln!(
+                "check_compat: --target expects mruby, opal or a Ruby version (2.6, ruby-2.6)"
+            );
+            std::process::exit(1);
+        }
+    };
+    let mut ruby_files: Vec<PathBuf> = vec![];
+    collect_ruby_files(path, &mut ruby_files)?;
+    let mut count = 0;
+    for ruby_file in ruby_files.iter() {
+        let result = parse_ruby_file(ruby_file);
+        let node = match result.ast {
+            Some(node) => node,
+            None => continue,
+        };
+        let file_name = ruby_file.display().to_string();
+        for incompatibility in check_compat(&node, target).iter() {
+            println!("{}", incompatibility.describe(&file_name, &result.input));
+            count += 1;
+        }
+    }
+    if count > 0 {
+        eprintln!("check_compat: {} incompatible nodes for {}", count, target);
+        std::process::exit(1);
+    }
+    return Ok(());
+}
+
+/** Injection given by `--into Foo::Bar --code SOURCE` (or `--code-file FILE`), `--top` or `--after NAME` and `--visibility` */
+fn injection_from_options() -> Result<Injection, String> {
+    let target = option_value("--into").ok_or("--into expects a namespace (Foo::Bar)")?;
+    let source = match (option_value("--code"), option_value("--code-file")) {
+        (Some(source), _) => source,
+        (None, Some(file)) => fs::read_to_string(&file)
+            .map_err(|error| format!("failed to read {}: {}", file, error))?,
+        (None, None) => return Err(String::from("--code or --code-file is expected")),
+    };
+    let position = match option_value("--after") {
+        Some(name) => InjectPosition::After(name),
+        None if has_option("--top") => InjectPosition::Top,
+        None => InjectPosition::Bottom,
+    };
+    let visibility = match option_value("--visibility") {
+        Some(visibility) => Visibility::from_method_name(&visibility)
+            .ok_or("--visibility expects public, protected or private")?,
+        None => Visibility::Public,
+    };
+    return Injection::from_source(&target, position, visibility, &source);
+}
+
+fn has_option(name: &str) -> bool {
+    return env::args().skip(3).any(|arg| arg == name);
+}
+
+fn option_value(name: &str) -> Option<String> {
+    let mut args = env::args().skip(3);
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return args.next();
+        }
+    }
+    return None;
+}
+
+/** Rules of the send handler evaluating method calls in constants, given by `--send-rules FILE` */
+fn send_rules() -> SendRules {
+    match option_value("--send-rules") {
+        Some(path) => return SendRules::from_file(Path::new(&path)),
+        None => return SendRules::default(),
+    }
+}
+
+/** Split a comma separated list of constant paths given to an option */
+fn split_paths(paths: &str) -> Vec<String> {
+    return paths
+        .split(',')
+        .map(|path| path.trim().trim_start_matches("::"))
+        .filter(|path| !path.is_empty())
+        .map(String::from)
+        .collect();
+}
+
+fn report_kept_separate(kept_separate: &Vec<KeptSeparate>, input: &DecodedInput) {
+    for kept in kept_separate.iter() {
+        eprintln!("combine_modules: {}", kept.describe(input));
+    }
+}
@@ -0,0 +1,410 @@
+use std::{collections::HashSet, io::BufWriter};
+
+use lib_ruby_parser::{
+    nodes::{Dstr, Str, Xstr},
+    source::{Comment, DecodedInput},
+    Bytes, Loc, Node, Parser, ParserOptions,
+};
+
+use crate::{
+    code_writer::{write_code, CodeWriterContext},
+    scope_analysis::{ScopeTree, UnsafeConstruct},
+    tests::edit_methods::edit_methods,
+    visitor::children_mut,
+};
+
+/** Magic comments changing how Ruby reads or runs a file, kept at the top of the minified code */
+const MAGIC_COMMENTS: [&str; 5] = [
+    "frozen_string_literal:",
+    "encoding:",
+    "coding:",
+    "warn_indent:",
+    "shareable_constant_value:",
+];
+
+/** Minified code of a program */
+pub struct Minified {
+    pub code: Vec<u8>,
+    /** Constructs (`binding`, `eval`...) that kept local variables from being renamed */
+    pub unsafe_constructs: Vec<UnsafeConstruct>,
+}
+
+/** Reason why the minified code was rejected */
+#[derive(Debug)]
+pub enum MinifyError {
+    /** The minified code does not parse, with the errors of the parser */
+    Unparsable(Vec<String>),
+    /** The minified code parses as a different program */
+    ProgramChanged,
+}
+
+impl MinifyError {
+    pub fn describe(&self) -> String {
+        match self {
+            MinifyError::Unparsable(errors) => {
+                return format!("the minified code does not parse: {}", errors.join(", "))
+            }
+            MinifyError::ProgramChanged => {
+                return String::from("the minified code parses as a different program")
+            }
+        }
+    }
+}
+
+/**
+ * Write a program on a single line: local variables get short names, comments, blank lines,
+ * optional spaces and parentheses are dropped, statements are joined with `;` and `do ... end`
+ * blocks become braces when they bind to the same call. Heredocs are written as strings and the
+ * data after `__END__` is kept as is. The minified code is parsed again and must give back the
+ * same program.
+ */
+pub fn minify(
+    node: &mut Box<Node>,
+    comments: &[Comment],
+    input: &DecodedInput,
+) -> Result<Minified, MinifyError> {
+    let unsafe_constructs = edit_methods(node);
+    let local_names: HashSet<String> = ScopeTree::new(node)
+        .variables
+        .iter()
+        .map(|variable| variable.name.clone())
+        .collect();
+    drop_call_parentheses(node, &local_names);
+    shorten_syntax(node, &local_names);
+
+    let mut code = magic_comments(comments, input, node.expression().begin);
+    code.extend(write_minified(node));
+    verify(&code, node)?;
+    code.push(b'\n');
+    if let Some(data) = data_section(input, node.expression().end) {
+        code.extend_from_slice(data);
+    }
+    return Ok(Minified {
+        code,
+        unsafe_constructs,
+    });
+}
+
+fn write_minified(node: &Node) -> Vec<u8> {
+    let mut writer = BufWriter::new(Vec::new());
+    write_code(node, &mut writer, &CodeWriterContext::new_minified())
+        .expect("Failed to write the minified code");
+    let mut code = writer
+        .into_inner()
+        .expect("Failed to write the minified code");
+    while code.last() == Some(&b';') {
+        code.pop();
+    }
+    return code;
+}
+
+/** Parse the minified code and compare it to the program it was written from */
+fn verify(code: &[u8], node: &Node) -> Result<(), MinifyError> {
+    let options = ParserOptions {
+        buffer_name: String::from("(minified)"),
+        record_tokens: false,
+        ..Default::default()
+    };
+    let result = Parser::new(code.to_vec(), options).do_parse();
+    let errors: Vec<String> = result
+        .diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.is_error())
+        .map(|diagnostic| {
+            format!(
+                "{} at column {}",
+                diagnostic.render_message(),
+                diagnostic.loc.begin + 1
+            )
+        })
+        .collect();
+    if !errors.is_empty() {
+        return Err(MinifyError::Unparsable(errors));
+    }
+    match result.ast {
+        Some(ast) if structure(&ast) == structure(node) => return Ok(()),
+        _ => return Err(MinifyError::ProgramChanged),
+    }
+}
+
+/** Debug representation of a node without its locations, the same for code written differently */
+pub fn structure(node: &Node) -> String {
+    let debug = format!("{:?}", node);
+    let mut structure = String::with_capacity(debug.len());
+    let mut rest = debug.as_str();
+    while let Some(index) = rest.find("_l: ") {
+        let field_start = rest[..index]
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .map_or(0, |start| start + 1);
+        structure.push_str(&rest[..field_start]);
+        rest = &rest[(index + 4)..];
+        // The value is `None`, `0...1` or `Some(0...1)`
+        let value_start = if rest.starts_with("Some(") { 5 } else { 0 };
+        let value_end = match rest[value_start..].find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        {
+            _ if rest.starts_with("None") => 4,
+            Some(end) if value_start > 0 => value_start + end + 1,
+            Some(end) => end,
+            None => rest.len(),
+        };
+        rest = &rest[value_end..];
+        rest = rest.strip_prefix(", ").unwrap_or(rest);
+    }
+    structure.push_str(rest);
+    return structure;
+}
+
+/** Magic comments written before the first statement, one per line */
+fn magic_comments(comments: &[Comment], input: &DecodedInput, code_begin: usize) -> Vec<u8> {
+    let mut code: Vec<u8> = vec![];
+    for comment in comments {
+        if comment.location.begin >= code_begin {
+            break;
+        }
+        if let Some(source) = comment.location.source(input) {
+            if MAGIC_COMMENTS.iter().any(|magic| source.contains(magic)) {
+                code.extend(source.trim_end().as_bytes());
+                code.push(b'\n');
+            }
+        }
+    }
+    return code;
+}
+
+/** `__END__` line and the data following it */
+fn data_section(input: &DecodedInput, code_end: usize) -> Option<&[u8]> {
+    let line = input.lines.iter().find(|line| {
+        line.start >= code_end
+            && matches!(
+                &input.bytes[line.start..line.end],
+                b"__END__" | b"__END__\n" | b"__END__\r\n"
+            )
+    })?;
+    return Some(&input.bytes[line.start..]);
+}
+
+/** Drop the parentheses of a method call written as a statement (`puts(a, b)` => `puts a, b`) */
+fn drop_call_parentheses(node: &mut Node, local_names: &HashSet<String>) {
+    if let Node::Send(send) = node {
+        if send.begin_l.is_none()
+            || send.args.is_empty()
+            || !send
+                .method_name
+                .starts_with(|c: char| c.is_alphabetic() || c == '_')
+            || send.method_name.ends_with('=')
+            || (send.recv.is_none() && local_names.contains(&send.method_name))
+            || starts_with_brace(&send.args[0])
+            || send.args.iter().any(is_do_block)
+        {
+            return;
+        }
+        send.begin_l = None;
+        send.end_l = None;
+    }
+}
+
+/** Tell if the code of a node starts with a hash literal, read as a block after a method name */
+fn starts_with_brace(node: &Node) -> bool {
+    match node {
+        Node::Hash(hash) => hash.begin_l.is_some(),
+        Node::Send(send) => send.recv.as_deref().map_or(false, starts_with_brace),
+        Node::CSend(send) => starts_with_brace(&send.recv),
+        Node::Index(index) => starts_with_brace(&index.recv),
+        _ => false,
+    }
+}
+
+fn is_do_block(node: &Node) -> bool {
+    match node {
+        Node::Block(block) => block.begin_l.size() == 2,
+        Node::Numblock(block) => block.begin_l.size() == 2,
+        _ => false,
+    }
+}
+
+/** Tell if braces given to a call bind to it like `do ... end` does */
+fn binds_like_braces(call: &Node, local_names: &HashSet<String>) -> bool {
+    match call {
+        Node::Send(send) => {
+            (send.args.is_empty() || send.begin_l.is_some())
+                && !(send.recv.is_none() && local_names.contains(&send.method_name))
+        }
+        Node::CSend(send) => send.args.is_empty() || send.begin_l.is_some(),
+        Node::Super(super_) => super_.args.is_empty() || super_.begin_l.is_some(),
+        Node::ZSuper(_) | Node::Lambda(_) => true,
+        _ => false,
+    }
+}
+
+fn use_braces(begin_l: &mut Loc, end_l: &mut Loc) {
+    *begin_l = Loc {
+        begin: begin_l.begin,
+        end: begin_l.begin + 1,
+    };
+    *end_l = Loc {
+        begin: end_l.end - 1,
+        end: end_l.end,
+    };
+}
+
+fn shorten_syntax(node: &mut Node, local_names: &HashSet<String>) {
+    match node {
+        Node::Begin(begin) if begin.begin_l.is_none() => {
+            for statement in begin.statements.iter_mut() {
+                drop_call_parentheses(statement, local_names);
+            }
+        }
+        Node::KwBegin(begin) => {
+            for statement in begin.statements.iter_mut() {
+                drop_call_parentheses(statement, local_names);
+            }
+        }
+        Node::Block(block) => {
+            if block.begin_l.size() == 2 && binds_like_braces(&block.call, local_names) {
+                use_braces(&mut block.begin_l, &mut block.end_l);
+            }
+            if let Some(body) = block.body.as_deref_mut() {
+                drop_call_parentheses(body, local_names);
+            }
+        }
+        Node::Numblock(block) => {
+            if block.begin_l.size() == 2 && binds_like_braces(&block.call, local_names) {
+                use_braces(&mut block.begin_l, &mut block.end_l);
+            }
+            drop_call_parentheses(&mut block.body, local_names);
+        }
+        Node::Def(def) => {
+            if let Some(body) = def.body.as_deref_mut() {
+                drop_call_parentheses(body, local_names);
+            }
+        }
+        Node::Defs(def) => {
+            if let Some(body) = def.body.as_deref_mut() {
+                drop_call_parentheses(body, local_names);
+            }
+        }
+        Node::Class(class) => {
+            if let Some(body) = class.body.as_deref_mut() {
+                drop_call_parentheses(body, local_names);
+            }
+        }
+        Node::Module(module) => {
+            if let Some(body) = module.body.as_deref_mut() {
+                drop_call_parentheses(body, local_names);
+            }
+        }
+        Node::SClass(sclass) => {
+            if let Some(body) = sclass.body.as_deref_mut() {
+                drop_call_parentheses(body, local_names);
+            }
+        }
+        Node::Heredoc(heredoc) => {
+            let string = join_string_parts(&heredoc.parts, heredoc.expression_l);
+            *node = string;
+        }
+        Node::Dstr(dstr) if dstr.begin_l.map_or(false, |begin_l| begin_l.size() == 1) => {
+            let string = join_string_parts(&dstr.parts, dstr.expression_l);
+            *node = string;
+        }
+        Node::XHeredoc(heredoc) => {
+            let expression_l = heredoc.expression_l;
+            let parts = join_parts(&heredoc.parts);
+            *node = Node::Xstr(Xstr {
+                parts,
+                begin_l: quote_l(&expression_l),
+                end_l: quote_l(&expression_l),
+                expression_l,
+            });
+        }
+        _ => {}
+    }
+    // Parentheses under a unary operator are kept: `-(1)` is not the literal `-1`
+    let unary = matches!(node, Node::Send(send) if send.method_name.ends_with('@'));
+    for child in children_mut(node) {
+        if !unary {
+            unwrap_parentheses(child);
+        }
+        shorten_syntax(child, local_names);
+    }
+}
+
+/** Drop the parentheses around a single literal or variable (`(1).times` => `1.times`) */
+fn unwrap_parentheses(node: &mut Node) {
+    if let Node::Begin(begin) = node {
+        if begin.statements.len() == 1
+            && begin.begin_l.map_or(false, |begin_l| begin_l.size() == 1)
+            && is_atomic(&begin.statements[0])
+        {
+            let statement = begin.statements.remove(0);
+            *node = statement;
+        }
+    }
+}
+
+fn is_atomic(node: &Node) -> bool {
+    match node {
+        Node::Int(int) => !int.value.starts_with('-'),
+        Node::Float(float) => !float.value.starts_with('-'),
+        Node::Array(array) => array.begin_l.is_some(),
+        Node::Str(_)
+        | Node::Sym(_)
+        | Node::Lvar(_)
+        | Node::Ivar(_)
+        | Node::Gvar(_)
+        | Node::Cvar(_)
+        | Node::Const(_)
+        | Node::Nil(_)
+        | Node::True(_)
+        | Node::False(_)
+        | Node::Self_(_) => true,
+        _ => false,
+    }
+}
+
+/** String literal with the same value as a heredoc or multi-line string */
+fn join_string_parts(parts: &Vec<Node>, expression_l: Loc) -> Node {
+    let parts = join_parts(parts);
+    let value = match parts.as_slice() {
+        [] => Bytes::empty(),
+        [Node::Str(str)] => str.value.clone(),
+        _ => {
+            return Node::Dstr(Dstr {
+                parts,
+                begin_l: Some(quote_l(&expression_l)),
+                end_l: Some(quote_l(&expression_l)),
+                expression_l,
+            })
+        }
+    };
+    return Node::Str(Str {
+        value,
+        begin_l: Some(quote_l(&expression_l)),
+        end_l: Some(quote_l(&expression_l)),
+        expression_l,
+    });
+}
+
+/** Merge the consecutive string parts (one by line) of a string */
+fn join_parts(parts: &Vec<Node>) -> Vec<Node> {
+    let mut joined: Vec<Node> = vec![];
+    for part in parts.iter() {
+        match (joined.last_mut(), part) {
+            (Some(Node::Str(previous)), Node::Str(str)) => {
+                let mut value = previous.value.as_raw().clone();
+                value.extend(str.value.as_raw());
+                previous.value = Bytes::new(value);
+                previous.expression_l = previous.expression_l.with_end(str.expression_l.end);
+            }
+            _ => joined.push(part.clone()),
+        }
+    }
+    return joined;
+}
+
+/** Location of a one character quote, so the writer writes `"` and not `%Q{` */
+fn quote_l(expression_l: &Loc) -> Loc {
+    return Loc {
+        begin: expression_l.begin,
+        end: expression_l.begin + 1,
+    };
+}
@@ -0,0 +1,443 @@
+use std::io::{BufWriter, Write};
+
+use lib_ruby_parser::Node;
+
+use crate::{
+    documentation_tree::{DocumentedKind, DocumentedObject},
+    yard::{YardDocstring, YardTag},
+};
+
+const UNTYPED: &str = "untyped";
+
+/** Write the RBS declarations of the documented objects */
+pub fn write_rbs<W: Write>(
+    objects: &Vec<DocumentedObject>,
+    writer: &mut BufWriter<W>,
+) -> Result<(), std::io::Error> {
+    let (top_level_methods, declarations): (Vec<&DocumentedObject>, Vec<&DocumentedObject>) =
+        objects
+            .iter()
+            .filter(|object| is_member_of(object, ""))
+            .partition(|object| {
+                object.kind == DocumentedKind::InstanceMethod
+                    || object.kind == DocumentedKind::ClassMethod
+                    || object.kind == DocumentedKind::Attribute
+            });
+    for object in declarations.iter() {
+        write_rbs_object(object, writer, 0)?;
+    }
+    if !top_level_methods.is_empty() {
+        writer.write_all(b"class Object\n")?;
+        for object in top_level_methods.iter() {
+            write_rbs_object(object, writer, 1)?;
+        }
+        writer.write_all(b"end\n")?;
+    }
+    return Ok(());
+}
+
+fn write_rbs_object<W: Write>(
+    object: &DocumentedObject,
+    writer: &mut BufWriter<W>,
+    indent: u32,
+) -> Result<(), std::io::Error> {
+    write_rbs_comment(&object.docstring, writer, indent)?;
+    write_rbs_indent(writer, indent)?;
+    match object.kind {
+        DocumentedKind::Module | DocumentedKind::Class => {
+            if object.kind == DocumentedKind::Class {
+                writer.write_fmt(format_args!("class {}", object.name))?;
+                if let Some(superclass) = &object.superclass {
+                    if is_constant_path(superclass) {
+                        writer.write_fmt(format_args!(" < {}", superclass))?;
+                    }
+                }
+            } else {
+                writer.write_fmt(format_args!("module {}", object.name))?;
+            }
+            writer.write_all(b"\n")?;
+            for mixin in object.mixins.iter() {
+                if is_constant_path(&mixin.module_name) {
+                    write_rbs_indent(writer, indent + 1)?;
+                    writer.write_fmt(format_args!(
+                        "{} {}\n",
+                        mixin.method_name, mixin.module_name
+                    ))?;
+                }
+            }
+            for child in object.children.iter() {
+                if is_member_of(child, &object.path) {
+                    write_rbs_object(child, writer, indent + 1)?;
+                }
+            }
+            write_rbs_indent(writer, indent)?;
+            writer.write_all(b"end\n")?;
+        }
+        DocumentedKind::InstanceMethod | DocumentedKind::ClassMethod => {
+            writer.write_all(b"def ")?;
+            if object.kind == DocumentedKind::ClassMethod {
+                writer.write_all(b"self.")?;
+            }
+            writer.write_fmt(format_args!(
+                "{}: {}\n",
+                object.name,
+                method_signature(object)
+            ))?;
+        }
+        DocumentedKind::Attribute => {
+            let access = object
+                .attribute_access
+                .as_deref()
+                .unwrap_or("attr_accessor");
+            writer.write_fmt(format_args!(
+                "{} {}: {}\n",
+                access,
+                object.name,
+                attribute_type(&object.docstring)
+            ))?;
+        }
+        DocumentedKind::Constant => {
+            let constant_type = match object.docstring.as_ref().and_then(return_tag) {
+                Some(tag) => union_type(&tag.types),
+                None => literal_type(&object.value),
+            };
+            writer.write_fmt(format_args!("{}: {}\n", object.name, constant_type))?;
+        }
+    }
+    return Ok(());
+}
+
+/** Singleton methods defined on another namespace (`def Other.name`) are not written in this one */
+fn is_member_of(object: &DocumentedObject, namespace_path: &str) -> bool {
+    return object.kind != DocumentedKind::ClassMethod
+        || object.path == format!("{}.{}", namespace_path, object.name);
+}
+
+fn write_rbs_comment<W: Write>(
+    docstring: &Option<YardDocstring>,
+    writer: &mut BufWriter<W>,
+    indent: u32,
+) -> Result<(), std::io::Error> {
+    if let Some(docstring) = docstring {
+        let mut text = docstring.summary.clone();
+        if !docstring.description.is_empty() {
+            text.push_str("\n\n");
+            text.push_str(&docstring.description);
+        }
+        for line in text.lines().filter(|_| !text.is_empty()) {
+            write_rbs_indent(writer, indent)?;
+            if line.is_empty() {
+                writer.write_all(b"#\n")?;
+            } else {
+                writer.write_fmt(format_args!("# {}\n", line))?;
+            }
+        }
+    }
+    return Ok(());
+}
+
+fn write_rbs_indent<W: Write>(
+    writer: &mut BufWriter<W>,
+    indent: u32,
+) -> Result<(), std::io::Error> {
+    for _ in 0..indent {
+        writer.write_all(b"  ")?;
+    }
+    return Ok(());
+}
+
+/** Build the `(params) ?{ (block params) -> type } -> type` signature of a method */
+pub fn method_signature(object: &DocumentedObject) -> String {
+    let docstring = object.docstring.as_ref();
+    let mut params: Vec<String> = vec![];
+    let mut block: Option<String> = None;
+    if let Some(args) = &object.args {
+        for arg in args.args.iter() {
+            match arg {
+                Node::Arg(arg) => params.push(format!(
+                    "{} {}",
+                    param_type(docstring, &arg.name, true),
+                    arg.name
+                )),
+                Node::Optarg(arg) => params.push(format!(
+                    "?{} {}",
+                    param_type(docstring, &arg.name, true),
+                    arg.name
+                )),
+                Node::Restarg(arg) => match &arg.name {
+                    Some(name) => params.push(format!(
+                        "*{} {}",
+                        collection_element_type(param_type(docstring, name, true), "Array["),
+                        name
+                    )),
+                    None => params.push(String::from("*untyped")),
+                },
+                Node::Kwarg(arg) => params.push(format!(
+                    "{}: {}",
+                    arg.name,
+                    param_type(docstring, &arg.name, false)
+                )),
+                Node::Kwoptarg(arg) => params.push(format!(
+                    "?{}: {}",
+                    arg.name,
+                    param_type(docstring, &arg.name, false)
+                )),
+                Node::Kwrestarg(arg) => match &arg.name {
+                    Some(name) => params.push(format!(
+                        "**{} {}",
+                        collection_element_type(param_type(docstring, name, true), "Hash["),
+                        name
+                    )),
+                    None => params.push(String::from("**untyped")),
+                },
+                Node::Blockarg(_) => block = Some(block_signature(docstring)),
+                Node::ForwardArg(_) => {
+                    params.push(String::from("*untyped"));
+                    params.push(String::from("**untyped"));
+                    block = Some(String::from("?{ (*untyped) -> untyped }"));
+                }
+                Node::Kwnilarg(_) => {}
+                _ => params.push(String::from(UNTYPED)),
+            }
+        }
+    }
+    if block.is_none() && docstring.map_or(false, |doc| yield_tag(doc).is_some()) {
+        block = Some(block_signature(docstring));
+    }
+    let return_type = if object.name == "initialize" {
+        String::from("void")
+    } else {
+        match docstring.and_then(return_tag) {
+            Some(tag) => union_type(&tag.types),
+            None => String::from(UNTYPED),
+        }
+    };
+    let mut signature = format!("({})", params.join(", "));
+    if let Some(block) = block {
+        signature.push(' ');
+        signature.push_str(&block);
+    }
+    signature.push_str(" -> ");
+    signature.push_str(&return_type);
+    return signature;
+}
+
+fn block_signature(docstring: Option<&YardDocstring>) -> String {
+    let mut params: Vec<String> = vec![];
+    let mut return_type = String::from(UNTYPED);
+    if let Some(docstring) = docstring {
+        let yield_params: Vec<&YardTag> = docstring
+            .tags
+            .iter()
+            .filter(|tag| tag.tag_name == "yieldparam")
+            .collect();
+        if !yield_params.is_empty() {
+            for tag in yield_params {
+                params.push(grouped_union_type(&tag.types));
+            }
+        } else if let Some(tag) = yield_tag(docstring) {
+            for _ in tag.types.iter() {
+                params.push(String::from(UNTYPED));
+            }
+        }
+        if let Some(tag) = docstring
+            .tags
+            .iter()
+            .find(|tag| tag.tag_name == "yieldreturn")
+        {
+            return_type = union_type(&tag.types);
+        }
+    }
+    return format!("?{{ ({}) -> {} }}", params.join(", "), return_type);
+}
+
+fn param_type(docstring: Option<&YardDocstring>, name: &str, grouped: bool) -> String {
+    let tag = docstring.and_then(|docstring| {
+        docstring
+            .tags
+            .iter()
+            .find(|tag| tag.tag_name == "param" && tag.name.as_deref() == Some(name))
+    });
+    match tag {
+        Some(tag) if grouped => grouped_union_type(&tag.types),
+        Some(tag) => union_type(&tag.types),
+        None => String::from(UNTYPED),
+    }
+}
+
+/** Get the type of the elements of a splat parameter documented with its collection type */
+fn collection_element_type(collection_type: String, collection_prefix: &str) -> String {
+    if let Some(parameters) = collection_type
+        .strip_prefix(collection_prefix)
+        .and_then(|parameters| parameters.strip_suffix(']'))
+    {
+        let parameters = split_top_level(parameters, ',');
+        if let Some(element_type) = parameters.last() {
+            return element_type.clone();
+        }
+    }
+    return collection_type;
+}
+
+fn attribute_type(docstring: &Option<YardDocstring>) -> String {
+    match docstring.as_ref().and_then(return_tag) {
+        Some(tag) => union_type(&tag.types),
+        None => String::from(UNTYPED),
+    }
+}
+
+fn return_tag(docstring: &YardDocstring) -> Option<&YardTag> {
+    return docstring.tags.iter().find(|tag| tag.tag_name == "return");
+}
+
+fn yield_tag(docstring: &YardDocstring) -> Option<&YardTag> {
+    return docstring.tags.iter().find(|tag| tag.tag_name == "yield");
+}
+
+fn literal_type(value: &Option<Node>) -> String {
+    let rbs_type = match value {
+        Some(Node::Int(_)) => "Integer",
+        Some(Node::Float(_)) => "Float",
+        Some(Node::Rational(_)) => "Rational",
+        Some(Node::Complex(_)) => "Complex",
+        Some(Node::Str(_)) | Some(Node::Dstr(_)) | Some(Node::Heredoc(_)) => "String",
+        Some(Node::Sym(_)) | Some(Node::Dsym(_)) => "Symbol",
+        Some(Node::Nil(_)) => "nil",
+        Some(Node::True(_)) | Some(Node::False(_)) => "bool",
+        Some(Node::Regexp(_)) => "Regexp",
+        Some(Node::Array(_)) => "Array[untyped]",
+        Some(Node::Hash(_)) => "Hash[untyped, untyped]",
+        Some(Node::Irange(_)) | Some(Node::Erange(_)) => "Range[untyped]",
+        _ => UNTYPED,
+    };
+    return String::from(rbs_type);
+}
+
+/** Convert a list of YARD types to a RBS union */
+pub fn union_type(types: &Vec<String>) -> String {
+    let mut rbs_types: Vec<String> = vec![];
+    for yard_type in types.iter() {
+        let rbs_type = yard_type_to_rbs(yard_type);
+        if !rbs_types.contains(&rbs_type) {
+            rbs_types.push(rbs_type);
+        }
+    }
+    if rbs_types.is_empty() || rbs_types.iter().any(|rbs_type| rbs_type == UNTYPED) {
+        return String::from(UNTYPED);
+    }
+    if rbs_types.len() == 2 && rbs_types.iter().any(|rbs_type| rbs_type == "nil") {
+        let other = rbs_types
+            .iter()
+            .find(|rbs_type| *rbs_type != "nil")
+            .expect("");
+        if !other.contains(' ') {
+            return format!("{}?", other);
+        }
+    }
+    return rbs_types.join(" | ");
+}
+
+fn grouped_union_type(types: &Vec<String>) -> String {
+    let rbs_type = union_type(types);
+    if rbs_type.contains(" | ") {
+        return format!("({})", rbs_type);
+    }
+    return rbs_type;
+}
+
+/** Convert a single YARD type (`Array<String>`, `Hash{Symbol => Integer}`, `Boolean`) to RBS */
+pub fn yard_type_to_rbs(yard_type: &str) -> String {
+    let yard_type = yard_type.trim();
+    match yard_type {
+        "Boolean" | "bool" => return String::from("bool"),
+        "nil" | "NilClass" => return String::from("nil"),
+        "void" => return String::from("void"),
+        "self" => return String::from("self"),
+        "true" | "false" => return String::from(yard_type),
+        "Object" | "BasicObject" => return String::from(UNTYPED),
+        "" => return String::from(UNTYPED),
+        _ => {}
+    }
+    if yard_type.starts_with('#') {
+        // Duck types cannot be expressed without an interface
+        return String::from(UNTYPED);
+    }
+    if yard_type.starts_with(':') || yard_type.starts_with('"') || yard_type.starts_with('\'') {
+        return String::from(yard_type);
+    }
+    if let Some(open) = yard_type.find(|c| c == '<' || c == '{' || c == '(') {
+        let base = &yard_type[..open];
+        let close = match yard_type.chars().last() {
+            Some(close) => close,
+            None => return String::from(UNTYPED),
+        };
+        let inner = &yard_type[(open + 1)..(yard_type.len() - close.len_utf8())];
+        match &yard_type[open..(open + 1)] {
+            "<" => {
+                let parameters = split_top_level(inner, ',');
+                let parameter = union_type(&parameters);
+                return match base {
+                    "Hash" => format!("Hash[untyped, {}]", parameter),
+                    _ => format!("{}[{}]", yard_type_to_rbs(base), parameter),
+                };
+            }
+            "{" => {
+                let pair = split_top_level(inner, '=');
+                if pair.len() == 2 {
+                    let key = union_type(&split_top_level(&pair[0], ','));
+                    let value = union_type(&split_top_level(pair[1].trim_start_matches('>'), ','));
+                    return format!("{}[{}, {}]", yard_type_to_rbs(base), key, value);
+                }
+                return format!("{}[untyped, untyped]", yard_type_to_rbs(base));
+            }
+            _ => {
+                let elements: Vec<String> = split_top_level(inner, ',')
+                    .iter()
+                    .map(|element| yard_type_to_rbs(element))
+                    .collect();
+                return format!("[{}]", elements.join(", "));
+            }
+        }
+    }
+    if is_constant_path(yard_type) {
+        return String::from(yard_type);
+    }
+    return String::from(UNTYPED);
+}
+
+fn split_top_level(text: &str, separator: char) -> Vec<String> {
+    let mut parts: Vec<String> = vec![];
+    let mut current = String::new();
+    let mut depth = 0;
+    for character in text.chars() {
+        match character {
+            '<' | '{' | '(' | '[' => depth += 1,
+            '>' | '}' | ')' | ']' if depth > 0 && !current.ends_with('=') => depth -= 1,
+            _ => {}
+        }
+        if character == separator && depth == 0 {
+            parts.push(String::from(current.trim()));
+            current.clear();
+        } else {
+            current.push(character);
+        }
+    }
+    if !current.trim().is_empty() {
+        parts.push(String::from(current.trim()));
+    }
+    return parts;
+}
+
+fn is_constant_path(text: &str) -> bool {
+    let text = text.trim_start_matches("::");
+    return !text.is_empty()
+        && text.split("::").all(|segment| {
+            segment
+                .chars()
+                .next()
+                .map_or(false, |c| c.is_ascii_uppercase())
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+}
@@ -0,0 +1,919 @@
+use std::{
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use lib_ruby_parser::{source::DecodedInput, Loc, Node, ParserResult};
+
+use crate::{
+    tests::constant_resolver::{written_path, ConstantResolver},
+    visitor::{walk, NodePath, Visitor},
+};
+
+/** Methods taking the name of a method of their receiver (or self) as first argument */
+const METHOD_NAME_SENDS: [&str; 7] = [
+    "send",
+    "public_send",
+    "__send__",
+    "respond_to?",
+    "method",
+    "public_method",
+    "singleton_method",
+];
+
+/** Methods of a class or module taking names of its instance methods as arguments */
+const INSTANCE_METHOD_NAME_SENDS: [&str; 14] = [
+    "alias_method",
+    "define_method",
+    "instance_method",
+    "public_instance_method",
+    "method_defined?",
+    "public_method_defined?",
+    "private_method_defined?",
+    "protected_method_defined?",
+    "remove_method",
+    "undef_method",
+    "private",
+    "protected",
+    "public",
+    "module_function",
+];
+
+/** Methods defining the readers and setters of instance variables */
+const ATTRIBUTE_SENDS: [&str; 4] = ["attr", "attr_reader", "attr_writer", "attr_accessor"];
+
+/** Methods taking the name of a constant as first argument */
+const CONSTANT_NAME_SENDS: [&str; 5] = [
+    "const_get",
+    "const_set",
+    "const_defined?",
+    "const_source_location",
+    "remove_const",
+];
+
+/** What a rename changes */
+#[derive(Debug, Clone)]
+pub enum RenameTarget {
+    /** Class, module or constant (`Foo::Bar`), renamed within its namespace */
+    Constant { path: String, new_name: String },
+    /**
+     * Instance method (`Foo#bar`), singleton method (`Foo.bar`) or any method with a name (`bar`)
+     */
+    Method {
+        owner: Option<String>,
+        singleton: bool,
+        name: String,
+        new_name: String,
+    },
+}
+
+impl RenameTarget {
+    /** Read the old and new names given to the `rename` command */
+    pub fn parse(old: &str, new: &str) -> Result<Self, String> {
+        let old = old.trim_start_matches("::");
+        let new = new.trim_start_matches("::");
+        if let Some(index) = old.rfind(|c| c == '#' || c == '.') {
+            let (owner, name) = (&old[..index], &old[(index + 1)..]);
+            let new_name = match new.rfind(|c| c == '#' || c == '.') {
+                Some(new_index) if &new[..new_index] != owner => {
+                    return Err(format!("{} cannot be moved to {}", old, &new[..new_index]))
+                }
+                Some(new_index) => &new[(new_index + 1)..],
+                None => new,
+            };
+            return Ok(RenameTarget::Method {
+                owner: Some(String::from(owner)),
+                singleton: old[index..].starts_with('.'),
+                name: String::from(name),
+                new_name: String::from(new_name),
+            });
+        }
+        if old.starts_with(|c: char| c.is_ascii_uppercase()) {
+            let namespace = old.rsplit_once("::").map(|(namespace, _)| namespace);
+            let new_name = match new.rsplit_once("::") {
+                Some((new_namespace, _)) if Some(new_namespace) != namespace => {
+                    return Err(format!("{} cannot be moved to {}", old, new_namespace))
+                }
+                Some((_, new_name)) => new_name,
+                None => new,
+            };
+            if !new_name.starts_with(|c: char| c.is_ascii_uppercase()) {
+                return Err(format!("{} is not a constant name", new_name));
+            }
+            return Ok(RenameTarget::Constant {
+                path: String::from(old),
+                new_name: String::from(new_name),
+            });
+        }
+        return Ok(RenameTarget::Method {
+            owner: None,
+            singleton: false,
+            name: String::from(old),
+            new_name: String::from(new),
+        });
+    }
+}
+
+/** Text replacing a range of a file */
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    pub range: Loc,
+    pub text: String,
+}
+
+/** Call site that could refer to the renamed name but was left untouched */
+#[derive(Debug, Clone)]
+pub struct RefusedSite {
+    pub description: String,
+    pub expression_l: Loc,
+}
+
+/** Changes a rename makes to a file */
+#[derive(Debug, Clone)]
+pub struct FileRename {
+    pub path: PathBuf,
+    /** New path of a file named after the renamed constant (`--move-file`) */
+    pub new_path: Option<PathBuf>,
+    pub replacements: Vec<Replacement>,
+    pub refused: Vec<RefusedSite>,
+}
+
+impl FileRename {
+    pub fn is_changed(&self) -> bool {
+        return !self.replacements.is_empty() || self.new_path.is_some();
+    }
+
+    /** Source of the file with the replacements applied */
+    pub fn renamed_source(&self, input: &DecodedInput) -> Vec<u8> {
+        let mut source: Vec<u8> = vec![];
+        let mut position = 0;
+        for replacement in self.replacements.iter() {
+            source.extend_from_slice(&input.bytes[position..replacement.range.begin]);
+            source.extend_from_slice(replacement.text.as_bytes());
+            position = replacement.range.end;
+        }
+        source.extend_from_slice(&input.bytes[position..]);
+        return source;
+    }
+
+    /** Write the changed lines of the file as a diff */
+    pub fn write_diff<W: Write>(
+        &self,
+        input: &DecodedInput,
+        writer: &mut BufWriter<W>,
+    ) -> Result<(), std::io::Error> {
+        writeln!(writer, "--- {}", self.path.display())?;
+        writeln!(
+            writer,
+            "+++ {}",
+            self.new_path.as_ref().unwrap_or(&self.path).display()
+        )?;
+        let mut renamed_input = DecodedInput::named(input.name.clone());
+        renamed_input.update_bytes(self.renamed_source(input));
+        let mut lines: Vec<usize> = self
+            .replacements
+            .iter()
+            .filter_map(|replacement| input.line_col_for_pos(replacement.range.begin))
+            .map(|(line, _)| line)
+            .collect();
+        lines.dedup();
+        // Names never span several lines, so the renamed file has the same lines
+        for line in lines {
+            writeln!(writer, "@@ -{} +{} @@", line + 1, line + 1)?;
+            writeln!(writer, "-{}", line_source(input, line))?;
+            writeln!(writer, "+{}", line_source(&renamed_input, line))?;
+        }
+        return Ok(());
+    }
+}
+
+fn line_source(input: &DecodedInput, line: usize) -> String {
+    match input.lines.get(line) {
+        Some(line) => String::from_utf8_lossy(&input.bytes[line.start..line.end])
+            .trim_end_matches(|c| c == '\n' || c == '\r')
+            .to_string(),
+        None => String::new(),
+    }
+}
+
+/**
+ * Rename a constant or method over the files of a project: definitions, references, `alias`
+ * and `alias_method` arguments and names given to `send`, `respond_to?` & co. Attributes are
+ * renamed with their instance variable, and with their setter when defined by `attr_accessor`.
+ * Calls whose receiver could be another class defining the same method, and names computed at
+ * runtime, are refused and listed instead.
+ */
+pub fn rename(
+    target: &RenameTarget,
+    programs: &[(PathBuf, ParserResult)],
+    move_files: bool,
+) -> Result<Vec<FileRename>, String> {
+    let nodes: Vec<&Node> = programs
+        .iter()
+        .filter_map(|(_, result)| result.ast.as_deref())
+        .collect();
+    let mut constants = ConstantResolver::default();
+    for node in nodes.iter() {
+        walk(&mut constants, node);
+    }
+    let mut method_definitions: Vec<(String, bool)> = vec![];
+    let mut attributes: Vec<(String, bool)> = vec![];
+    for node in nodes.iter() {
+        let mut renamer = FileRenamer::new(target, &constants, &[], &[]);
+        walk(&mut renamer, node);
+        method_definitions.extend(renamer.method_definitions);
+        attributes.extend(renamer.attribute_definitions);
+    }
+    check_new_name_is_free(target, &constants, &method_definitions)?;
+
+    let mut renames: Vec<FileRename> = vec![];
+    for (path, result) in programs.iter() {
+        let mut file_rename = FileRename {
+            path: path.clone(),
+            new_path: None,
+            replacements: vec![],
+            refused: vec![],
+        };
+        let node = match &result.ast {
+            Some(node) => node,
+            None => {
+                renames.push(file_rename);
+                continue;
+            }
+        };
+        let mut renamer = FileRenamer::new(target, &constants, &method_definitions, &attributes);
+        walk(&mut renamer, node);
+        file_rename.replacements = renamer.replacements;
+        file_rename.refused = renamer.refused;
+        if let RenameTarget::Constant { path, new_name } = target {
+            let (replacements, defines_constant) =
+                rename_constant_references(node, &constants, path, new_name);
+            file_rename.replacements.extend(replacements);
+            let name = path.rsplit("::").next().unwrap_or(path);
+            if move_files && defines_constant && file_stem(&file_rename.path) == snake_case(name) {
+                file_rename.new_path = Some(
+                    file_rename
+                        .path
+                        .with_file_name(format!("{}.rb", snake_case(new_name))),
+                );
+            }
+        }
+        file_rename
+            .replacements
+            .sort_by_key(|replacement| replacement.range.begin);
+        file_rename
+            .replacements
+            .dedup_by_key(|replacement| replacement.range.begin);
+        renames.push(file_rename);
+    }
+    if move_files {
+        update_requires(programs, &mut renames);
+    }
+    return Ok(renames);
+}
+
+fn check_new_name_is_free(
+    target: &RenameTarget,
+    constants: &ConstantResolver,
+    method_definitions: &[(String, bool)],
+) -> Result<(), String> {
+    match target {
+        RenameTarget::Constant { path, new_name } => {
+            let new_path = match path.rsplit_once("::") {
+                Some((namespace, _)) => format!("{}::{}", namespace, new_name),
+                None => new_name.clone(),
+            };
+            if constants.definitions_of(path).is_empty() {
+                return Err(format!("{} is not defined", path));
+            }
+            if !constants.definitions_of(&new_path).is_empty() {
+                return Err(format!("{} is already defined", new_path));
+            }
+        }
+        RenameTarget::Method {
+            owner: Some(owner),
+            singleton,
+            name,
+            ..
+        } => {
+            if !method_definitions
+                .iter()
+                .any(|definition| definition.0 == *owner && definition.1 == *singleton)
+            {
+                return Err(format!(
+                    "{}{}{} is not defined",
+                    owner,
+                    if *singleton { "." } else { "#" },
+                    name
+                ));
+            }
+        }
+        RenameTarget::Method { .. } => {}
+    }
+    return Ok(());
+}
+
+/** Replace the references of a constant, telling if the program defines it */
+fn rename_constant_references(
+    node: &Node,
+    constants: &ConstantResolver,
+    path: &str,
+    new_name: &str,
+) -> (Vec<Replacement>, bool) {
+    let mut replacements: Vec<Replacement> = vec![];
+    let mut defines_constant = false;
+    for reference in ConstantResolver::new(node).references.iter() {
+        if constants.resolve(reference).as_deref() != Some(path) {
+            continue;
+        }
+        let name_l = match &reference.node {
+            Node::Const(constant) => constant.name_l,
+            Node::Casgn(casgn) => casgn.name_l,
+            _ => continue,
+        };
+        defines_constant |= reference.is_definition();
+        replacements.push(Replacement {
+            range: name_l,
+            text: String::from(new_name),
+        });
+    }
+    return (replacements, defines_constant);
+}
+
+/** Point the `require` and `require_relative` of the moved files to their new path */
+fn update_requires(programs: &[(PathBuf, ParserResult)], renames: &mut Vec<FileRename>) {
+    let moves: Vec<(PathBuf, PathBuf)> = renames
+        .iter()
+        .filter_map(|rename| Some((rename.path.clone(), rename.new_path.clone()?)))
+        .collect();
+    if moves.is_empty() {
+        return;
+    }
+    for ((path, result), rename) in programs.iter().zip(renames.iter_mut()) {
+        let node = match &result.ast {
+            Some(node) => node,
+            None => continue,
+        };
+        let mut requires = RequireCollector::default();
+        walk(&mut requires, node);
+        for (method_name, string) in requires.requires {
+            let value = match &string {
+                Node::Str(str) => str.value.to_string_lossy(),
+                _ => continue,
+            };
+            let required = Path::new(value.trim_end_matches(".rb")).with_extension("rb");
+            for (old_path, new_path) in moves.iter() {
+                let matches = if method_name == "require_relative" {
+                    path.parent().map(|directory| directory.join(&required))
+                        == Some(old_path.clone())
+                } else {
+                    old_path.ends_with(&required)
+                };
+                if matches {
+                    let stem = file_stem(new_path);
+                    rename.replacements.push(Replacement {
+                        range: string_value_l(&string),
+                        text: match value.rsplit_once('/') {
+                            Some((directory, _)) => format!("{}/{}", directory, stem),
+                            None => stem,
+                        },
+                    });
+                }
+            }
+        }
+        rename
+            .replacements
+            .sort_by_key(|replacement| replacement.range.begin);
+    }
+}
+
+#[derive(Default)]
+struct RequireCollector {
+    requires: Vec<(String, Node)>,
+}
+
+impl<'a> Visitor<'a> for RequireCollector {
+    fn enter(&mut self, node: &'a Node, _path: &NodePath<'a>) -> bool {
+        match node {
+            Node::Send(send)
+                if send.recv.is_none()
+                    && matches!(send.method_name.as_str(), "require" | "require_relative") =>
+            {
+                if let Some(argument) = send.args.first() {
+                    self.requires
+                        .push((send.method_name.clone(), argument.clone()));
+                }
+            }
+            _ => {}
+        }
+        return true;
+    }
+}
+
+/** Find the definitions and uses of a method in a program, with the namespaces enclosing them */
+struct FileRenamer<'a> {
+    target: &'a RenameTarget,
+    constants: &'a ConstantResolver,
+    /** Namespaces defining a method with the renamed name, and whether it is a singleton one */
+    definitions: &'a [(String, bool)],
+    nesting: Vec<String>,
+    /** Whether `self` is the class or module itself rather than one of its instances */
+    singleton_self: bool,
+    /** Whether methods defined with `def name` are singleton methods (`class << self`) */
+    singleton_defs: bool,
+    method_definitions: Vec<(String, bool)>,
+    /** Namespaces defining the renamed method with `attr_*`, and whether with `attr_accessor` */
+    attributes: &'a [(String, bool)],
+    attribute_definitions: Vec<(String, bool)>,
+    replacements: Vec<Replacement>,
+    refused: Vec<RefusedSite>,
+}
+
+/** Whether a use of a method name refers to the renamed method */
+enum NameUse {
+    Renamed,
+    Unrelated,
+    Ambiguous,
+}
+
+impl<'a> FileRenamer<'a> {
+    fn new(
+        target: &'a RenameTarget,
+        constants: &'a ConstantResolver,
+        definitions: &'a [(String, bool)],
+        attributes: &'a [(String, bool)],
+    ) -> Self {
+        return FileRenamer {
+            target,
+            constants,
+            definitions,
+            nesting: vec![],
+            singleton_self: false,
+            singleton_defs: false,
+            method_definitions: vec![],
+            attributes,
+            attribute_definitions: vec![],
+            replacements: vec![],
+            refused: vec![],
+        };
+    }
+
+    fn namespace(&self) -> String {
+        return self.nesting.last().cloned().unwrap_or_default();
+    }
+
+    /** Full path of a constant written in the current nesting */
+    fn constant_path(&self, node: &Node) -> String {
+        return self
+            .constants
+            .resolve_path(node, &self.nesting)
+            .unwrap_or_else(|| written_path(node));
+    }
+
+    fn enter_namespace(&mut self, name: &Node, body: &Option<Box<Node>>) {
+        let path = match (self.nesting.last(), name) {
+            (Some(namespace), Node::Const(constant))
+                if !matches!(constant.scope.as_deref(), Some(Node::Cbase(_))) =>
+            {
+                format!("{}::{}", namespace, written_path(name))
+            }
+            _ => written_path(name),
+        };
+        if let Some(body) = body {
+            let singleton = (self.singleton_self, self.singleton_defs);
+            self.nesting.push(path);
+            self.singleton_self = true;
+            self.singleton_defs = false;
+            walk(self, body);
+            self.nesting.pop();
+            (self.singleton_self, self.singleton_defs) = singleton;
+        }
+    }
+
+    fn visit_method_body(&mut self, body: &Option<Box<Node>>, singleton: bool) {
+        if let Some(body) = body {
+            let singleton_self = self.singleton_self;
+            self.singleton_self = singleton;
+            walk(self, body);
+            self.singleton_self = singleton_self;
+        }
+    }
+
+    fn define_method(&mut self, name: &str, singleton: bool, name_l: Loc) {
+        if let Some(new_name) = self.record_definition(name, singleton) {
+            self.replacements.push(Replacement {
+                range: name_l,
+                text: new_name,
+            });
+        }
+    }
+
+    /** Record a definition of the renamed method, returning its new name when it is renamed */
+    fn record_definition(&mut self, name: &str, singleton: bool) -> Option<String> {
+        let (owner, target_singleton, target_name) = match self.target {
+            RenameTarget::Method {
+                owner,
+                singleton,
+                name,
+                ..
+            } => (owner, *singleton, name),
+            _ => return None,
+        };
+        if name != target_name {
+            return None;
+        }
+        let namespace = self.namespace();
+        self.method_definitions.push((namespace.clone(), singleton));
+        let renamed = match owner {
+            Some(owner) => *owner == namespace && singleton == target_singleton,
+            None => true,
+        };
+        if !renamed {
+            return None;
+        }
+        return self.renamed_name(name);
+    }
+
+    /**
+     * New name of a method name when it is the renamed one, the setter of an attribute defined by
+     * `attr_accessor` being renamed with its reader
+     */
+    fn renamed_name(&self, method_name: &str) -> Option<String> {
+        let (name, new_name) = match self.target {
+            RenameTarget::Method { name, new_name, .. } => (name, new_name),
+            _ => return None,
+        };
+        if method_name == name {
+            return Some(new_name.clone());
+        }
+        let (attribute, new_attribute) =
+            (name.trim_end_matches('='), new_name.trim_end_matches('='));
+        if !self.attributes.iter().any(|(_, accessor)| *accessor) {
+            return None;
+        }
+        if method_name == attribute {
+            return Some(String::from(new_attribute));
+        }
+        if method_name.strip_suffix('=') == Some(attribute) {
+            return Some(format!("{}=", new_attribute));
+        }
+        return None;
+    }
+
+    /** The instance variable of a renamed attribute is renamed in the namespaces defining it */
+    fn rename_instance_variable(&mut self, name: &str, name_l: Loc) {
+        let (attribute, new_attribute) = match self.target {
+            RenameTarget::Method { name, new_name, .. } => {
+                (name.trim_end_matches('='), new_name.trim_end_matches('='))
+            }
+            _ => return,
+        };
+        let namespace = self.namespace();
+        if self.singleton_self
+            || name.strip_prefix('@') != Some(attribute)
+            || !self.attributes.iter().any(|(owner, _)| *owner == namespace)
+        {
+            return;
+        }
+        self.replacements.push(Replacement {
+            range: name_l,
+            text: format!("@{}", new_attribute),
+        });
+    }
+
+    /** `attr_accessor :name` defines `name` and `name=`, the symbol is their definition */
+    fn define_attributes(&mut self, method_name: &str, args: &[Node]) {
+        for argument in args {
+            let attribute = match literal_name(argument) {
+                Some(attribute) => attribute,
+                // `attr :name, true` is the old way to define the setter too
+                None if method_name == "attr" => continue,
+                None => {
+                    self.refused.push(RefusedSite {
+                        description: format!("{} with a name computed at runtime", method_name),
+                        expression_l: *argument.expression(),
+                    });
+                    continue;
+                }
+            };
+            let mut defined: Vec<String> = vec![];
+            if method_name != "attr_writer" {
+                defined.push(attribute.clone());
+            }
+            if method_name == "attr_writer" || method_name == "attr_accessor" {
+                defined.push(format!("{}=", attribute));
+            }
+            for name in defined.iter() {
+                if let Some(new_name) = self.record_definition(name, self.singleton_defs) {
+                    self.attribute_definitions
+                        .push((self.namespace(), method_name == "attr_accessor"));
+                    self.replacements.push(Replacement {
+                        range: string_value_l(argument),
+                        text: String::from(new_name.trim_end_matches('=')),
+                    });
+                }
+            }
+        }
+    }
+
+    /** Namespace of a receiver (None for self) when known, and whether it is the class itself */
+    fn receiver_namespace(&self, recv: Option<&Node>) -> (Option<String>, bool) {
+        match recv {
+            None | Some(Node::Self_(_)) => return (Some(self.namespace()), self.singleton_self),
+            Some(constant @ Node::Const(_)) => return (Some(self.constant_path(constant)), true),
+            Some(_) => return (None, false),
+        }
+    }
+
+    /** Tell if a method of a namespace (None when unknown) is the renamed method */
+    fn name_use(&self, namespace: Option<String>, singleton: bool) -> NameUse {
+        let (owner, target_singleton) = match self.target {
+            RenameTarget::Method {
+                owner: Some(owner),
+                singleton,
+                ..
+            } => (owner, *singleton),
+            RenameTarget::Method { owner: None, .. } => return NameUse::Renamed,
+            _ => return NameUse::Unrelated,
+        };
+        if let Some(namespace) = namespace {
+            if namespace == *owner && singleton == target_singleton {
+                return NameUse::Renamed;
+            }
+            if self
+                .definitions
+                .iter()
+                .any(|definition| definition.0 == namespace && definition.1 == singleton)
+            {
+                return NameUse::Unrelated;
+            }
+        }
+        // Inherited or called on an unknown receiver: fine as long as no other class defines it
+        if self
+            .definitions
+            .iter()
+            .all(|definition| definition.0 == *owner && definition.1 == target_singleton)
+        {
+            return NameUse::Renamed;
+        }
+        return NameUse::Ambiguous;
+    }
+
+    fn rename_call(&mut self, method_name: &str, recv: Option<&Node>, send: &Node) {
+        let (selector_l, is_setter) = match send {
+            Node::Send(send) => (send.selector_l, send.operator_l.is_some()),
+            Node::CSend(send) => (send.selector_l, send.operator_l.is_some()),
+            _ => return,
+        };
+        // The selector of `self.name = value` is `name`, the method being `name=`
+        let (selector_l, new_name) = match (selector_l, self.renamed_name(method_name)) {
+            (Some(selector_l), Some(new_name)) => (selector_l, new_name),
+            _ => return,
+        };
+        let (namespace, singleton) = self.receiver_namespace(recv);
+        match self.name_use(namespace, singleton) {
+            NameUse::Renamed => self.replacements.push(Replacement {
+                range: selector_l,
+                text: match is_setter {
+                    true => String::from(new_name.trim_end_matches('=')),
+                    false => new_name,
+                },
+            }),
+            NameUse::Unrelated => {}
+            NameUse::Ambiguous => self.refused.push(RefusedSite {
+                description: format!("call to {} on a receiver of unknown class", method_name),
+                expression_l: *send.expression(),
+            }),
+        }
+    }
+
+    /** Rename a method name given as a symbol or string, the method being one of a namespace */
+    fn rename_name_argument(
+        &mut self,
+        argument: &Node,
+        (namespace, singleton): (Option<String>, bool),
+        method_name: &str,
+    ) {
+        if !matches!(self.target, RenameTarget::Method { .. }) {
+            return;
+        }
+        let value = literal_name(argument);
+        match value
+            .as_deref()
+            .map(|value| (value, self.renamed_name(value)))
+        {
+            Some((value, Some(new_name))) => match self.name_use(namespace, singleton) {
+                NameUse::Renamed => self.replacements.push(Replacement {
+                    range: string_value_l(argument),
+                    text: new_name,
+                }),
+                NameUse::Unrelated => {}
+                NameUse::Ambiguous => self.refused.push(RefusedSite {
+                    description: format!(
+                        "{} :{} for a receiver of unknown class",
+                        method_name, value
+                    ),
+                    expression_l: *argument.expression(),
+                }),
+            },
+            Some(_) => {}
+            None => self.refused.push(RefusedSite {
+                description: format!("{} with a name computed at runtime", method_name),
+                expression_l: *argument.expression(),
+            }),
+        }
+    }
+
+    fn rename_constant_argument(&mut self, argument: &Node, method_name: &str) {
+        let (path, new_name) = match self.target {
+            RenameTarget::Constant { path, new_name } => (path, new_name),
+            _ => return,
+        };
+        match literal_name(argument) {
+            Some(value) if value.trim_start_matches("::") == *path => {
+                let new_path = match path.rsplit_once("::") {
+                    Some((namespace, _)) => format!("{}::{}", namespace, new_name),
+                    None => new_name.clone(),
+                };
+                self.replacements.push(Replacement {
+                    range: string_value_l(argument),
+                    text: if value.starts_with("::") {
+                        format!("::{}", new_path)
+                    } else {
+                        new_path
+                    },
+                });
+            }
+            Some(_) => {}
+            None => self.refused.push(RefusedSite {
+                description: format!("{} with a name computed at runtime", method_name),
+                expression_l: *argument.expression(),
+            }),
+        }
+    }
+
+    fn rename_send_arguments(&mut self, method_name: &str, recv: Option<&Node>, args: &[Node]) {
+        let argument = match args.first() {
+            Some(argument) => argument,
+            None => return,
+        };
+        if METHOD_NAME_SENDS.contains(&method_name) {
+            self.rename_name_argument(argument, self.receiver_namespace(recv), method_name);
+        } else if CONSTANT_NAME_SENDS.contains(&method_name) {
+            self.rename_constant_argument(argument, method_name);
+        } else if recv.is_none() && ATTRIBUTE_SENDS.contains(&method_name) {
+            self.define_attributes(method_name, args);
+        } else if recv.is_none() && INSTANCE_METHOD_NAME_SENDS.contains(&method_name) {
+            let arguments = match method_name {
+                "define_method" | "instance_method" | "public_instance_method" => &args[..1],
+                _ => args,
+            };
+            // The names are methods of the instances of self (of self in `class << self`)
+            let receiver = (Some(self.namespace()), self.singleton_defs);
+            for argument in arguments {
+                if !matches!(argument, Node::Def(_) | Node::Defs(_)) {
+                    self.rename_name_argument(argument, receiver.clone(), method_name);
+                }
+            }
+        }
+    }
+}
+
+/** Namespaces and method bodies are walked by hand to track `self` */
+impl<'a, 'n> Visitor<'n> for FileRenamer<'a> {
+    fn enter(&mut self, node: &'n Node, _path: &NodePath<'n>) -> bool {
+        match node {
+            Node::Class(class) => {
+                if let Some(superclass) = &class.superclass {
+                    walk(self, superclass);
+                }
+                self.enter_namespace(&class.name, &class.body);
+                return false;
+            }
+            Node::Module(module) => {
+                self.enter_namespace(&module.name, &module.body);
+                return false;
+            }
+            Node::SClass(sclass) => {
+                walk(self, &sclass.expr);
+                if let (Some(body), Node::Self_(_)) = (&sclass.body, sclass.expr.as_ref()) {
+                    let singleton = (self.singleton_self, self.singleton_defs);
+                    self.singleton_self = true;
+                    self.singleton_defs = true;
+                    walk(self, body);
+                    (self.singleton_self, self.singleton_defs) = singleton;
+                }
+                return false;
+            }
+            Node::Def(def) => {
+                self.define_method(&def.name, self.singleton_defs, def.name_l);
+                if let Some(args) = &def.args {
+                    walk(self, args);
+                }
+                self.visit_method_body(&def.body, self.singleton_defs);
+                return false;
+            }
+            Node::Defs(defs) => {
+                let defines_in_namespace = match defs.definee.as_ref() {
+                    Node::Self_(_) => true,
+                    constant @ Node::Const(_) => self.constant_path(constant) == self.namespace(),
+                    _ => false,
+                };
+                if defines_in_namespace {
+                    self.define_method(&defs.name, true, defs.name_l);
+                }
+                walk(self, &defs.definee);
+                if let Some(args) = &defs.args {
+                    walk(self, args);
+                }
+                self.visit_method_body(&defs.body, true);
+                return false;
+            }
+            Node::Send(send) => {
+                self.rename_call(&send.method_name, send.recv.as_deref(), node);
+                self.rename_send_arguments(&send.method_name, send.recv.as_deref(), &send.args);
+            }
+            Node::CSend(send) => {
+                self.rename_call(&send.method_name, Some(&send.recv), node);
+                self.rename_send_arguments(&send.method_name, Some(&send.recv), &send.args);
+            }
+            Node::Alias(alias) => {
+                let receiver = (Some(self.namespace()), self.singleton_defs);
+                for name in [&alias.to, &alias.from] {
+                    if let Node::Sym(_) = name.as_ref() {
+                        self.rename_name_argument(name, receiver.clone(), "alias");
+                    }
+                }
+            }
+            Node::BlockPass(block_pass) => {
+                // `items.map(&:name)` calls `name` on the items
+                if let Some(value @ Node::Sym(_)) = block_pass.value.as_deref() {
+                    self.rename_name_argument(value, (None, false), "block argument");
+                }
+            }
+            Node::Ivar(ivar) => self.rename_instance_variable(&ivar.name, ivar.expression_l),
+            Node::Ivasgn(asgn) => self.rename_instance_variable(&asgn.name, asgn.name_l),
+            Node::Undef(undef) => {
+                let receiver = (Some(self.namespace()), self.singleton_defs);
+                for name in undef.names.iter() {
+                    if let Node::Sym(_) = name {
+                        self.rename_name_argument(name, receiver.clone(), "undef");
+                    }
+                }
+            }
+            _ => {}
+        }
+        return true;
+    }
+}
+
+/** Value of a symbol or string literal */
+fn literal_name(node: &Node) -> Option<String> {
+    match node {
+        Node::Sym(sym) => Some(sym.name.to_string_lossy()),
+        Node::Str(str) => Some(str.value.to_string_lossy()),
+        _ => None,
+    }
+}
+
+/** Location of the value of a symbol or string, without its quotes and colon */
+fn string_value_l(node: &Node) -> Loc {
+    let (begin_l, end_l, expression_l) = match node {
+        Node::Sym(sym) => (sym.begin_l, sym.end_l, sym.expression_l),
+        Node::Str(str) => (str.begin_l, str.end_l, str.expression_l),
+        _ => return *node.expression(),
+    };
+    return Loc {
+        begin: begin_l.map_or(expression_l.begin, |begin_l| begin_l.end),
+        end: end_l.map_or(expression_l.end, |end_l| end_l.begin),
+    };
+}
+
+fn file_stem(path: &Path) -> String {
+    return path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+}
+
+/** File name of a constant, the way Zeitwerk and Rails name them (`HTTPClient` => `http_client`) */
+fn snake_case(name: &str) -> String {
+    let characters: Vec<char> = name.chars().collect();
+    let mut snake_case = String::new();
+    for (i, &character) in characters.iter().enumerate() {
+        if character.is_ascii_uppercase() && i > 0 {
+            let previous = characters[i - 1];
+            let next_is_lowercase = characters
+                .get(i + 1)
+                .map_or(false, |next| next.is_ascii_lowercase());
+            if previous.is_ascii_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_ascii_uppercase() && next_is_lowercase)
+            {
+                snake_case.push('_');
+            }
+        }
+        snake_case.push(character.to_ascii_lowercase());
+    }
+    return snake_case;
+}
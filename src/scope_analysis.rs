@@ -0,0 +1,600 @@
+use std::{
+    collections::HashSet,
+    io::{BufWriter, Write},
+};
+
+use lib_ruby_parser::{nodes::Send, source::DecodedInput, Loc, Node};
+
+use crate::visitor::{walk, NodePath, Visitor};
+
+pub type ScopeId = usize;
+pub type VariableId = usize;
+
+/** Construct introducing a scope for local variables */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeKind {
+    TopLevel,
+    Def,
+    Defs,
+    Block,
+    Lambda,
+    Numblock,
+    Class,
+    Module,
+    SClass,
+}
+
+impl ScopeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScopeKind::TopLevel => "top level",
+            ScopeKind::Def => "def",
+            ScopeKind::Defs => "defs",
+            ScopeKind::Block => "block",
+            ScopeKind::Lambda => "lambda",
+            ScopeKind::Numblock => "numbered parameters block",
+            ScopeKind::Class => "class",
+            ScopeKind::Module => "module",
+            ScopeKind::SClass => "singleton class",
+        }
+    }
+
+    /** Blocks see the local variables of the scope they are written in, other scopes do not */
+    pub fn is_transparent(&self) -> bool {
+        match self {
+            ScopeKind::Block | ScopeKind::Lambda | ScopeKind::Numblock => true,
+            _ => false,
+        }
+    }
+}
+
+/** How a local variable is introduced */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariableKind {
+    /** Positional, rest or block argument */
+    Argument,
+    /** Keyword argument, its name is part of the interface of the method */
+    KeywordArgument,
+    /** Block local variable declared after `;` in the block parameters */
+    BlockLocal,
+    /** Variable bound by a pattern (`in [x, *]`) */
+    MatchVariable,
+    /** Variable introduced by an assignment */
+    Local,
+    /** Variable assigned by a named capture of a regexp (`/(?<year>\d+)/ =~ s`) */
+    NamedCapture,
+}
+
+impl VariableKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VariableKind::Argument => "argument",
+            VariableKind::KeywordArgument => "keyword argument",
+            VariableKind::BlockLocal => "block local variable",
+            VariableKind::MatchVariable => "match variable",
+            VariableKind::Local => "local variable",
+            VariableKind::NamedCapture => "named capture",
+        }
+    }
+}
+
+/** Read or write of a local variable */
+#[derive(Debug, Clone, Copy)]
+pub struct Occurrence {
+    pub expression_l: Loc,
+    pub is_write: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub name: String,
+    pub kind: VariableKind,
+    pub scope: ScopeId,
+    /** Also written by a named capture, the name is in the regexp and cannot change */
+    pub reserved: bool,
+    /** Declaration first, then the other reads and writes in source order */
+    pub occurrences: Vec<Occurrence>,
+}
+
+/** Call giving access to the local variables by name, renaming them would change the program */
+#[derive(Debug, Clone)]
+pub struct UnsafeConstruct {
+    pub description: String,
+    pub expression_l: Loc,
+}
+
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub kind: ScopeKind,
+    pub parent: Option<ScopeId>,
+    pub children: Vec<ScopeId>,
+    pub expression_l: Loc,
+    /** Variables declared in this scope, in declaration order */
+    pub variables: Vec<VariableId>,
+    /** Names of the methods called without receiver, a variable with the same name would hide them */
+    pub method_calls: HashSet<String>,
+    /** Local variable reads that are not declared by the code (`_1`, named regexp captures) */
+    pub unresolved: Vec<(String, Loc)>,
+    pub unsafe_constructs: Vec<UnsafeConstruct>,
+}
+
+/** Scopes of a program with the declaration and uses of each local variable */
+#[derive(Debug)]
+pub struct ScopeTree {
+    pub scopes: Vec<Scope>,
+    pub variables: Vec<Variable>,
+    current_scope: ScopeId,
+}
+
+impl ScopeTree {
+    pub fn new(node: &Node) -> Self {
+        let mut tree = ScopeTree {
+            scopes: vec![],
+            variables: vec![],
+            current_scope: 0,
+        };
+        tree.push_scope(ScopeKind::TopLevel, *node.expression());
+        walk(&mut tree, node);
+        return tree;
+    }
+
+    /** Variable read or written at a position (`Lvar`, `Lvasgn`, `MatchVar` or argument node) */
+    pub fn variable_at(&self, offset: usize) -> Option<VariableId> {
+        let mut found: Option<(VariableId, usize)> = None;
+        for (variable_id, variable) in self.variables.iter().enumerate() {
+            for occurrence in variable.occurrences.iter() {
+                let expression_l = occurrence.expression_l;
+                // An assignment contains its value, the innermost occurrence is the one at the position
+                if expression_l.begin <= offset
+                    && offset < expression_l.end
+                    && found.map_or(true, |(_, size)| expression_l.size() < size)
+                {
+                    found = Some((variable_id, expression_l.size()));
+                }
+            }
+        }
+        return found.map(|(variable_id, _)| variable_id);
+    }
+
+    /** Innermost scope containing a position */
+    pub fn scope_at(&self, offset: usize) -> ScopeId {
+        let mut scope_id = 0;
+        'descend: loop {
+            for &child in self.scopes[scope_id].children.iter() {
+                let expression_l = self.scopes[child].expression_l;
+                if expression_l.begin <= offset && offset < expression_l.end {
+                    scope_id = child;
+                    continue 'descend;
+                }
+            }
+            return scope_id;
+        }
+    }
+
+    /** Scope owning the local variables a scope can see (the one of the def around a block) */
+    pub fn local_root(&self, scope_id: ScopeId) -> ScopeId {
+        let mut scope_id = scope_id;
+        while self.scopes[scope_id].kind.is_transparent() {
+            match self.scopes[scope_id].parent {
+                Some(parent) => scope_id = parent,
+                None => break,
+            }
+        }
+        return scope_id;
+    }
+
+    /** Variables a scope can read, innermost declarations first */
+    pub fn visible_variables(&self, scope_id: ScopeId) -> Vec<VariableId> {
+        let mut visible: Vec<VariableId> = vec![];
+        let mut names: HashSet<&str> = HashSet::new();
+        let mut scope_id = Some(scope_id);
+        while let Some(id) = scope_id {
+            let scope = &self.scopes[id];
+            for &variable_id in scope.variables.iter() {
+                if names.insert(&self.variables[variable_id].name) {
+                    visible.push(variable_id);
+                }
+            }
+            scope_id = if scope.kind.is_transparent() {
+                scope.parent
+            } else {
+                None
+            };
+        }
+        return visible;
+    }
+
+    /** Scopes sharing the local variables of a root scope: the root and its nested blocks */
+    pub fn transparent_descendants(&self, scope_id: ScopeId) -> Vec<ScopeId> {
+        let mut scopes = vec![scope_id];
+        let mut index = 0;
+        while index < scopes.len() {
+            for &child in self.scopes[scopes[index]].children.iter() {
+                if self.scopes[child].kind.is_transparent() {
+                    scopes.push(child);
+                }
+            }
+            index += 1;
+        }
+        return scopes;
+    }
+
+    /** Constructs preventing the variables visible from a scope to be renamed */
+    pub fn unsafe_constructs(&self, scope_id: ScopeId) -> Vec<&UnsafeConstruct> {
+        return self
+            .transparent_descendants(self.local_root(scope_id))
+            .into_iter()
+            .flat_map(|id| self.scopes[id].unsafe_constructs.iter())
+            .collect();
+    }
+
+    pub fn is_renaming_safe(&self, scope_id: ScopeId) -> bool {
+        return self.unsafe_constructs(scope_id).is_empty();
+    }
+
+    /** Write the scopes with their variables, or the occurrences of the variable at a position */
+    pub fn write<W: Write>(
+        &self,
+        input: &DecodedInput,
+        position: Option<usize>,
+        writer: &mut BufWriter<W>,
+    ) -> Result<(), std::io::Error> {
+        let line_col = |loc: Loc| {
+            let (line, column) = input.line_col_for_pos(loc.begin).unwrap_or((0, 0));
+            return format!("{}:{}", line + 1, column + 1);
+        };
+        if let Some(offset) = position {
+            let scope = &self.scopes[self.scope_at(offset)];
+            writeln!(
+                writer,
+                "{} at {}",
+                scope.kind.as_str(),
+                line_col(scope.expression_l)
+            )?;
+            if let Some(variable_id) = self.variable_at(offset) {
+                let variable = &self.variables[variable_id];
+                writeln!(
+                    writer,
+                    "  {} {} of the {} at {}",
+                    variable.kind.as_str(),
+                    variable.name,
+                    self.scopes[variable.scope].kind.as_str(),
+                    line_col(self.scopes[variable.scope].expression_l)
+                )?;
+                for occurrence in variable.occurrences.iter() {
+                    let access = if occurrence.is_write { "write" } else { "read" };
+                    writeln!(
+                        writer,
+                        "    {} {}",
+                        access,
+                        line_col(occurrence.expression_l)
+                    )?;
+                }
+            }
+            return Ok(());
+        }
+        for scope in self.scopes.iter() {
+            let mut depth = 0;
+            let mut parent = scope.parent;
+            while let Some(id) = parent {
+                depth += 1;
+                parent = self.scopes[id].parent;
+            }
+            let indent = "  ".repeat(depth);
+            writeln!(
+                writer,
+                "{}{} at {}",
+                indent,
+                scope.kind.as_str(),
+                line_col(scope.expression_l)
+            )?;
+            for &variable_id in scope.variables.iter() {
+                let variable = &self.variables[variable_id];
+                let writes = variable
+                    .occurrences
+                    .iter()
+                    .filter(|occurrence| occurrence.is_write)
+                    .count();
+                writeln!(
+                    writer,
+                    "{}  {} {}: {} writes, {} reads",
+                    indent,
+                    variable.kind.as_str(),
+                    variable.name,
+                    writes,
+                    variable.occurrences.len() - writes
+                )?;
+            }
+            for unsafe_construct in scope.unsafe_constructs.iter() {
+                writeln!(
+                    writer,
+                    "{}  renaming is unsafe: {} at {}",
+                    indent,
+                    unsafe_construct.description,
+                    line_col(unsafe_construct.expression_l)
+                )?;
+            }
+        }
+        return Ok(());
+    }
+
+    fn push_scope(&mut self, kind: ScopeKind, expression_l: Loc) {
+        let parent = if self.scopes.is_empty() {
+            None
+        } else {
+            Some(self.current_scope)
+        };
+        let scope_id = self.scopes.len();
+        self.scopes.push(Scope {
+            kind,
+            parent,
+            children: vec![],
+            expression_l,
+            variables: vec![],
+            method_calls: HashSet::new(),
+            unresolved: vec![],
+            unsafe_constructs: vec![],
+        });
+        if let Some(parent) = parent {
+            self.scopes[parent].children.push(scope_id);
+        }
+        self.current_scope = scope_id;
+    }
+
+    fn pop_scope(&mut self) {
+        if let Some(parent) = self.scopes[self.current_scope].parent {
+            self.current_scope = parent;
+        }
+    }
+
+    fn find_variable(&self, name: &str) -> Option<VariableId> {
+        return self
+            .visible_variables(self.current_scope)
+            .into_iter()
+            .find(|&variable_id| self.variables[variable_id].name == name);
+    }
+
+    /** Declare a variable in the current scope, shadowing the outer variables with the same name */
+    fn declare(&mut self, name: &str, kind: VariableKind, expression_l: Loc) {
+        let variable_id = self.variables.len();
+        self.variables.push(Variable {
+            name: String::from(name),
+            kind,
+            scope: self.current_scope,
+            reserved: false,
+            occurrences: vec![],
+        });
+        self.scopes[self.current_scope].variables.push(variable_id);
+        self.add_occurrence(variable_id, expression_l, true);
+    }
+
+    /** Assignments declare the variable unless it is already visible */
+    fn assign(&mut self, name: &str, kind: VariableKind, expression_l: Loc) {
+        match self.find_variable(name) {
+            Some(variable_id) => self.add_occurrence(variable_id, expression_l, true),
+            None => self.declare(name, kind, expression_l),
+        }
+    }
+
+    /** Named captures assign the variable when visible, declare it otherwise */
+    fn reserve(&mut self, name: &str, expression_l: Loc) {
+        let variable_id = match self.find_variable(name) {
+            Some(variable_id) => {
+                self.add_occurrence(variable_id, expression_l, true);
+                variable_id
+            }
+            None => {
+                self.declare(name, VariableKind::NamedCapture, expression_l);
+                self.variables.len() - 1
+            }
+        };
+        self.variables[variable_id].reserved = true;
+    }
+
+    fn add_occurrence(&mut self, variable_id: VariableId, expression_l: Loc, is_write: bool) {
+        self.variables[variable_id].occurrences.push(Occurrence {
+            expression_l,
+            is_write,
+        });
+    }
+
+    fn add_unsafe_construct(&mut self, description: String, expression_l: Loc) {
+        self.scopes[self.current_scope]
+            .unsafe_constructs
+            .push(UnsafeConstruct {
+                description,
+                expression_l,
+            });
+    }
+
+    fn visit_scope(&mut self, kind: ScopeKind, expression_l: Loc, nodes: &[Option<&Node>]) {
+        self.push_scope(kind, expression_l);
+        for node in nodes.iter().flatten() {
+            walk(self, node);
+        }
+        self.pop_scope();
+    }
+
+    fn record_send(&mut self, node: &Send) {
+        if node.recv.is_none() {
+            self.scopes[self.current_scope]
+                .method_calls
+                .insert(node.method_name.clone());
+        }
+        let evaluates_string = node
+            .args
+            .iter()
+            .any(|arg| matches!(arg, Node::Str(_) | Node::Dstr(_) | Node::Heredoc(_)));
+        let is_unsafe = match node.method_name.as_str() {
+            "binding" | "local_variables" => node.recv.is_none(),
+            "eval" => true,
+            "instance_eval" | "class_eval" | "module_eval" => evaluates_string,
+            _ => false,
+        };
+        if is_unsafe {
+            self.add_unsafe_construct(format!("call to {}", node.method_name), node.expression_l);
+        }
+        // Without onigmo the parser does not see the captures, `=~` is a plain call
+        if let (Some(Node::Regexp(regexp)), "=~") =
+            (node.recv.as_deref(), node.method_name.as_str())
+        {
+            if regexp
+                .parts
+                .iter()
+                .any(|part| !matches!(part, Node::Str(_)))
+            {
+                self.add_unsafe_construct(
+                    String::from("interpolated regexp matched with =~"),
+                    regexp.expression_l,
+                );
+                return;
+            }
+            for part in regexp.parts.iter() {
+                if let Node::Str(str) = part {
+                    for name in named_captures(&str.value.to_string_lossy()) {
+                        self.reserve(&name, regexp.expression_l);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/** Names of the `(?<name>...)` groups of a regexp source */
+fn named_captures(source: &str) -> Vec<String> {
+    let mut names = vec![];
+    for (index, _) in source.match_indices("(?<") {
+        let name: String = source[index + 3..]
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_')
+            .collect();
+        let is_group = source[index + 3 + name.len()..].starts_with('>');
+        if is_group && !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) {
+            names.push(name);
+        }
+    }
+    return names;
+}
+
+/** Scopes are walked by hand, the nodes before them (receiver, name, superclass) being outside */
+impl<'a> Visitor<'a> for ScopeTree {
+    fn enter(&mut self, node: &'a Node, _path: &NodePath<'a>) -> bool {
+        match node {
+            Node::Def(def) => {
+                self.visit_scope(
+                    ScopeKind::Def,
+                    def.expression_l,
+                    &[def.args.as_deref(), def.body.as_deref()],
+                );
+                return false;
+            }
+            Node::Defs(defs) => {
+                walk(self, &defs.definee);
+                self.visit_scope(
+                    ScopeKind::Defs,
+                    defs.expression_l,
+                    &[defs.args.as_deref(), defs.body.as_deref()],
+                );
+                return false;
+            }
+            Node::Class(class) => {
+                walk(self, &class.name);
+                if let Some(superclass) = &class.superclass {
+                    walk(self, superclass);
+                }
+                self.visit_scope(
+                    ScopeKind::Class,
+                    class.expression_l,
+                    &[class.body.as_deref()],
+                );
+                return false;
+            }
+            Node::Module(module) => {
+                walk(self, &module.name);
+                self.visit_scope(
+                    ScopeKind::Module,
+                    module.expression_l,
+                    &[module.body.as_deref()],
+                );
+                return false;
+            }
+            Node::SClass(sclass) => {
+                walk(self, &sclass.expr);
+                self.visit_scope(
+                    ScopeKind::SClass,
+                    sclass.expression_l,
+                    &[sclass.body.as_deref()],
+                );
+                return false;
+            }
+            Node::Block(block) => {
+                walk(self, &block.call);
+                let kind = match block.call.as_ref() {
+                    Node::Lambda(_) => ScopeKind::Lambda,
+                    _ => ScopeKind::Block,
+                };
+                self.visit_scope(
+                    kind,
+                    block.expression_l,
+                    &[block.args.as_deref(), block.body.as_deref()],
+                );
+                return false;
+            }
+            Node::Numblock(block) => {
+                walk(self, &block.call);
+                self.visit_scope(
+                    ScopeKind::Numblock,
+                    block.expression_l,
+                    &[Some(&block.body)],
+                );
+                return false;
+            }
+            Node::Arg(arg) => self.declare(&arg.name, VariableKind::Argument, arg.expression_l),
+            Node::Optarg(arg) => self.declare(&arg.name, VariableKind::Argument, arg.expression_l),
+            Node::Kwarg(arg) => {
+                self.declare(&arg.name, VariableKind::KeywordArgument, arg.expression_l)
+            }
+            Node::Kwoptarg(arg) => {
+                self.declare(&arg.name, VariableKind::KeywordArgument, arg.expression_l)
+            }
+            Node::Restarg(arg) => {
+                if let Some(name) = &arg.name {
+                    self.declare(name, VariableKind::Argument, arg.expression_l);
+                }
+            }
+            Node::Kwrestarg(arg) => {
+                if let Some(name) = &arg.name {
+                    self.declare(name, VariableKind::Argument, arg.expression_l);
+                }
+            }
+            Node::Blockarg(arg) => {
+                if let Some(name) = &arg.name {
+                    self.declare(name, VariableKind::Argument, arg.expression_l);
+                }
+            }
+            Node::Shadowarg(arg) => {
+                self.declare(&arg.name, VariableKind::BlockLocal, arg.expression_l)
+            }
+            Node::Lvasgn(asgn) => self.assign(&asgn.name, VariableKind::Local, asgn.expression_l),
+            Node::MatchVar(var) => {
+                self.assign(&var.name, VariableKind::MatchVariable, var.expression_l)
+            }
+            Node::Lvar(lvar) => match self.find_variable(&lvar.name) {
+                Some(variable_id) => self.add_occurrence(variable_id, lvar.expression_l, false),
+                None => self.scopes[self.current_scope]
+                    .unresolved
+                    .push((lvar.name.clone(), lvar.expression_l)),
+            },
+            Node::MatchWithLvasgn(node) => {
+                // The variables are named by the captures of the regexp
+                self.add_unsafe_construct(
+                    String::from("regexp with named captures"),
+                    node.expression_l,
+                );
+            }
+            Node::Send(send) => self.record_send(send),
+            _ => {}
+        }
+        return true;
+    }
+}
@@ -0,0 +1,741 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use lib_ruby_parser::{
+    nodes::Send, source::DecodedInput, Bytes, Loc, Node, Parser, ParserOptions, ParserResult,
+};
+
+use crate::{
+    builder as b,
+    code_writer::code_to_string,
+    minifier::structure,
+    rename::Replacement,
+    visitor::{
+        children_mut, children_with_steps, walk, walk_mut, NodePath, PathStep, Visitor, VisitorMut,
+    },
+};
+
+/** Identifier standing for `$name` once the pattern is given to the parser */
+const METAVARIABLE_PREFIX: &str = "__metavariable_";
+
+/**
+ * Ruby code with metavariables: `$name` matches any expression (or any method, symbol or
+ * variable name where a name is expected), every use of the same metavariable matching the same
+ * code. `$_` matches anything without capturing, `*$name` matches any number of arguments,
+ * elements or statements, and `$$name` is the global variable `$name`.
+ */
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    node: Node,
+}
+
+/** Code matched by a metavariable */
+#[derive(Debug, Clone)]
+enum Capture {
+    Node(Node),
+    Nodes(Vec<Node>),
+    Name(String),
+}
+
+type Captures = HashMap<String, Capture>;
+
+impl Pattern {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let options = ParserOptions {
+            buffer_name: String::from("(pattern)"),
+            record_tokens: false,
+            ..Default::default()
+        };
+        let result = Parser::new(replace_metavariables(source), options).do_parse();
+        if let Some(error) = result
+            .diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic.is_error())
+        {
+            return Err(format!(
+                "invalid pattern `{}`: {}",
+                source.trim(),
+                error.render_message()
+            ));
+        }
+        match result.ast {
+            Some(node) => match *node {
+                Node::Begin(begin) if begin.begin_l.is_none() => {
+                    return Err(format!(
+                        "invalid pattern `{}`: expected a single expression",
+                        source.trim()
+                    ))
+                }
+                node => return Ok(Pattern { node }),
+            },
+            None => return Err(String::from("empty pattern")),
+        }
+    }
+
+    /** Names of the metavariables used by the pattern */
+    fn metavariables(&self) -> Vec<String> {
+        let mut collector = MetavariableCollector { names: vec![] };
+        walk(&mut collector, &self.node);
+        return collector.names;
+    }
+
+    fn match_node(&self, node: &Node) -> Option<Captures> {
+        let mut captures: Captures = HashMap::new();
+        if match_node(&self.node, node, &mut captures) {
+            return Some(captures);
+        }
+        return None;
+    }
+}
+
+/** Turn `$name` into an identifier the parser accepts, `$$name` into the global `$name` */
+fn replace_metavariables(source: &str) -> String {
+    let mut replaced = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(index) = rest.find('$') {
+        replaced.push_str(&rest[..index]);
+        rest = &rest[(index + 1)..];
+        if let Some(global) = rest.strip_prefix('$') {
+            replaced.push('$');
+            rest = global;
+            continue;
+        }
+        let name_end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if name_end == 0 || rest.starts_with(|c: char| c.is_ascii_digit()) {
+            replaced.push('$');
+            continue;
+        }
+        replaced.push_str(METAVARIABLE_PREFIX);
+        replaced.push_str(&rest[..name_end]);
+        rest = &rest[name_end..];
+    }
+    replaced.push_str(rest);
+    return replaced;
+}
+
+fn metavariable_name(name: &str) -> Option<&str> {
+    return name.strip_prefix(METAVARIABLE_PREFIX);
+}
+
+/** Metavariable standing for a whole expression */
+fn metavariable(node: &Node) -> Option<&str> {
+    match node {
+        Node::Send(send) if send.recv.is_none() && send.args.is_empty() => {
+            metavariable_name(&send.method_name)
+        }
+        Node::Lvar(lvar) => metavariable_name(&lvar.name),
+        _ => None,
+    }
+}
+
+/** Metavariable standing for any number of nodes (`*$name`) */
+fn list_metavariable(node: &Node) -> Option<&str> {
+    match node {
+        Node::Splat(splat) => splat.value.as_deref().and_then(metavariable),
+        _ => None,
+    }
+}
+
+/** Name of a node that a metavariable can stand for */
+fn node_name(node: &Node) -> Option<String> {
+    match node {
+        Node::Send(send) => Some(send.method_name.clone()),
+        Node::CSend(send) => Some(send.method_name.clone()),
+        Node::Def(def) => Some(def.name.clone()),
+        Node::Defs(defs) => Some(defs.name.clone()),
+        Node::Lvasgn(asgn) => Some(asgn.name.clone()),
+        Node::Arg(arg) => Some(arg.name.clone()),
+        Node::Optarg(arg) => Some(arg.name.clone()),
+        Node::Shadowarg(arg) => Some(arg.name.clone()),
+        Node::Sym(sym) => Some(sym.name.to_string_lossy()),
+        _ => None,
+    }
+}
+
+fn set_node_name(node: &mut Node, name: String) {
+    match node {
+        Node::Send(send) => send.method_name = name,
+        Node::CSend(send) => send.method_name = name,
+        Node::Def(def) => def.name = name,
+        Node::Defs(defs) => defs.name = name,
+        Node::Lvasgn(asgn) => asgn.name = name,
+        Node::Arg(arg) => arg.name = name,
+        Node::Optarg(arg) => arg.name = name,
+        Node::Shadowarg(arg) => arg.name = name,
+        Node::Sym(sym) => sym.name = Bytes::new(name.into_bytes()),
+        _ => {}
+    }
+}
+
+/** Record what a metavariable matches, telling if it is the same as its previous matches */
+fn bind(captures: &mut Captures, name: &str, capture: Capture) -> bool {
+    if name == "_" {
+        return true;
+    }
+    match (captures.get(name), &capture) {
+        (None, _) => {
+            captures.insert(String::from(name), capture);
+            return true;
+        }
+        (Some(Capture::Node(bound)), Capture::Node(node)) => structure(bound) == structure(node),
+        (Some(Capture::Nodes(bound)), Capture::Nodes(nodes)) => {
+            bound.len() == nodes.len()
+                && bound
+                    .iter()
+                    .zip(nodes.iter())
+                    .all(|(bound, node)| structure(bound) == structure(node))
+        }
+        (Some(Capture::Name(bound)), Capture::Name(name)) => bound == name,
+        // `$x = 1; $x` reads the variable it assigned
+        (Some(Capture::Name(bound)), Capture::Node(Node::Lvar(lvar))) => *bound == lvar.name,
+        (Some(Capture::Node(Node::Lvar(lvar))), Capture::Name(name)) => lvar.name == *name,
+        _ => false,
+    }
+}
+
+/** Match a node against a pattern, whatever the locations and the way the code is written */
+fn match_node(pattern: &Node, node: &Node, captures: &mut Captures) -> bool {
+    if let Some(name) = metavariable(pattern) {
+        return bind(captures, name, Capture::Node(node.clone()));
+    }
+    if pattern.str_type() != node.str_type() {
+        return false;
+    }
+    if let Some(pattern_name) = node_name(pattern) {
+        let name = node_name(node).unwrap_or_default();
+        match metavariable_name(&pattern_name) {
+            Some(metavariable) => {
+                if !bind(captures, metavariable, Capture::Name(name)) {
+                    return false;
+                }
+            }
+            None if pattern_name != name => return false,
+            None => {}
+        }
+    }
+    if attributes(pattern) != attributes(node) {
+        return false;
+    }
+    let pattern_fields = fields(pattern);
+    let node_fields = fields(node);
+    let mut field_names: Vec<&str> = pattern_fields.iter().map(|field| field.0).collect();
+    for field in node_fields.iter() {
+        if !field_names.contains(&field.0) {
+            field_names.push(field.0);
+        }
+    }
+    for field_name in field_names {
+        let (pattern_is_list, pattern_children) = field_children(&pattern_fields, field_name);
+        let (node_is_list, node_children) = field_children(&node_fields, field_name);
+        let matched = if pattern_is_list || node_is_list {
+            match_list(&pattern_children, &node_children, captures)
+        } else {
+            pattern_children.len() == node_children.len()
+                && pattern_children
+                    .iter()
+                    .zip(node_children.iter())
+                    .all(|(pattern, node)| match_node(pattern, node, captures))
+        };
+        if !matched {
+            return false;
+        }
+    }
+    return true;
+}
+
+/** Match the elements of a list, `*$name` taking as few elements as it can */
+fn match_list(patterns: &[&Node], nodes: &[&Node], captures: &mut Captures) -> bool {
+    let pattern = match patterns.first() {
+        Some(pattern) => pattern,
+        None => return nodes.is_empty(),
+    };
+    if let Some(name) = list_metavariable(pattern) {
+        for count in 0..=nodes.len() {
+            let mut attempt = captures.clone();
+            let taken: Vec<Node> = nodes[..count].iter().map(|&node| node.clone()).collect();
+            if bind(&mut attempt, name, Capture::Nodes(taken))
+                && match_list(&patterns[1..], &nodes[count..], &mut attempt)
+            {
+                *captures = attempt;
+                return true;
+            }
+        }
+        return false;
+    }
+    match nodes.first() {
+        Some(node) => {
+            match_node(pattern, node, captures) && match_list(&patterns[1..], &nodes[1..], captures)
+        }
+        None => false,
+    }
+}
+
+/** Name of a field, if it is a list and the children it holds */
+type Field<'a> = (&'static str, bool, Vec<&'a Node>);
+
+/** Children of a node grouped by field */
+fn fields(node: &Node) -> Vec<Field<'_>> {
+    let mut fields: Vec<Field> = vec![];
+    for (step, child) in children_with_steps(node) {
+        match fields.last_mut() {
+            Some(field) if field.0 == step.field => field.2.push(child),
+            _ => fields.push((step.field, step.index.is_some(), vec![child])),
+        }
+    }
+    return fields;
+}
+
+fn field_children<'a>(fields: &[Field<'a>], name: &str) -> (bool, Vec<&'a Node>) {
+    match fields.iter().find(|field| field.0 == name) {
+        Some(field) => return (field.1, field.2.clone()),
+        None => return (false, vec![]),
+    }
+}
+
+/** Values of a node other than its children, names and locations (literal values, operators...) */
+fn attributes(node: &Node) -> String {
+    let mut node = node.clone();
+    let placeholder = b::nil();
+    for child in children_mut(&mut node) {
+        *child = placeholder.clone();
+    }
+    set_node_name(&mut node, String::new());
+    // Lists of children compare whatever their length, the children are matched afterwards
+    let placeholder = structure(&placeholder);
+    return structure(&node)
+        .replace(&format!("{}, ", placeholder), "")
+        .replace(&placeholder, "");
+}
+
+struct MetavariableCollector {
+    names: Vec<String>,
+}
+
+impl<'a> Visitor<'a> for MetavariableCollector {
+    fn enter(&mut self, node: &'a Node, _path: &NodePath<'a>) -> bool {
+        let name = metavariable(node).map(String::from).or_else(|| {
+            node_name(node).and_then(|name| metavariable_name(&name).map(String::from))
+        });
+        if let Some(name) = name {
+            if name != "_" && !self.names.contains(&name) {
+                self.names.push(name);
+            }
+        }
+        return true;
+    }
+}
+
+/** Pattern and the code replacing what it matches */
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub pattern: Pattern,
+    pub replacement: Pattern,
+}
+
+impl Rule {
+    pub fn new(pattern: &str, replacement: &str) -> Result<Self, String> {
+        let pattern = Pattern::parse(pattern)?;
+        let replacement = Pattern::parse(replacement)?;
+        let captured = pattern.metavariables();
+        if let Some(name) = replacement
+            .metavariables()
+            .into_iter()
+            .find(|name| !captured.contains(name))
+        {
+            return Err(format!("${} is not captured by the pattern", name));
+        }
+        return Ok(Rule {
+            pattern,
+            replacement,
+        });
+    }
+
+    /** Replacement of a node matching the pattern, the captured code being rewritten too */
+    fn apply(&self, node: &Node, rules: &[Rule]) -> Option<Node> {
+        let mut captures = self.pattern.match_node(node)?;
+        for capture in captures.values_mut() {
+            match capture {
+                Capture::Node(node) => rewrite_node(rules, node),
+                Capture::Nodes(nodes) => {
+                    nodes.iter_mut().for_each(|node| rewrite_node(rules, node))
+                }
+                Capture::Name(_) => {}
+            }
+        }
+        let mut replacement = self.replacement.node.clone();
+        walk_mut(
+            &mut Substitution {
+                captures: &captures,
+            },
+            &mut replacement,
+        );
+        return Some(replacement);
+    }
+}
+
+/** Rewrite a tree in place, innermost matches first */
+fn rewrite_node(rules: &[Rule], node: &mut Node) {
+    walk_mut(&mut TreeRewriter { rules }, node);
+}
+
+struct TreeRewriter<'a> {
+    rules: &'a [Rule],
+}
+
+impl<'a> VisitorMut for TreeRewriter<'a> {
+    fn leave(&mut self, node: &mut Node, _path: &[PathStep]) {
+        if let Some(replacement) = self.rules.iter().find_map(|rule| rule.apply(node, &[])) {
+            *node = replacement;
+        }
+    }
+}
+
+/** Put the captured code in place of the metavariables of a replacement */
+struct Substitution<'a> {
+    captures: &'a Captures,
+}
+
+impl<'a> VisitorMut for Substitution<'a> {
+    fn enter(&mut self, node: &mut Node, path: &[PathStep]) -> bool {
+        if let Some(name) = metavariable(node) {
+            match self.captures.get(name) {
+                Some(Capture::Node(capture)) => {
+                    // `$x.foo` with `a + b` for `$x` is `(a + b).foo`
+                    let is_receiver = path.last().map_or(false, |step| step.field == "recv");
+                    *node = if is_receiver && !is_primary(capture) {
+                        b::parenthesize(capture.clone())
+                    } else {
+                        capture.clone()
+                    };
+                }
+                Some(Capture::Name(name)) => set_node_name(node, name.clone()),
+                _ => {}
+            }
+            return false;
+        }
+        if let Some(name) = node_name(node).as_deref().and_then(metavariable_name) {
+            if let Some(Capture::Name(name)) = self.captures.get(name) {
+                set_node_name(node, name.clone());
+            }
+        }
+        for list in lists_mut(node) {
+            splice_lists(list, self.captures);
+        }
+        return true;
+    }
+}
+
+/** Replace the `*$name` of a list by the nodes they captured */
+fn splice_lists(list: &mut Vec<Node>, captures: &Captures) {
+    let mut spliced: Vec<Node> = Vec::with_capacity(list.len());
+    for node in list.drain(..) {
+        match list_metavariable(&node).and_then(|name| captures.get(name)) {
+            Some(Capture::Nodes(nodes)) => spliced.extend(nodes.iter().cloned()),
+            _ => spliced.push(node),
+        }
+    }
+    *list = spliced;
+}
+
+/** Lists of a node where `*$name` can stand for several nodes */
+fn lists_mut(node: &mut Node) -> Vec<&mut Vec<Node>> {
+    match node {
+        Node::Send(send) => vec![&mut send.args],
+        Node::CSend(send) => vec![&mut send.args],
+        Node::Super(node) => vec![&mut node.args],
+        Node::Yield(node) => vec![&mut node.args],
+        Node::Index(index) => vec![&mut index.indexes],
+        Node::Array(array) => vec![&mut array.elements],
+        Node::Hash(hash) => vec![&mut hash.pairs],
+        Node::Begin(begin) => vec![&mut begin.statements],
+        Node::KwBegin(begin) => vec![&mut begin.statements],
+        Node::Return(node) => vec![&mut node.args],
+        Node::Break(node) => vec![&mut node.args],
+        Node::Next(node) => vec![&mut node.args],
+        _ => vec![],
+    }
+}
+
+/** Tell if a node can be the receiver of a method call without parentheses */
+pub fn is_primary(node: &Node) -> bool {
+    match node {
+        Node::Int(int) => !int.value.starts_with('-'),
+        Node::Float(float) => !float.value.starts_with('-'),
+        Node::Send(send) => {
+            send.method_name == "[]"
+                || send
+                    .method_name
+                    .starts_with(|c: char| c.is_alphabetic() || c == '_')
+                    && (send.args.is_empty() || send.begin_l.is_some())
+        }
+        Node::CSend(send) => send.args.is_empty() || send.begin_l.is_some(),
+        Node::Begin(begin) => begin.begin_l.is_some(),
+        Node::Block(block) => block.begin_l.size() == 1,
+        Node::Str(_)
+        | Node::Dstr(_)
+        | Node::Sym(_)
+        | Node::Dsym(_)
+        | Node::Array(_)
+        | Node::Hash(_)
+        | Node::Index(_)
+        | Node::Lvar(_)
+        | Node::Ivar(_)
+        | Node::Gvar(_)
+        | Node::Cvar(_)
+        | Node::Const(_)
+        | Node::Nil(_)
+        | Node::True(_)
+        | Node::False(_)
+        | Node::Self_(_)
+        | Node::Regexp(_) => true,
+        _ => false,
+    }
+}
+
+/**
+ * Rewrite rules described in a Ruby file, each rule being a call to `rewrite` with the pattern and
+ * its replacement (single quoted or heredoc, so `$` is not interpolated):
+ *
+ * ```ruby
+ * rewrite '$x.nil? ? nil : $x.$m', '$x&.$m'
+ * rewrite 'Hash.new.merge($h)', '$h.dup'
+ * ```
+ */
+pub fn rules_from_file(path: &Path) -> Result<Vec<Rule>, Vec<String>> {
+    let file_name = path.display().to_string();
+    let source = match fs::read(path) {
+        Ok(source) => source,
+        Err(error) => return Err(vec![format!("{}: {}", file_name, error)]),
+    };
+    let options = ParserOptions {
+        buffer_name: file_name.clone(),
+        record_tokens: false,
+        ..Default::default()
+    };
+    let result = Parser::new(source, options).do_parse();
+    if result
+        .diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.is_error())
+    {
+        return Err(vec![format!("{}: failed to parse the rules", file_name)]);
+    }
+    let statements: Vec<Node> = match result.ast.map(|node| *node) {
+        Some(Node::Begin(begin)) => begin.statements,
+        Some(node) => vec![node],
+        None => vec![],
+    };
+    let mut rules: Vec<Rule> = vec![];
+    let mut errors: Vec<String> = vec![];
+    for statement in statements.iter() {
+        let line = result
+            .input
+            .line_col_for_pos(statement.expression().begin)
+            .map_or(0, |(line, _)| line + 1);
+        match compile_rule(statement) {
+            Ok(rule) => rules.push(rule),
+            Err(message) => errors.push(format!("{}:{}: {}", file_name, line, message)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    return Ok(rules);
+}
+
+fn compile_rule(statement: &Node) -> Result<Rule, String> {
+    let send: &Send = match statement {
+        Node::Send(send) if send.recv.is_none() && send.method_name == "rewrite" => send,
+        _ => return Err(String::from("expected `rewrite 'pattern', 'replacement'`")),
+    };
+    let sources: Vec<String> = send.args.iter().filter_map(string_value).collect();
+    if send.args.len() != 2 || sources.len() != 2 {
+        return Err(String::from(
+            "rewrite expects a pattern and a replacement string",
+        ));
+    }
+    return Rule::new(&sources[0], &sources[1]);
+}
+
+fn string_value(node: &Node) -> Option<String> {
+    match node {
+        Node::Str(str) => Some(str.value.to_string_lossy()),
+        Node::Heredoc(heredoc) => heredoc
+            .parts
+            .iter()
+            .map(|part| match part {
+                Node::Str(str) => Some(str.value.to_string_lossy()),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/** Rewrites of a file, outermost matches first */
+#[derive(Debug, Clone)]
+pub struct FileRewrite {
+    pub path: PathBuf,
+    pub replacements: Vec<Replacement>,
+}
+
+impl FileRewrite {
+    /** Source of the file with the replacements applied */
+    pub fn rewritten_source(&self, input: &DecodedInput) -> Vec<u8> {
+        return apply_replacements(&input.bytes, 0, &self.replacements);
+    }
+
+    /** Write the changed lines of the file as a diff */
+    pub fn write_diff<W: Write>(
+        &self,
+        input: &DecodedInput,
+        writer: &mut BufWriter<W>,
+    ) -> Result<(), std::io::Error> {
+        writeln!(writer, "--- {}", self.path.display())?;
+        writeln!(writer, "+++ {}", self.path.display())?;
+        // Replacements sharing lines are shown in the same hunk
+        let mut hunks: Vec<(usize, usize, Vec<Replacement>)> = vec![];
+        for replacement in self.replacements.iter() {
+            let first_line = line_of(input, replacement.range.begin);
+            let last_line = line_of(input, replacement.range.end.saturating_sub(1));
+            match hunks.last_mut() {
+                Some(hunk) if hunk.1 >= first_line => {
+                    hunk.1 = hunk.1.max(last_line);
+                    hunk.2.push(replacement.clone());
+                }
+                _ => hunks.push((first_line, last_line, vec![replacement.clone()])),
+            }
+        }
+        let mut line_offset: isize = 0;
+        for (first_line, last_line, replacements) in hunks {
+            let begin = input.lines[first_line].start;
+            let end = input.lines[last_line].end;
+            let old_code = String::from_utf8_lossy(&input.bytes[begin..end]).to_string();
+            let new_code = String::from_utf8_lossy(&apply_replacements(
+                &input.bytes[begin..end],
+                begin,
+                &replacements,
+            ))
+            .to_string();
+            let old_lines: Vec<&str> = old_code.lines().collect();
+            let new_lines: Vec<&str> = new_code.lines().collect();
+            writeln!(
+                writer,
+                "@@ -{},{} +{},{} @@",
+                first_line + 1,
+                old_lines.len(),
+                first_line as isize + 1 + line_offset,
+                new_lines.len()
+            )?;
+            for line in old_lines.iter() {
+                writeln!(writer, "-{}", line)?;
+            }
+            for line in new_lines.iter() {
+                writeln!(writer, "+{}", line)?;
+            }
+            line_offset += new_lines.len() as isize - old_lines.len() as isize;
+        }
+        return Ok(());
+    }
+}
+
+fn line_of(input: &DecodedInput, position: usize) -> usize {
+    return input.line_col_for_pos(position).map_or(0, |(line, _)| line);
+}
+
+/** Apply sorted replacements to the bytes of a file starting at an offset */
+fn apply_replacements(bytes: &[u8], offset: usize, replacements: &[Replacement]) -> Vec<u8> {
+    let mut source: Vec<u8> = vec![];
+    let mut position = 0;
+    for replacement in replacements.iter() {
+        source.extend_from_slice(&bytes[position..(replacement.range.begin - offset)]);
+        source.extend_from_slice(replacement.text.as_bytes());
+        position = replacement.range.end - offset;
+    }
+    source.extend_from_slice(&bytes[position..]);
+    return source;
+}
+
+/** Rewrite the code of a program matching the rules, the first matching rule winning */
+pub fn search_replace(rules: &[Rule], path: &Path, result: &ParserResult) -> FileRewrite {
+    let mut finder = MatchFinder {
+        rules,
+        input: &result.input,
+        replacements: vec![],
+    };
+    if let Some(node) = &result.ast {
+        walk(&mut finder, node);
+    }
+    return FileRewrite {
+        path: path.to_path_buf(),
+        replacements: finder.replacements,
+    };
+}
+
+struct MatchFinder<'r> {
+    rules: &'r [Rule],
+    input: &'r DecodedInput,
+    replacements: Vec<Replacement>,
+}
+
+impl<'r, 'a> Visitor<'a> for MatchFinder<'r> {
+    fn enter(&mut self, node: &'a Node, _path: &NodePath<'a>) -> bool {
+        let replacement = self
+            .rules
+            .iter()
+            .find_map(|rule| rule.apply(node, self.rules));
+        let replacement = match replacement {
+            Some(replacement) => replacement,
+            None => return true,
+        };
+        self.replacements.push(write_replacement(
+            &replacement,
+            *node.expression(),
+            self.input,
+        ));
+        return false;
+    }
+}
+
+/** Replacement of a range of a file by the code of a node, indented like the line it starts */
+pub fn write_replacement(node: &Node, range: Loc, input: &DecodedInput) -> Replacement {
+    return Replacement {
+        range,
+        text: indent_continuation_lines(
+            &code_to_string(node),
+            line_indentation(input, range.begin),
+        ),
+    };
+}
+
+/** Leading whitespace of the line holding a position */
+pub fn line_indentation(input: &DecodedInput, position: usize) -> &str {
+    let line = &input.lines[line_of(input, position)];
+    let source = std::str::from_utf8(&input.bytes[line.start..line.end]).unwrap_or("");
+    let code_start = source
+        .find(|c: char| c != ' ' && c != '\t')
+        .unwrap_or(source.len());
+    return &source[..code_start];
+}
+
+fn indent_continuation_lines(code: &str, indentation: &str) -> String {
+    return code
+        .trim_end_matches('\n')
+        .lines()
+        .enumerate()
+        .map(|(index, line)| {
+            if index == 0 || line.is_empty() {
+                String::from(line)
+            } else {
+                format!("{}{}", indentation, line)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+}
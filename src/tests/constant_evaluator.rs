@@ -0,0 +1,593 @@
+use std::rc::Rc;
+
+use lib_ruby_parser::{
+    nodes::{Array, Hash, Pair, Send},
+    Node,
+};
+
+use crate::builder as b;
+
+use super::explore_constants::{find_constant_value, ConstantHashMap, SendHandler};
+
+/** Reason why the value of a constant could not be computed */
+#[derive(Debug, Clone)]
+pub enum UnevaluableReason {
+    /** The constant refers to a constant that is not known (yet) */
+    UnknownConstant(String),
+    /** The kind of expression cannot be evaluated at compile time */
+    UnsupportedExpression(&'static str),
+    /** The operator or method is not supported for the values it is called on */
+    UnsupportedOperation(String, &'static str),
+    /** The method call is not handled by the send handler */
+    UnhandledMethodCall(String),
+    DivisionByZero,
+    IntegerOverflow,
+    /** Infinity and NaN have no literal */
+    NonFiniteFloat,
+    /** The string would be longer than `MAX_STRING_LENGTH` bytes */
+    StringTooLong,
+}
+
+impl UnevaluableReason {
+    pub fn describe(&self) -> String {
+        match self {
+            UnevaluableReason::UnknownConstant(name) => format!("constant {} is unknown", name),
+            UnevaluableReason::UnsupportedExpression(kind) => {
+                format!("{} cannot be evaluated", kind)
+            }
+            UnevaluableReason::UnsupportedOperation(operator, kind) => {
+                format!("{} is not supported on {}", operator, kind)
+            }
+            UnevaluableReason::UnhandledMethodCall(name) => {
+                format!("call to {} is not handled", name)
+            }
+            UnevaluableReason::DivisionByZero => String::from("it divides by zero"),
+            UnevaluableReason::IntegerOverflow => {
+                String::from("the integer does not fit in 64 bits")
+            }
+            UnevaluableReason::NonFiniteFloat => String::from("the float is Infinity or NaN"),
+            UnevaluableReason::StringTooLong => {
+                format!("the string is longer than {} bytes", MAX_STRING_LENGTH)
+            }
+        }
+    }
+}
+
+type Evaluation = Result<Node, UnevaluableReason>;
+
+/** Longest string built by `*`, folding bigger ones would bloat the code */
+const MAX_STRING_LENGTH: usize = 1 << 16;
+
+/** Compute the value of constant expressions from literals and the constants already explored */
+pub struct ConstantEvaluator<'a> {
+    pub root: &'a ConstantHashMap,
+    /** Keys of the modules lexically enclosing the expression, outermost first */
+    pub nesting: &'a [Rc<str>],
+    pub send_handler: &'a SendHandler<'a>,
+}
+
+/** Number extracted from an `Int` or `Float` node */
+#[derive(Debug, Clone, Copy)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl<'a> ConstantEvaluator<'a> {
+    pub fn evaluate(&self, node: &Node) -> Evaluation {
+        match node {
+            Node::Int(int) => {
+                if int.value.parse::<i64>().is_ok() {
+                    return Ok(node.clone());
+                }
+                // Hexadecimal, octal, binary or `1_000` literals are given to the send handler in decimal
+                return make_int(Some(parse_int(&int.value)?));
+            }
+            Node::Float(_)
+            | Node::Str(_)
+            | Node::Sym(_)
+            | Node::Nil(_)
+            | Node::True(_)
+            | Node::False(_) => return Ok(node.clone()),
+            Node::Begin(begin) if begin.statements.len() == 1 => {
+                return self.evaluate(&begin.statements[0]);
+            }
+            Node::Dstr(dstr) => {
+                let mut value = String::new();
+                for part in dstr.parts.iter() {
+                    value.push_str(&to_s(&self.evaluate(part)?)?);
+                }
+                return Ok(b::str(&value));
+            }
+            Node::Array(array) => {
+                let mut elements: Vec<Node> = vec![];
+                for element in array.elements.iter() {
+                    elements.push(self.evaluate(element)?);
+                }
+                return Ok(Node::Array(Array {
+                    elements,
+                    ..array.clone()
+                }));
+            }
+            Node::Hash(hash) => {
+                let mut pairs: Vec<Node> = vec![];
+                for pair in hash.pairs.iter() {
+                    match pair {
+                        Node::Pair(pair) => pairs.push(Node::Pair(Pair {
+                            key: Box::new(self.evaluate(&pair.key)?),
+                            value: Box::new(self.evaluate(&pair.value)?),
+                            ..pair.clone()
+                        })),
+                        _ => return Err(UnevaluableReason::UnsupportedExpression("double splat")),
+                    }
+                }
+                return Ok(Node::Hash(Hash {
+                    pairs,
+                    ..hash.clone()
+                }));
+            }
+            Node::Irange(range) => {
+                let mut range = range.clone();
+                range.left = self.evaluate_range_end(&range.left)?;
+                range.right = self.evaluate_range_end(&range.right)?;
+                return Ok(Node::Irange(range));
+            }
+            Node::Erange(range) => {
+                let mut range = range.clone();
+                range.left = self.evaluate_range_end(&range.left)?;
+                range.right = self.evaluate_range_end(&range.right)?;
+                return Ok(Node::Erange(range));
+            }
+            Node::Const(_) => {
+                let path = constant_path(node)?;
+                match self.find_constant(&path) {
+                    Some(value) => return Ok(value.clone()),
+                    None => return Err(UnevaluableReason::UnknownConstant(path)),
+                }
+            }
+            Node::Send(send) => return self.evaluate_send(send),
+            Node::Index(index) => {
+                let recv = self.evaluate(&index.recv)?;
+                let mut indexes: Vec<Node> = vec![];
+                for index in index.indexes.iter() {
+                    indexes.push(self.evaluate(index)?);
+                }
+                return index_value(&recv, &indexes);
+            }
+            Node::Lvar(_) => {
+                return Err(UnevaluableReason::UnsupportedExpression("local variable"))
+            }
+            Node::Ivar(_) => {
+                return Err(UnevaluableReason::UnsupportedExpression(
+                    "instance variable",
+                ))
+            }
+            Node::Gvar(_) => {
+                return Err(UnevaluableReason::UnsupportedExpression("global variable"))
+            }
+            Node::Regexp(_) => return Err(UnevaluableReason::UnsupportedExpression("regexp")),
+            Node::Block(_) | Node::Numblock(_) | Node::Lambda(_) => {
+                return Err(UnevaluableReason::UnsupportedExpression("block"))
+            }
+            Node::Rational(_) | Node::Complex(_) => {
+                return Err(UnevaluableReason::UnsupportedExpression(
+                    "rational or complex number",
+                ))
+            }
+            _ => return Err(UnevaluableReason::UnsupportedExpression("expression")),
+        }
+    }
+
+    fn evaluate_range_end(
+        &self,
+        node: &Option<Box<Node>>,
+    ) -> Result<Option<Box<Node>>, UnevaluableReason> {
+        match node {
+            Some(node) => Ok(Some(Box::new(self.evaluate(node)?))),
+            None => Ok(None),
+        }
+    }
+
+    /** Find a constant from the innermost lexical scope to the top level */
+    fn find_constant(&self, path: &str) -> Option<&'a Node> {
+        if let Some(absolute_path) = path.strip_prefix("::") {
+            return find_constant_value(self.root, absolute_path);
+        }
+        for depth in (0..=self.nesting.len()).rev() {
+            let mut prefix = self.nesting[..depth].join("::");
+            if !prefix.is_empty() {
+                prefix.push_str("::");
+            }
+            if let Some(value) = find_constant_value(self.root, &format!("{}{}", prefix, path)) {
+                return Some(value);
+            }
+        }
+        return None;
+    }
+
+    fn evaluate_send(&self, send: &Send) -> Evaluation {
+        // A receiver like `Foo` in `Foo.compute(1)` may have no value but still be known by the send handler
+        let (recv, recv_error) = match &send.recv {
+            Some(recv) => match self.evaluate(recv) {
+                Ok(value) => (Some(value), None),
+                Err(reason) => (Some(recv.as_ref().clone()), Some(reason)),
+            },
+            None => (None, None),
+        };
+        let mut args: Vec<Node> = vec![];
+        for arg in send.args.iter() {
+            args.push(self.evaluate(arg)?);
+        }
+        let method_name = send.method_name.as_str();
+        match (
+            recv_error
+                .is_none()
+                .then_some(&recv)
+                .and_then(|recv| recv.as_ref()),
+            args.as_slice(),
+        ) {
+            (Some(value), []) if method_name == "freeze" => return Ok(value.clone()),
+            (Some(value), [])
+                if method_name == "-@" || method_name == "+@" || method_name == "~" =>
+            {
+                return unary_operation(method_name, value);
+            }
+            (Some(lhs), [rhs]) => {
+                if let Some(result) = binary_operation(method_name, lhs, rhs) {
+                    return result;
+                }
+            }
+            _ => {}
+        }
+        // Let the send handler compute the calls it knows, with the arguments already evaluated
+        let evaluated_send = Send {
+            recv: recv.map(Box::new),
+            args,
+            ..send.clone()
+        };
+        match (self.send_handler)(&evaluated_send) {
+            Some(value) => return self.evaluate(&value),
+            None => {
+                return Err(recv_error.unwrap_or(UnevaluableReason::UnhandledMethodCall(
+                    send.method_name.clone(),
+                )))
+            }
+        }
+    }
+}
+
+fn unary_operation(method_name: &str, value: &Node) -> Evaluation {
+    match (method_name, to_number(value)) {
+        ("-@", Some(Number::Int(value))) => make_int(value.checked_neg()),
+        ("-@", Some(Number::Float(value))) => Ok(b::float(-value)),
+        ("+@", Some(_)) => Ok(value.clone()),
+        ("~", Some(Number::Int(value))) => make_int(Some(!value)),
+        _ => Err(UnevaluableReason::UnsupportedOperation(
+            String::from(method_name),
+            node_kind(value),
+        )),
+    }
+}
+
+/** Compute `lhs <method_name> rhs`, None when it is not an operator */
+fn binary_operation(method_name: &str, lhs: &Node, rhs: &Node) -> Option<Evaluation> {
+    const OPERATORS: [&str; 18] = [
+        "+", "-", "*", "/", "%", "**", "&", "|", "^", "<<", ">>", "==", "!=", "<", ">", "<=", ">=",
+        "merge",
+    ];
+    if !OPERATORS.contains(&method_name) {
+        return None;
+    }
+    let unsupported = || {
+        Err(UnevaluableReason::UnsupportedOperation(
+            String::from(method_name),
+            node_kind(lhs),
+        ))
+    };
+    if let (Some(a), Some(b)) = (to_number(lhs), to_number(rhs)) {
+        return Some(number_operation(method_name, a, b).unwrap_or_else(unsupported));
+    }
+    let result = match (lhs, rhs) {
+        (Node::Str(a), Node::Str(b)) if method_name == "+" || method_name == "<<" => Ok(b::str(
+            &format!("{}{}", a.value.to_string_lossy(), b.value.to_string_lossy()),
+        )),
+        (Node::Str(a), Node::Int(b)) if method_name == "*" => match parse_int(&b.value) {
+            Ok(count) if count >= 0 => {
+                let value = a.value.to_string_lossy();
+                match usize::try_from(count)
+                    .ok()
+                    .and_then(|count| value.len().checked_mul(count))
+                {
+                    Some(length) if length <= MAX_STRING_LENGTH => {
+                        Ok(b::str(&value.repeat(count as usize)))
+                    }
+                    _ => Err(UnevaluableReason::StringTooLong),
+                }
+            }
+            Ok(_) => unsupported(),
+            Err(reason) => Err(reason),
+        },
+        (Node::Array(a), Node::Array(b)) if method_name == "+" => Ok(b::array(
+            a.elements
+                .iter()
+                .chain(b.elements.iter())
+                .map(standalone_element)
+                .collect(),
+        )),
+        (Node::Array(a), element) if method_name == "<<" => {
+            let mut elements: Vec<Node> = a.elements.iter().map(standalone_element).collect();
+            elements.push(element.clone());
+            Ok(b::array(elements))
+        }
+        (Node::Hash(a), Node::Hash(b)) if method_name == "merge" => {
+            let mut hash = a.clone();
+            hash.pairs.extend(b.pairs.iter().cloned());
+            Ok(Node::Hash(hash))
+        }
+        _ if method_name == "==" || method_name == "!=" => {
+            let equal = lhs_equals_rhs(lhs, rhs);
+            match equal {
+                Some(equal) => Ok(b::bool(equal == (method_name == "=="))),
+                None => unsupported(),
+            }
+        }
+        _ => unsupported(),
+    };
+    return Some(result);
+}
+
+/** Element of a `%w[]` or `%i[]` array written so it can be moved to a `[]` array */
+fn standalone_element(element: &Node) -> Node {
+    match element {
+        Node::Str(str) if str.begin_l.is_none() => return b::str(&str.value.to_string_lossy()),
+        Node::Sym(sym) if sym.begin_l.is_none() => return b::sym(&sym.name.to_string_lossy()),
+        _ => return element.clone(),
+    }
+}
+
+fn number_operation(method_name: &str, a: Number, b: Number) -> Option<Evaluation> {
+    if let (Number::Int(a), Number::Int(b)) = (a, b) {
+        let result = match method_name {
+            "+" => make_int(a.checked_add(b)),
+            "-" => make_int(a.checked_sub(b)),
+            "*" => make_int(a.checked_mul(b)),
+            "/" | "%" if b == 0 => Err(UnevaluableReason::DivisionByZero),
+            "/" => make_int(floor_div(a, b)),
+            "%" => make_int(Some(floor_mod(a, b))),
+            "**" if b >= 0 => make_int(u32::try_from(b).ok().and_then(|b| a.checked_pow(b))),
+            "**" => return None,
+            "&" => make_int(Some(a & b)),
+            "|" => make_int(Some(a | b)),
+            "^" => make_int(Some(a ^ b)),
+            "<<" => make_int(shift_left(a, b)),
+            ">>" => make_int(shift_left(a, b.checked_neg()?)),
+            _ => Ok(compare_numbers(method_name, a as f64, b as f64)?),
+        };
+        return Some(result);
+    }
+    let (a, b) = (to_f64(a), to_f64(b));
+    let result = match method_name {
+        "+" => a + b,
+        "-" => a - b,
+        "*" => a * b,
+        "/" => a / b,
+        "%" => a - b * (a / b).floor(),
+        "**" => a.powf(b),
+        _ => return Some(Ok(compare_numbers(method_name, a, b)?)),
+    };
+    if !result.is_finite() {
+        return Some(Err(UnevaluableReason::NonFiniteFloat));
+    }
+    return Some(Ok(b::float(result)));
+}
+
+fn compare_numbers(method_name: &str, a: f64, b: f64) -> Option<Node> {
+    let result = match method_name {
+        "==" => a == b,
+        "!=" => a != b,
+        "<" => a < b,
+        ">" => a > b,
+        "<=" => a <= b,
+        ">=" => a >= b,
+        _ => return None,
+    };
+    return Some(b::bool(result));
+}
+
+fn lhs_equals_rhs(lhs: &Node, rhs: &Node) -> Option<bool> {
+    match (lhs, rhs) {
+        (Node::Str(a), Node::Str(b)) => Some(a.value == b.value),
+        (Node::Sym(a), Node::Sym(b)) => Some(a.name == b.name),
+        (Node::Nil(_), Node::Nil(_))
+        | (Node::True(_), Node::True(_))
+        | (Node::False(_), Node::False(_)) => Some(true),
+        (Node::Str(_), _) | (Node::Sym(_), _) | (Node::Nil(_), _) => Some(false),
+        _ => None,
+    }
+}
+
+/** Compute `recv[index]` on a hash or an array, missing entries being nil */
+fn index_value(recv: &Node, indexes: &[Node]) -> Evaluation {
+    match (recv, indexes) {
+        (Node::Hash(hash), [key]) => {
+            for pair in hash.pairs.iter() {
+                if let Node::Pair(pair) = pair {
+                    if values_eql(&pair.key, key) {
+                        return Ok(pair.value.as_ref().clone());
+                    }
+                }
+            }
+            return Ok(b::nil());
+        }
+        (Node::Array(array), [Node::Int(index)]) => {
+            let index = parse_int(&index.value)?;
+            let position = if index < 0 {
+                array.elements.len() as i64 + index
+            } else {
+                index
+            };
+            match usize::try_from(position)
+                .ok()
+                .and_then(|position| array.elements.get(position))
+            {
+                Some(element) => return Ok(element.clone()),
+                None => return Ok(b::nil()),
+            }
+        }
+        _ => Err(UnevaluableReason::UnsupportedOperation(
+            String::from("[]"),
+            node_kind(recv),
+        )),
+    }
+}
+
+/** Hash key equality (`eql?`), `1` and `1.0` being different keys */
+fn values_eql(lhs: &Node, rhs: &Node) -> bool {
+    match (lhs, rhs) {
+        (Node::Int(a), Node::Int(b)) => parse_int(&a.value).ok() == parse_int(&b.value).ok(),
+        (Node::Float(_), Node::Float(_)) => match (to_number(lhs), to_number(rhs)) {
+            (Some(Number::Float(a)), Some(Number::Float(b))) => a == b,
+            _ => false,
+        },
+        (Node::Array(a), Node::Array(b)) => {
+            a.elements.len() == b.elements.len()
+                && a.elements
+                    .iter()
+                    .zip(b.elements.iter())
+                    .all(|(a, b)| values_eql(a, b))
+        }
+        _ => lhs_equals_rhs(lhs, rhs).unwrap_or(false),
+    }
+}
+
+/** Ruby integer division rounds toward negative infinity, None on overflow (`MIN / -1`) */
+fn floor_div(a: i64, b: i64) -> Option<i64> {
+    let quotient = a.checked_div(b)?;
+    if a.wrapping_rem(b) != 0 && ((a < 0) != (b < 0)) {
+        return Some(quotient - 1);
+    }
+    return Some(quotient);
+}
+
+/** Ruby modulo has the sign of the divisor, it never overflows (`MIN % -1` is 0) */
+fn floor_mod(a: i64, b: i64) -> i64 {
+    let remainder = a.wrapping_rem(b);
+    if remainder != 0 && ((remainder < 0) != (b < 0)) {
+        return remainder + b;
+    }
+    return remainder;
+}
+
+fn shift_left(value: i64, shift: i64) -> Option<i64> {
+    if shift >= 0 {
+        if value == 0 {
+            return Some(0);
+        }
+        let shift = u32::try_from(shift).ok().filter(|shift| *shift < 63)?;
+        return value.checked_mul(1i64 << shift);
+    }
+    let shift = shift.unsigned_abs().min(63) as u32;
+    return Some(value >> shift);
+}
+
+/** Parse the source of an integer literal (`0x1F`, `0b101`, `017`, `1_000`, `-5`) */
+fn parse_int(value: &str) -> Result<i64, UnevaluableReason> {
+    let value = value.replace('_', "");
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value.trim_start_matches('+')),
+    };
+    let lowercase = digits.to_ascii_lowercase();
+    let (radix, digits) = if let Some(digits) = lowercase.strip_prefix("0x") {
+        (16, digits)
+    } else if let Some(digits) = lowercase.strip_prefix("0b") {
+        (2, digits)
+    } else if let Some(digits) = lowercase.strip_prefix("0o") {
+        (8, digits)
+    } else if let Some(digits) = lowercase.strip_prefix("0d") {
+        (10, digits)
+    } else if lowercase.len() > 1 && lowercase.starts_with('0') {
+        (8, &lowercase[1..])
+    } else {
+        (10, lowercase.as_str())
+    };
+    // The sign is parsed with the digits, `-9223372036854775808` has no positive counterpart
+    let digits = if negative {
+        format!("-{}", digits)
+    } else {
+        String::from(digits)
+    };
+    return i64::from_str_radix(&digits, radix).map_err(|_| UnevaluableReason::IntegerOverflow);
+}
+
+fn to_number(node: &Node) -> Option<Number> {
+    match node {
+        Node::Int(int) => parse_int(&int.value).ok().map(Number::Int),
+        Node::Float(float) => float
+            .value
+            .replace('_', "")
+            .parse::<f64>()
+            .ok()
+            .map(Number::Float),
+        _ => None,
+    }
+}
+
+fn to_f64(number: Number) -> f64 {
+    match number {
+        Number::Int(value) => value as f64,
+        Number::Float(value) => value,
+    }
+}
+
+/** String conversion of a value interpolated in a string */
+fn to_s(node: &Node) -> Result<String, UnevaluableReason> {
+    match node {
+        Node::Str(str) => Ok(str.value.to_string_lossy()),
+        Node::Sym(sym) => Ok(sym.name.to_string_lossy()),
+        Node::Int(int) => Ok(parse_int(&int.value)?.to_string()),
+        Node::Float(float) => Ok(float.value.clone()),
+        Node::Nil(_) => Ok(String::new()),
+        Node::True(_) => Ok(String::from("true")),
+        Node::False(_) => Ok(String::from("false")),
+        _ => Err(UnevaluableReason::UnsupportedOperation(
+            String::from("interpolation"),
+            node_kind(node),
+        )),
+    }
+}
+
+fn constant_path(node: &Node) -> Result<String, UnevaluableReason> {
+    match node {
+        Node::Const(constant) => match constant.scope.as_deref() {
+            None => Ok(constant.name.clone()),
+            Some(Node::Cbase(_)) => Ok(format!("::{}", constant.name)),
+            Some(scope) => Ok(format!("{}::{}", constant_path(scope)?, constant.name)),
+        },
+        _ => Err(UnevaluableReason::UnsupportedExpression(
+            "dynamic constant scope",
+        )),
+    }
+}
+
+fn node_kind(node: &Node) -> &'static str {
+    match node {
+        Node::Int(_) => "Integer",
+        Node::Float(_) => "Float",
+        Node::Str(_) => "String",
+        Node::Sym(_) => "Symbol",
+        Node::Array(_) => "Array",
+        Node::Hash(_) => "Hash",
+        Node::Irange(_) | Node::Erange(_) => "Range",
+        Node::Nil(_) => "nil",
+        Node::True(_) | Node::False(_) => "Boolean",
+        _ => "value",
+    }
+}
+
+fn make_int(value: Option<i64>) -> Evaluation {
+    match value {
+        Some(value) => Ok(b::int(value)),
+        None => Err(UnevaluableReason::IntegerOverflow),
+    }
+}
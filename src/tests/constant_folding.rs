@@ -0,0 +1,378 @@
+use std::{collections::HashMap, ops::Deref, rc::Rc};
+
+use lib_ruby_parser::{
+    nodes::{Casgn, Send},
+    Loc, Node,
+};
+
+use crate::{
+    builder as b,
+    code_writer::code_to_string,
+    visitor::{children_mut, children_with_steps},
+};
+
+use super::{
+    constant_evaluator::ConstantEvaluator,
+    constant_resolver::ConstantResolver,
+    explore_constants::{constant_to_rc_str, find_constant_value, ConstantHashMap, SendHandler},
+};
+
+/** Constants touched by the folding and what is done with them */
+pub struct ConstantFoldingOptions {
+    /** Write the original expression as a comment after the folded value */
+    pub keep_expression: bool,
+    /** Replace the references to small immutable constants by their value */
+    pub inline: bool,
+    /** Full paths of the only constants to touch, all of them if None */
+    pub only: Option<Vec<String>>,
+    /** Full paths of the constants to leave untouched */
+    pub except: Vec<String>,
+}
+
+impl ConstantFoldingOptions {
+    pub fn new() -> Self {
+        return ConstantFoldingOptions {
+            keep_expression: false,
+            inline: false,
+            only: None,
+            except: vec![],
+        };
+    }
+
+    fn touches(&self, path: &str) -> bool {
+        if self.except.iter().any(|except| except == path) {
+            return false;
+        }
+        match &self.only {
+            Some(only) => return only.iter().any(|only| only == path),
+            None => return true,
+        }
+    }
+}
+
+/** Changes made by the constant folding */
+#[derive(Default)]
+pub struct FoldedConstants {
+    /** Paths of the constants whose expression was replaced by its value */
+    pub folded: Vec<String>,
+    /** Number of constant references replaced by their value */
+    pub inlined: usize,
+    /** Original expressions of the folded constants, by beginning of the assignment */
+    pub trailing_comments: HashMap<usize, String>,
+}
+
+/** Replace the constant expressions by their value and inline the small immutable constants */
+pub fn fold_constants(
+    node: &mut Node,
+    constants: &ConstantHashMap,
+    send_handler: &SendHandler<'_>,
+    options: &ConstantFoldingOptions,
+) -> FoldedConstants {
+    let mut folded = FoldedConstants::default();
+    // References are resolved before the folding removes the ones in the folded expressions
+    let resolver = ConstantResolver::new(node);
+    let replacements = if options.inline {
+        inlined_references(&resolver, constants, options)
+    } else {
+        HashMap::new()
+    };
+    let context = FoldingContext {
+        constants,
+        send_handler,
+        options,
+        reassigned_reads: reassigned_reads(&resolver),
+        frozen_reads: frozen_reads(node, &resolver),
+    };
+    let mut nesting: Vec<Rc<str>> = vec![];
+    fold_body(node, &mut nesting, &context, &mut folded);
+    if options.inline {
+        inline_references(node, &replacements, &mut folded.inlined);
+    }
+    return folded;
+}
+
+/** What the folding of each assignment needs to know */
+struct FoldingContext<'a> {
+    constants: &'a ConstantHashMap,
+    send_handler: &'a SendHandler<'a>,
+    options: &'a ConstantFoldingOptions,
+    /** References to constants assigned more than once, the map only knows their last value */
+    reassigned_reads: Vec<Loc>,
+    /** References to constants assigned a `.freeze` call, their value in the map is not frozen */
+    frozen_reads: Vec<Loc>,
+}
+
+fn fold_body(
+    node: &mut Node,
+    nesting: &mut Vec<Rc<str>>,
+    context: &FoldingContext<'_>,
+    folded: &mut FoldedConstants,
+) {
+    let (name, body) = match node {
+        Node::Casgn(asgn) => {
+            fold_casgn(asgn, nesting, context, folded);
+            return;
+        }
+        Node::Begin(begin) => {
+            for statement in begin.statements.iter_mut() {
+                fold_body(statement, nesting, context, folded);
+            }
+            return;
+        }
+        Node::Module(module) => (constant_to_rc_str(&module.name), &mut module.body),
+        Node::Class(class) => (constant_to_rc_str(&class.name), &mut class.body),
+        _ => return,
+    };
+    if let Some(body) = body {
+        nesting.push(name);
+        fold_body(body, nesting, context, folded);
+        nesting.pop();
+    }
+}
+
+fn fold_casgn(
+    asgn: &mut Casgn,
+    nesting: &[Rc<str>],
+    context: &FoldingContext<'_>,
+    folded: &mut FoldedConstants,
+) {
+    let constant_name = constant_to_rc_str(&Node::Casgn(asgn.clone()));
+    let value = match asgn.value.as_deref_mut().map(frozen_receiver) {
+        Some(value) if !is_literal(value) => value,
+        _ => return,
+    };
+    let mut path: Vec<&str> = nesting.iter().map(|name| name.deref()).collect();
+    path.push(&constant_name);
+    let path = path.join("::");
+    if !context.options.touches(&path) {
+        return;
+    }
+    // The value of a reassigned constant at this point of the file is not known
+    let value_l = *value.expression();
+    if context
+        .reassigned_reads
+        .iter()
+        .any(|read| read.begin >= value_l.begin && read.end <= value_l.end)
+    {
+        return;
+    }
+    // The evaluator sees through `.freeze`, the folded value would not be frozen anymore
+    if calls_freeze(value) {
+        return;
+    }
+    let reads_frozen_constant = context
+        .frozen_reads
+        .iter()
+        .any(|read| read.begin >= value_l.begin && read.end <= value_l.end);
+    let evaluator = ConstantEvaluator {
+        root: context.constants,
+        nesting,
+        send_handler: context.send_handler,
+    };
+    if let Ok(evaluated_value) = evaluator.evaluate(value) {
+        // `[A, 'y']` would hold a copy of the frozen string of `A`
+        if reads_frozen_constant && !is_immediate(&evaluated_value) {
+            return;
+        }
+        if context.options.keep_expression {
+            let expression = code_to_string(value);
+            folded.trailing_comments.insert(
+                asgn.expression_l.begin,
+                expression
+                    .split_whitespace()
+                    .collect::<Vec<&str>>()
+                    .join(" "),
+            );
+        }
+        *value = evaluated_value;
+        folded.folded.push(path);
+    }
+}
+
+/** Receiver of a `.freeze` call, the value folded in place of the receiver stays frozen */
+fn frozen_receiver(node: &mut Node) -> &mut Node {
+    if !is_freeze_call(node) {
+        return node;
+    }
+    match node {
+        Node::Send(Send {
+            recv: Some(recv), ..
+        }) => return recv,
+        node => return node,
+    }
+}
+
+fn is_freeze_call(node: &Node) -> bool {
+    match node {
+        Node::Send(send) => {
+            send.method_name == "freeze" && send.args.is_empty() && send.recv.is_some()
+        }
+        _ => false,
+    }
+}
+
+fn calls_freeze(node: &Node) -> bool {
+    if is_freeze_call(node) {
+        return true;
+    }
+    return children_with_steps(node)
+        .into_iter()
+        .any(|(_, child)| calls_freeze(child));
+}
+
+/** Locations of the references to constants having more than one definition */
+fn reassigned_reads(resolver: &ConstantResolver) -> Vec<Loc> {
+    return resolver
+        .references
+        .iter()
+        .filter(|reference| !reference.is_definition())
+        .filter(|reference| match resolver.resolve(reference) {
+            Some(path) => resolver.definitions_of(&path).len() > 1,
+            None => false,
+        })
+        .map(|reference| reference.expression_l)
+        .collect();
+}
+
+/** Locations of the references to constants whose value is a `.freeze` call */
+fn frozen_reads(node: &Node, resolver: &ConstantResolver) -> Vec<Loc> {
+    let mut frozen_names: Vec<usize> = vec![];
+    collect_frozen_names(node, &mut frozen_names);
+    let frozen_paths: Vec<&str> = resolver
+        .definitions
+        .iter()
+        .filter(|definition| frozen_names.contains(&definition.name_l.begin))
+        .map(|definition| definition.path.as_str())
+        .collect();
+    return resolver
+        .references
+        .iter()
+        .filter(|reference| !reference.is_definition())
+        .filter(|reference| match resolver.resolve(reference) {
+            Some(path) => frozen_paths.contains(&path.as_str()),
+            None => false,
+        })
+        .map(|reference| reference.expression_l)
+        .collect();
+}
+
+/** Beginning of the names of the constants assigned a `.freeze` call */
+fn collect_frozen_names(node: &Node, frozen_names: &mut Vec<usize>) {
+    if let Node::Casgn(asgn) = node {
+        if asgn.value.as_deref().map_or(false, is_freeze_call) {
+            frozen_names.push(asgn.name_l.begin);
+        }
+    }
+    for (_, child) in children_with_steps(node) {
+        collect_frozen_names(child, frozen_names);
+    }
+}
+
+/** Values of the references to inline, by location of the reference */
+fn inlined_references(
+    resolver: &ConstantResolver,
+    constants: &ConstantHashMap,
+    options: &ConstantFoldingOptions,
+) -> HashMap<(usize, usize), Node> {
+    let mut replacements: HashMap<(usize, usize), Node> = HashMap::new();
+    for reference in resolver.references.iter() {
+        if reference.is_definition() {
+            continue;
+        }
+        let path = match resolver.resolve(reference) {
+            Some(path) => path,
+            None => continue,
+        };
+        // A reassigned constant has a different value depending on where it is read
+        if !options.touches(&path) || resolver.definitions_of(&path).len() != 1 {
+            continue;
+        }
+        match find_constant_value(constants, &path) {
+            Some(value) if is_immediate(value) => {
+                replacements.insert(
+                    (reference.expression_l.begin, reference.expression_l.end),
+                    value.clone(),
+                );
+            }
+            _ => {}
+        }
+    }
+    return replacements;
+}
+
+fn inline_references(
+    node: &mut Node,
+    replacements: &HashMap<(usize, usize), Node>,
+    inlined: &mut usize,
+) {
+    let receiver_begin = match node {
+        Node::Send(send) => send.recv.as_ref().map(|recv| recv.expression().begin),
+        Node::CSend(send) => Some(send.recv.expression().begin),
+        _ => None,
+    };
+    for child in children_mut(node) {
+        let expression_l = *child.expression();
+        let replacement = match child {
+            Node::Const(_) => replacements.get(&(expression_l.begin, expression_l.end)),
+            _ => None,
+        };
+        match replacement {
+            Some(value) => {
+                // `-1.abs` is fine but `-2 ** 2` is `-(2 ** 2)`, negative receivers get parenthesis
+                *child = if receiver_begin == Some(expression_l.begin) && is_negative(value) {
+                    b::parenthesize(value.clone())
+                } else {
+                    value.clone()
+                };
+                *inlined += 1;
+            }
+            None => inline_references(child, replacements, inlined),
+        }
+    }
+}
+
+/** Values that are written as is, folding them changes nothing */
+fn is_literal(node: &Node) -> bool {
+    match node {
+        Node::Int(_)
+        | Node::Float(_)
+        | Node::Str(_)
+        | Node::Sym(_)
+        | Node::Nil(_)
+        | Node::True(_)
+        | Node::False(_) => true,
+        Node::Array(array) => array.elements.iter().all(is_literal),
+        Node::Hash(hash) => hash.pairs.iter().all(|pair| match pair {
+            Node::Pair(pair) => is_literal(&pair.key) && is_literal(&pair.value),
+            _ => false,
+        }),
+        Node::Irange(range) => range_ends_are_literal(&range.left, &range.right),
+        Node::Erange(range) => range_ends_are_literal(&range.left, &range.right),
+        _ => false,
+    }
+}
+
+fn range_ends_are_literal(left: &Option<Box<Node>>, right: &Option<Box<Node>>) -> bool {
+    return left.as_deref().map_or(true, is_literal) && right.as_deref().map_or(true, is_literal);
+}
+
+/** Small immutable values that can be written in place of the constant */
+fn is_immediate(node: &Node) -> bool {
+    match node {
+        Node::Int(_)
+        | Node::Float(_)
+        | Node::Sym(_)
+        | Node::Nil(_)
+        | Node::True(_)
+        | Node::False(_) => true,
+        _ => false,
+    }
+}
+
+fn is_negative(node: &Node) -> bool {
+    match node {
+        Node::Int(int) => int.value.starts_with('-'),
+        Node::Float(float) => float.value.starts_with('-'),
+        _ => false,
+    }
+}
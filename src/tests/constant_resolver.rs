@@ -0,0 +1,330 @@
+use std::collections::{HashMap, HashSet};
+
+use lib_ruby_parser::{nodes::Send, source::DecodedInput, Loc, Node};
+
+use crate::visitor::{walk, NodePath, Visitor};
+
+/** Kind of definition giving a value to a constant */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefinitionKind {
+    Constant,
+    Class,
+    Module,
+}
+
+impl DefinitionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DefinitionKind::Constant => "constant",
+            DefinitionKind::Class => "class",
+            DefinitionKind::Module => "module",
+        }
+    }
+}
+
+/** Assignment, class or module definition of a constant */
+#[derive(Debug, Clone)]
+pub struct ConstantDefinition {
+    pub path: String,
+    pub kind: DefinitionKind,
+    pub name_l: Loc,
+}
+
+/** Constant written in the code, with the lexical scope it appears in */
+#[derive(Debug, Clone)]
+pub struct ConstantReference {
+    pub node: Node,
+    /** Full paths of the enclosing classes and modules (`Module.nesting`), outermost first */
+    pub nesting: Vec<String>,
+    pub expression_l: Loc,
+    /** Path of the constant for the names of definitions, known without lookup */
+    defined_path: Option<String>,
+}
+
+impl ConstantReference {
+    /** Whether the reference is the name of a class, module or constant definition */
+    pub fn is_definition(&self) -> bool {
+        return self.defined_path.is_some();
+    }
+}
+
+/** Ancestors declared by a class or module (superclass, included and prepended modules) */
+#[derive(Debug, Default)]
+struct Namespace {
+    superclass: Option<String>,
+    mixins: Vec<String>,
+}
+
+/** Resolve the constants of a program the way Ruby does: lexical scope first, then ancestors */
+#[derive(Debug, Default)]
+pub struct ConstantResolver {
+    pub definitions: Vec<ConstantDefinition>,
+    pub references: Vec<ConstantReference>,
+    namespaces: HashMap<String, Namespace>,
+    nesting: Vec<String>,
+}
+
+impl ConstantResolver {
+    pub fn new(node: &Node) -> Self {
+        let mut resolver = ConstantResolver::default();
+        walk(&mut resolver, node);
+        return resolver;
+    }
+
+    /** Full path of the constant a reference refers to */
+    pub fn resolve(&self, reference: &ConstantReference) -> Option<String> {
+        if let Some(path) = &reference.defined_path {
+            return Some(path.clone());
+        }
+        return self.resolve_node(&reference.node, &reference.nesting);
+    }
+
+    /** Full path of a constant written within classes and modules (full paths, outermost first) */
+    pub fn resolve_path(&self, node: &Node, nesting: &[String]) -> Option<String> {
+        return self.resolve_node(node, nesting);
+    }
+
+    /** Definitions (assignments and reopenings) of a constant path */
+    pub fn definitions_of(&self, path: &str) -> Vec<&ConstantDefinition> {
+        return self
+            .definitions
+            .iter()
+            .filter(|definition| definition.path == path)
+            .collect();
+    }
+
+    /** Innermost constant reference at a byte offset */
+    pub fn reference_at(&self, offset: usize) -> Option<&ConstantReference> {
+        return self
+            .references
+            .iter()
+            .filter(|reference| {
+                reference.expression_l.begin <= offset && offset < reference.expression_l.end
+            })
+            .min_by_key(|reference| reference.expression_l.size());
+    }
+
+    fn resolve_node(&self, node: &Node, nesting: &[String]) -> Option<String> {
+        let constant = match node {
+            Node::Const(constant) => constant,
+            _ => return None,
+        };
+        match constant.scope.as_deref() {
+            Some(Node::Cbase(_)) => return self.find_in(&[String::new()], &constant.name),
+            Some(scope) => {
+                let scope = self.resolve_node(scope, nesting)?;
+                return self.find_in(&self.ancestors(&scope), &constant.name);
+            }
+            None => {}
+        }
+        // Module.nesting, innermost first
+        for namespace in nesting.iter().rev() {
+            let path = join_path(namespace, &constant.name);
+            if self.is_defined(&path) {
+                return Some(path);
+            }
+        }
+        // Ancestors of the innermost namespace, then Object (the top level)
+        let mut namespaces = match nesting.last() {
+            Some(namespace) => self.ancestors(namespace),
+            None => vec![],
+        };
+        namespaces.push(String::new());
+        return self.find_in(&namespaces, &constant.name);
+    }
+
+    fn find_in(&self, namespaces: &[String], name: &str) -> Option<String> {
+        return namespaces
+            .iter()
+            .map(|namespace| join_path(namespace, name))
+            .find(|path| self.is_defined(path));
+    }
+
+    /** The namespace followed by its ancestors, modules included last coming first */
+    fn ancestors(&self, path: &str) -> Vec<String> {
+        let mut ancestors: Vec<String> = vec![];
+        let mut visited: HashSet<String> = HashSet::new();
+        self.collect_ancestors(path, &mut ancestors, &mut visited);
+        return ancestors;
+    }
+
+    fn collect_ancestors(
+        &self,
+        path: &str,
+        ancestors: &mut Vec<String>,
+        visited: &mut HashSet<String>,
+    ) {
+        if !visited.insert(String::from(path)) {
+            return;
+        }
+        ancestors.push(String::from(path));
+        if let Some(namespace) = self.namespaces.get(path) {
+            for mixin in namespace.mixins.iter().rev() {
+                self.collect_ancestors(mixin, ancestors, visited);
+            }
+            if let Some(superclass) = &namespace.superclass {
+                self.collect_ancestors(superclass, ancestors, visited);
+            }
+        }
+    }
+
+    fn is_defined(&self, path: &str) -> bool {
+        return self
+            .definitions
+            .iter()
+            .any(|definition| definition.path == path);
+    }
+
+    /** Path of the constant defined by a class, module or constant assignment name */
+    fn definition_path(&self, scope: &Option<Box<Node>>, name: &str) -> String {
+        match scope.as_deref() {
+            Some(Node::Cbase(_)) => return String::from(name),
+            Some(scope) => {
+                let scope = self
+                    .resolve_node(scope, &self.nesting)
+                    .unwrap_or_else(|| written_path(scope));
+                return join_path(&scope, name);
+            }
+            None => match self.nesting.last() {
+                Some(namespace) => return join_path(namespace, name),
+                None => return String::from(name),
+            },
+        }
+    }
+
+    fn add_reference(&mut self, node: &Node, defined_path: Option<String>) {
+        self.references.push(ConstantReference {
+            node: node.clone(),
+            nesting: self.nesting.clone(),
+            expression_l: *node.expression(),
+            defined_path,
+        });
+    }
+
+    fn enter_namespace(
+        &mut self,
+        name: &Node,
+        kind: DefinitionKind,
+        superclass: Option<&Node>,
+        body: &Option<Box<Node>>,
+    ) {
+        let constant = match name {
+            Node::Const(constant) => constant,
+            _ => return,
+        };
+        if let Some(scope) = &constant.scope {
+            walk(self, scope);
+        }
+        let path = self.definition_path(&constant.scope, &constant.name);
+        self.add_reference(name, Some(path.clone()));
+        self.definitions.push(ConstantDefinition {
+            path: path.clone(),
+            kind,
+            name_l: constant.name_l,
+        });
+        if let Some(superclass) = superclass {
+            walk(self, superclass);
+            let superclass = self.resolve_node(superclass, &self.nesting);
+            self.namespaces.entry(path.clone()).or_default().superclass = superclass;
+        }
+        if let Some(body) = body {
+            self.nesting.push(path);
+            walk(self, body);
+            self.nesting.pop();
+        }
+    }
+
+    /** Modules included or prepended by a call, once its arguments are walked */
+    fn add_mixins(&mut self, node: &Send) {
+        if node.recv.is_some() || !matches!(node.method_name.as_str(), "include" | "prepend") {
+            return;
+        }
+        let namespace = match self.nesting.last() {
+            Some(namespace) => namespace.clone(),
+            None => return,
+        };
+        for arg in node.args.iter() {
+            if let Some(mixin) = self.resolve_node(arg, &self.nesting) {
+                self.namespaces
+                    .entry(namespace.clone())
+                    .or_default()
+                    .mixins
+                    .push(mixin);
+            }
+        }
+    }
+}
+
+/**
+ * Classes and modules are walked by hand to track the nesting. Constants of `class << self` are
+ * looked up from the enclosing namespace, its body is walked as is.
+ */
+impl<'a> Visitor<'a> for ConstantResolver {
+    fn enter(&mut self, node: &'a Node, _path: &NodePath<'a>) -> bool {
+        match node {
+            Node::Class(class) => {
+                self.enter_namespace(
+                    &class.name,
+                    DefinitionKind::Class,
+                    class.superclass.as_deref(),
+                    &class.body,
+                );
+                return false;
+            }
+            Node::Module(module) => {
+                self.enter_namespace(&module.name, DefinitionKind::Module, None, &module.body);
+                return false;
+            }
+            Node::Casgn(asgn) => {
+                let path = self.definition_path(&asgn.scope, &asgn.name);
+                self.definitions.push(ConstantDefinition {
+                    path: path.clone(),
+                    kind: DefinitionKind::Constant,
+                    name_l: asgn.name_l,
+                });
+                self.references.push(ConstantReference {
+                    node: node.clone(),
+                    nesting: self.nesting.clone(),
+                    expression_l: asgn.name_l,
+                    defined_path: Some(path),
+                });
+            }
+            Node::Const(_) => self.add_reference(node, None),
+            _ => {}
+        }
+        return true;
+    }
+
+    fn leave(&mut self, node: &'a Node, _path: &NodePath<'a>) {
+        if let Node::Send(send) = node {
+            self.add_mixins(send);
+        }
+    }
+}
+
+/** Byte offset of a 1-based `LINE:COL` position */
+pub fn offset_of_position(input: &DecodedInput, position: &str) -> Option<usize> {
+    let (line, column) = position.split_once(':')?;
+    let line = line.trim().parse::<usize>().ok()?.checked_sub(1)?;
+    let column = column.trim().parse::<usize>().ok()?.checked_sub(1)?;
+    let source_line = input.lines.get(line)?;
+    return Some(source_line.start + column);
+}
+
+fn join_path(namespace: &str, name: &str) -> String {
+    if namespace.is_empty() {
+        return String::from(name);
+    }
+    return format!("{}::{}", namespace, name);
+}
+
+/** Path of a constant as written, without lookup */
+pub fn written_path(node: &Node) -> String {
+    match node {
+        Node::Const(constant) => match constant.scope.as_deref() {
+            None | Some(Node::Cbase(_)) => constant.name.clone(),
+            Some(scope) => join_path(&written_path(scope), &constant.name),
+        },
+        _ => String::new(),
+    }
+}
@@ -1,379 +1,177 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use lib_ruby_parser::{
-    nodes::{Arg, Args, Blockarg, Kwarg, Kwoptarg, Kwrestarg, Optarg, Restarg, Shadowarg},
-    Node,
-};
+use lib_ruby_parser::{Loc, Node};
 
 use crate::{
-    create_arg, create_some_name_arg, edit_node_array_attr, edit_node_attr, edit_node_opt_attr,
+    scope_analysis::{ScopeId, ScopeTree, UnsafeConstruct, VariableKind},
+    visitor::{walk_mut, PathStep, VisitorMut},
 };
 
-pub fn edit_methods(node: &mut Box<Node>) -> () {
-    let mut arg_name_to_new_arg: ArgsMap = HashMap::new();
-    edit_node(&mut arg_name_to_new_arg, node.as_mut());
+/** Rename the local variables to short names, returning what prevented renaming some of them */
+pub fn edit_methods(node: &mut Box<Node>) -> Vec<UnsafeConstruct> {
+    let tree = ScopeTree::new(node);
+    let new_names = short_variable_names(&tree);
+    walk_mut(
+        &mut VariableRenamer {
+            new_names: &new_names,
+        },
+        node.as_mut(),
+    );
+    let mut unsafe_constructs: Vec<UnsafeConstruct> = vec![];
+    for (scope_id, scope) in tree.scopes.iter().enumerate() {
+        if !scope.kind.is_transparent() {
+            unsafe_constructs.extend(tree.unsafe_constructs(scope_id).into_iter().cloned());
+        }
+    }
+    return unsafe_constructs;
 }
 
-const ARGS_LIST: [&str; 26] = [
-    "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q", "r", "s",
-    "t", "u", "v", "w", "x", "y", "z",
+#[rustfmt::skip]
+pub const RUBY_KEYWORDS: [&str; 41] = [
+    "BEGIN", "END", "alias", "and", "begin", "break", "case", "class", "def", "defined?", "do",
+    "else", "elsif", "end", "ensure", "false", "for", "if", "in", "module", "next", "nil", "not",
+    "or", "redo", "rescue", "retry", "return", "self", "super", "then", "true", "undef", "unless",
+    "until", "when", "while", "yield", "__FILE__", "__LINE__", "__ENCODING__",
 ];
 
-type ArgsMap = HashMap<String, &'static str>;
+/** New names of the local variables, by beginning of the nodes reading or writing them */
+type NewNames = HashMap<usize, String>;
 
-fn edit_method(args: &mut Args, body: &mut Box<Node>) {
-    let mut arg_name_to_new_arg: ArgsMap = HashMap::new();
-    edit_args(&mut arg_name_to_new_arg, args);
-    edit_node(&mut arg_name_to_new_arg, body.as_mut());
-}
-
-fn edit_args(arg_name_to_new_arg: &mut ArgsMap, args: &mut Args) {
-    args.args = args
-        .args
-        .iter()
-        .map(|node| match node {
-            Node::Arg(arg) => Node::Arg(create_arg!(Arg, arg_name_to_new_arg, arg)),
-            Node::Kwarg(arg) => Node::Kwarg(create_arg!(Kwarg, arg_name_to_new_arg, arg)),
-            Node::Shadowarg(arg) => {
-                Node::Shadowarg(create_arg!(Shadowarg, arg_name_to_new_arg, arg))
-            }
-            Node::Optarg(arg) => {
-                let mut new_arg = create_arg!(Optarg, arg_name_to_new_arg, arg);
-                edit_node(arg_name_to_new_arg, new_arg.default.as_mut());
-                return Node::Optarg(new_arg);
-            }
-            Node::Kwoptarg(arg) => {
-                let mut new_arg = create_arg!(Kwoptarg, arg_name_to_new_arg, arg);
-                edit_node(arg_name_to_new_arg, new_arg.default.as_mut());
-                return Node::Kwoptarg(new_arg);
-            }
-            Node::Restarg(arg) => {
-                create_some_name_arg!(Restarg, arg_name_to_new_arg, arg, node);
-            }
-            Node::Kwrestarg(arg) => {
-                create_some_name_arg!(Kwrestarg, arg_name_to_new_arg, arg, node);
-            }
-            Node::Blockarg(arg) => {
-                create_some_name_arg!(Blockarg, arg_name_to_new_arg, arg, node);
+/**
+ * Give the local variables short names (`a`..`z`, `aa`, `ab`...) that collide with no keyword,
+ * receiverless method call (`p`, `x`) or variable visible in the same scope. Keyword arguments
+ * keep their name as well as the named captures and the variables of scopes using `binding`,
+ * `eval` & co.
+ */
+pub fn short_variable_names(tree: &ScopeTree) -> NewNames {
+    let mut new_names: NewNames = HashMap::new();
+    for (scope_id, scope) in tree.scopes.iter().enumerate() {
+        if scope.kind.is_transparent() {
+            continue;
+        }
+        let renaming_safe = tree.is_renaming_safe(scope_id);
+        let mut reserved: HashSet<String> = RUBY_KEYWORDS
+            .iter()
+            .map(|&name| String::from(name))
+            .collect();
+        for id in tree.transparent_descendants(scope_id) {
+            let scope = &tree.scopes[id];
+            reserved.extend(scope.method_calls.iter().cloned());
+            reserved.extend(scope.unresolved.iter().map(|(name, _)| name.clone()));
+            for &variable_id in scope.variables.iter() {
+                let variable = &tree.variables[variable_id];
+                if !renaming_safe
+                    || variable.kind == VariableKind::KeywordArgument
+                    || variable.reserved
+                {
+                    reserved.insert(variable.name.clone());
+                }
             }
-            node => node.to_owned(),
-        })
-        .collect();
-}
-
-fn resolve_or_assign_arg<'a>(arg_name_to_new_arg: &mut ArgsMap, arg: &'a String) -> &'a str {
-    if arg_name_to_new_arg.contains_key(arg) {
-        let &value = arg_name_to_new_arg.get(arg).unwrap();
-        if value.len() == 0 {
-            return arg.as_str();
         }
-        return value;
+        name_scope_variables(
+            tree,
+            scope_id,
+            &reserved,
+            &HashSet::new(),
+            renaming_safe,
+            &mut new_names,
+        );
     }
-    let new_key = if arg_name_to_new_arg.len() < 26 {
-        ARGS_LIST[arg_name_to_new_arg.len()]
-    } else {
-        &""
-    };
-    arg_name_to_new_arg.insert(arg.clone(), new_key);
-    return new_key;
+    return new_names;
 }
 
-fn edit_node(arg_name_to_new_arg: &mut ArgsMap, node: &mut Node) -> () {
-    match node {
-        Node::And(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, lhs);
-            edit_node_attr!(arg_name_to_new_arg, node, rhs);
-        }
-        Node::AndAsgn(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, recv);
-            edit_node_attr!(arg_name_to_new_arg, node, value);
-        }
-        Node::Array(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, elements);
-        }
-        Node::ArrayPattern(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, elements);
-        }
-        Node::ArrayPatternWithTail(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, elements);
-        }
-        Node::Begin(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, statements);
-        }
-        Node::Block(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, call);
-            let mut new_args_map = arg_name_to_new_arg.clone();
-            if let Some(Node::Args(args)) = node.args.as_deref_mut() {
-                edit_args(&mut new_args_map, args);
-            } else {
-                edit_node_opt_attr!(&mut new_args_map, node, args);
-            }
-            edit_node_opt_attr!(&mut new_args_map, node, body);
-        }
-        Node::BlockPass(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, value);
-        }
-        Node::Break(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, args);
-        }
-        Node::Case(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, expr);
-            edit_node_array_attr!(arg_name_to_new_arg, node, when_bodies);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, else_body);
-        }
-        Node::CaseMatch(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, expr);
-            edit_node_array_attr!(arg_name_to_new_arg, node, in_bodies);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, else_body);
-        }
-        Node::Class(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, superclass);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, body);
-        }
-        Node::ConstPattern(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, pattern);
-        }
-        Node::CSend(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, recv);
-            edit_node_array_attr!(arg_name_to_new_arg, node, args);
-        }
-        Node::Cvasgn(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, value);
-        }
-        Node::Def(node) => {
-            if let Some(Node::Args(args)) = node.args.as_deref_mut() {
-                if let Some(body) = node.body.as_mut() {
-                    edit_method(args, body);
+fn name_scope_variables(
+    tree: &ScopeTree,
+    scope_id: ScopeId,
+    reserved: &HashSet<String>,
+    parent_names: &HashSet<String>,
+    renaming_safe: bool,
+    new_names: &mut NewNames,
+) {
+    let mut taken_names = parent_names.clone();
+    let mut next_index = 0;
+    for &variable_id in tree.scopes[scope_id].variables.iter() {
+        let variable = &tree.variables[variable_id];
+        let new_name = if !renaming_safe
+            || variable.kind == VariableKind::KeywordArgument
+            || variable.reserved
+        {
+            variable.name.clone()
+        } else {
+            loop {
+                let name = identifier_at(next_index);
+                next_index += 1;
+                if !reserved.contains(&name) && !taken_names.contains(&name) {
+                    break name;
                 }
-            } else {
-                let mut args_map: ArgsMap = HashMap::new();
-                edit_node_opt_attr!(&mut args_map, node, body);
             }
+        };
+        for occurrence in variable.occurrences.iter() {
+            new_names.insert(occurrence.expression_l.begin, new_name.clone());
         }
-        Node::Defs(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, definee);
-            let mut args_map: ArgsMap = HashMap::new();
-            if let Some(Node::Args(args)) = node.args.as_deref_mut() {
-                edit_args(&mut args_map, args);
-            } else {
-                edit_node_opt_attr!(&mut args_map, node, args);
-            }
-            edit_node_opt_attr!(&mut args_map, node, body);
-        }
-        Node::Dstr(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, parts);
-        }
-        Node::Dsym(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, parts);
-        }
-        Node::EFlipFlop(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, right);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, left);
-        }
-        Node::Ensure(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, body);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, ensure);
-        }
-        Node::Erange(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, right);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, left);
-        }
-        Node::FindPattern(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, elements);
-        }
-        Node::For(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, iterator);
-            edit_node_attr!(arg_name_to_new_arg, node, iteratee);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, body);
-        }
-        Node::Gvasgn(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, value);
-        }
-        Node::Hash(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, pairs);
-        }
-        Node::HashPattern(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, elements);
-        }
-        Node::Heredoc(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, parts);
-        }
-        Node::If(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, cond);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, if_true);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, if_false);
-        }
-        Node::IfGuard(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, cond);
-        }
-        Node::IFlipFlop(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, right);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, left);
-        }
-        Node::IfMod(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, cond);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, if_true);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, if_false);
-        }
-        Node::IfTernary(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, cond);
-            edit_node_attr!(arg_name_to_new_arg, node, if_true);
-            edit_node_attr!(arg_name_to_new_arg, node, if_false);
-        }
-        Node::Index(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, recv);
-            edit_node_array_attr!(arg_name_to_new_arg, node, indexes);
-        }
-        Node::IndexAsgn(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, recv);
-            edit_node_array_attr!(arg_name_to_new_arg, node, indexes);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, value);
-        }
-        Node::InPattern(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, pattern);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, guard);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, body);
-        }
-        Node::Irange(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, right);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, left);
-        }
-        Node::Ivasgn(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, value);
-        }
-        Node::Kwargs(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, pairs);
-        }
-        Node::KwBegin(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, statements);
-        }
-        Node::Kwsplat(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, value);
-        }
-        Node::Lvar(node) => {
-            node.name = String::from(resolve_or_assign_arg(arg_name_to_new_arg, &node.name));
-        }
-        Node::Lvasgn(node) => {
-            node.name = String::from(resolve_or_assign_arg(arg_name_to_new_arg, &node.name)); // TODO: end_of_use optimization
-            edit_node_opt_attr!(arg_name_to_new_arg, node, value);
-        }
-        Node::Masgn(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, lhs);
-            edit_node_attr!(arg_name_to_new_arg, node, rhs);
-        }
-        Node::MatchAlt(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, lhs);
-            edit_node_attr!(arg_name_to_new_arg, node, rhs);
-        }
-        Node::MatchAs(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, value);
-            edit_node_attr!(arg_name_to_new_arg, node, as_);
-        }
-        Node::MatchCurrentLine(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, re);
-        }
-        Node::MatchPattern(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, value);
-            edit_node_attr!(arg_name_to_new_arg, node, pattern);
-        }
-        Node::MatchPatternP(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, value);
-            edit_node_attr!(arg_name_to_new_arg, node, pattern);
-        }
-        Node::MatchRest(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, name);
-        }
-        Node::MatchVar(node) => {
-            arg_name_to_new_arg.insert(node.name.clone(), "");
-        }
-        Node::MatchWithLvasgn(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, re);
-            edit_node_attr!(arg_name_to_new_arg, node, value);
-        }
-        Node::Mlhs(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, items);
-        }
-        Node::Module(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, body);
-        }
-        Node::Next(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, args);
-        }
-        Node::OpAsgn(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, recv);
-            edit_node_attr!(arg_name_to_new_arg, node, value);
-        }
-        Node::Or(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, lhs);
-            edit_node_attr!(arg_name_to_new_arg, node, rhs);
-        }
-        Node::OrAsgn(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, recv);
-            edit_node_attr!(arg_name_to_new_arg, node, value);
-        }
-        Node::Pair(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, key);
-            edit_node_attr!(arg_name_to_new_arg, node, value);
-        }
-        Node::Pin(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, var);
-        }
-        Node::Procarg0(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, args);
-        }
-        Node::Regexp(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, parts);
-        }
-        Node::Rescue(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, body);
-            edit_node_array_attr!(arg_name_to_new_arg, node, rescue_bodies);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, else_);
-        }
-        Node::Return(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, args);
-        }
-        Node::Send(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, recv);
-            edit_node_array_attr!(arg_name_to_new_arg, node, args);
-        }
-        Node::Splat(node) => {
-            edit_node_opt_attr!(arg_name_to_new_arg, node, value);
-        }
-        Node::Super(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, args);
-        }
-        Node::UnlessGuard(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, cond);
-        }
-        Node::Until(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, cond);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, body);
-        }
-        Node::UntilPost(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, cond);
-            edit_node_attr!(arg_name_to_new_arg, node, body);
-        }
-        Node::When(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, patterns);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, body);
-        }
-        Node::While(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, cond);
-            edit_node_opt_attr!(arg_name_to_new_arg, node, body);
-        }
-        Node::WhilePost(node) => {
-            edit_node_attr!(arg_name_to_new_arg, node, cond);
-            edit_node_attr!(arg_name_to_new_arg, node, body);
-        }
-        Node::XHeredoc(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, parts);
+        taken_names.insert(new_name);
+    }
+    // Blocks see the variables of their parent, their own variables take other names
+    for &child in tree.scopes[scope_id].children.iter() {
+        if tree.scopes[child].kind.is_transparent() {
+            name_scope_variables(
+                tree,
+                child,
+                reserved,
+                &taken_names,
+                renaming_safe,
+                new_names,
+            );
         }
-        Node::Xstr(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, parts);
+    }
+}
+
+/** Name at an index of the `a`..`z`, `aa`..`az`, `ba`... sequence */
+fn identifier_at(index: usize) -> String {
+    let mut index = index + 1;
+    let mut name: Vec<u8> = vec![];
+    while index > 0 {
+        index -= 1;
+        name.push(b'a' + (index % 26) as u8);
+        index /= 26;
+    }
+    name.reverse();
+    return String::from_utf8(name).unwrap();
+}
+
+/** Give the new names to the variables and arguments, keyword arguments being left as is */
+struct VariableRenamer<'a> {
+    new_names: &'a NewNames,
+}
+
+impl<'a> VariableRenamer<'a> {
+    fn rename(&self, expression_l: &Loc, name: &mut String) {
+        if let Some(new_name) = self.new_names.get(&expression_l.begin) {
+            *name = new_name.clone();
         }
-        Node::Yield(node) => {
-            edit_node_array_attr!(arg_name_to_new_arg, node, args);
+    }
+
+    fn rename_optional(&self, expression_l: &Loc, name: &mut Option<String>) {
+        if let Some(name) = name {
+            self.rename(expression_l, name);
         }
-        _ => {}
+    }
+}
+
+impl<'a> VisitorMut for VariableRenamer<'a> {
+    fn enter(&mut self, node: &mut Node, _path: &[PathStep]) -> bool {
+        match node {
+            Node::Lvar(node) => self.rename(&node.expression_l, &mut node.name),
+            Node::Lvasgn(node) => self.rename(&node.expression_l, &mut node.name), // TODO: end_of_use optimization
+            Node::MatchVar(node) => self.rename(&node.expression_l, &mut node.name),
+            Node::Arg(node) => self.rename(&node.expression_l, &mut node.name),
+            Node::Optarg(node) => self.rename(&node.expression_l, &mut node.name),
+            Node::Shadowarg(node) => self.rename(&node.expression_l, &mut node.name),
+            Node::Restarg(node) => self.rename_optional(&node.expression_l, &mut node.name),
+            Node::Kwrestarg(node) => self.rename_optional(&node.expression_l, &mut node.name),
+            Node::Blockarg(node) => self.rename_optional(&node.expression_l, &mut node.name),
+            _ => {}
+        }
+        return true;
     }
 }
@@ -2,130 +2,255 @@ use std::{collections::HashMap, ops::Deref, rc::Rc};
 
 use lib_ruby_parser::{
     nodes::{Casgn, Send},
-    Node,
+    source::DecodedInput,
+    Loc, Node,
 };
 
+use super::constant_evaluator::{ConstantEvaluator, UnevaluableReason};
+
 #[derive(Debug)]
 pub enum ConstantHashValue {
     EndValue(Node),
     ModuleValue(ConstantHashMap),
 }
 
-type ConstantHashMap = HashMap<Rc<str>, ConstantHashValue>;
-type SendHandler = dyn Fn(&Send) -> Option<Box<Node>>;
+pub type SendHandler<'a> = dyn Fn(&Send) -> Option<Box<Node>> + 'a;
+
+/** Map of the explored constants, iterated in definition order */
+#[derive(Default)]
+pub struct ConstantHashMap {
+    /** Tell if the namespace is defined with `class` rather than `module` */
+    pub is_class: bool,
+    order: Vec<Rc<str>>,
+    values: HashMap<Rc<str>, ConstantHashValue>,
+}
+
+impl ConstantHashMap {
+    pub fn get(&self, key: &str) -> Option<&ConstantHashValue> {
+        return self.values.get(key);
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut ConstantHashValue> {
+        return self.values.get_mut(key);
+    }
 
-// impl std::fmt::Debug for ConstantHashMap {
-//     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-//         let mut debug_struct = f.debug_struct("HashMap");
-//         for (key, val) in self.iter() {
-//             debug_struct.field(key, val);
-//         }
-//         debug_struct.finish()
-//     }
-// }
+    /** Insert a value, a redefined constant keeps the position of its first definition */
+    pub fn insert(&mut self, key: Rc<str>, value: ConstantHashValue) {
+        if !self.values.contains_key(&key) {
+            self.order.push(key.clone());
+        }
+        self.values.insert(key, value);
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<ConstantHashValue> {
+        self.order
+            .retain(|existing_key| existing_key.deref() != key);
+        return self.values.remove(key);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.values.is_empty();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Rc<str>, &ConstantHashValue)> {
+        return self.order.iter().map(|key| (key, &self.values[key]));
+    }
+}
+
+impl std::fmt::Debug for ConstantHashMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f.debug_map().entries(self.iter()).finish();
+    }
+}
 
 pub fn make_constant_hash_map() -> ConstantHashMap {
-    return HashMap::new();
+    return ConstantHashMap::default();
+}
+
+/** Constant whose value could not be computed */
+#[derive(Debug, Clone)]
+pub struct UnevaluableConstant {
+    pub path: String,
+    pub reason: UnevaluableReason,
+    pub expression_l: Loc,
+}
+
+impl UnevaluableConstant {
+    pub fn describe(&self, input: &DecodedInput) -> String {
+        let line = match input.line_col_for_pos(self.expression_l.begin) {
+            Some((line, _)) => line + 1,
+            None => 0,
+        };
+        return format!(
+            "{} at line {} cannot be evaluated: {}",
+            self.path,
+            line,
+            self.reason.describe()
+        );
+    }
 }
 
 pub fn explore_constants(
-    top_level_or_current_module: &mut ConstantHashMap,
+    top_level: &mut ConstantHashMap,
+    node: &Node,
+    send_handler: &SendHandler<'_>,
+) -> Vec<UnevaluableConstant> {
+    let mut nesting: Vec<Rc<str>> = vec![];
+    let mut unevaluable: Vec<UnevaluableConstant> = vec![];
+    explore_node(
+        top_level,
+        &mut nesting,
+        node,
+        send_handler,
+        &mut unevaluable,
+    );
+    return unevaluable;
+}
+
+fn explore_node(
+    top_level: &mut ConstantHashMap,
+    nesting: &mut Vec<Rc<str>>,
     node: &Node,
-    send_handler: &SendHandler,
+    send_handler: &SendHandler<'_>,
+    unevaluable: &mut Vec<UnevaluableConstant>,
 ) -> () {
     match node {
         Node::Module(module) => {
             explore_module_or_class(
-                top_level_or_current_module,
+                top_level,
+                nesting,
                 &module.name,
                 &module.body,
+                false,
                 send_handler,
+                unevaluable,
             );
         }
         Node::Class(class) => {
             explore_module_or_class(
-                top_level_or_current_module,
+                top_level,
+                nesting,
                 &class.name,
                 &class.body,
+                true,
                 send_handler,
+                unevaluable,
             );
         }
         _ => {
-            explore_body(top_level_or_current_module, node, send_handler);
+            explore_body(top_level, nesting, node, send_handler, unevaluable);
         }
     }
 }
 
+/** Get the map of the module currently explored */
+fn current_module<'a>(
+    top_level: &'a mut ConstantHashMap,
+    nesting: &[Rc<str>],
+) -> &'a mut ConstantHashMap {
+    let mut map = top_level;
+    for module_name in nesting.iter() {
+        map = match map.get_mut(module_name) {
+            Some(ConstantHashValue::ModuleValue(module)) => module,
+            _ => unreachable!("explored modules are inserted before their body is explored"),
+        };
+    }
+    return map;
+}
+
 fn explore_module_or_class(
-    current_module: &mut ConstantHashMap,
+    top_level: &mut ConstantHashMap,
+    nesting: &mut Vec<Rc<str>>,
     name: &Node,
     body: &Option<Box<Node>>,
-    send_handler: &SendHandler,
+    is_class: bool,
+    send_handler: &SendHandler<'_>,
+    unevaluable: &mut Vec<UnevaluableConstant>,
 ) -> () {
     let explored_module_name = constant_to_rc_str(name);
-    if !current_module.contains_key(&explored_module_name) {
-        current_module.insert(
-            explored_module_name.clone(),
-            ConstantHashValue::ModuleValue(make_constant_hash_map()),
-        );
+    let current_module_map = current_module(top_level, nesting);
+    match current_module_map.get_mut(&explored_module_name) {
+        Some(ConstantHashValue::ModuleValue(map)) => map.is_class |= is_class,
+        _ => {
+            let mut map = make_constant_hash_map();
+            map.is_class = is_class;
+            current_module_map.insert(
+                explored_module_name.clone(),
+                ConstantHashValue::ModuleValue(map),
+            );
+        }
     }
     if let Some(body) = body {
-        if let Some(ConstantHashValue::ModuleValue(map)) =
-            current_module.get_mut(&explored_module_name)
-        {
-            explore_body(map, &body, send_handler);
-        };
+        nesting.push(explored_module_name.clone());
+        explore_body(top_level, nesting, &body, send_handler, unevaluable);
+        nesting.pop();
     };
     // Remove empty module after exploration
-    if let Some(ConstantHashValue::ModuleValue(map)) = current_module.get(&explored_module_name) {
+    let current_module_map = current_module(top_level, nesting);
+    if let Some(ConstantHashValue::ModuleValue(map)) = current_module_map.get(&explored_module_name)
+    {
         if map.is_empty() {
-            current_module.remove(&explored_module_name);
+            current_module_map.remove(&explored_module_name);
         }
     }
 }
 
-fn explore_body(map: &mut ConstantHashMap, body: &Node, send_handler: &SendHandler) -> () {
+fn explore_body(
+    top_level: &mut ConstantHashMap,
+    nesting: &mut Vec<Rc<str>>,
+    body: &Node,
+    send_handler: &SendHandler<'_>,
+    unevaluable: &mut Vec<UnevaluableConstant>,
+) -> () {
     match body {
         Node::Casgn(asgn) => {
-            handle_casgn(map, asgn, send_handler);
+            handle_casgn(top_level, nesting, asgn, send_handler, unevaluable);
         }
         Node::Module(_) | Node::Class(_) => {
-            explore_constants(map, body, send_handler);
+            explore_node(top_level, nesting, body, send_handler, unevaluable);
         }
         Node::Begin(begin) => {
             for node in begin.statements.iter() {
-                explore_body(map, node, send_handler);
+                explore_body(top_level, nesting, node, send_handler, unevaluable);
             }
         }
         _ => {}
     }
 }
 
-fn handle_casgn(map: &mut ConstantHashMap, asgn: &Casgn, send_handler: &SendHandler) -> () {
+fn handle_casgn(
+    top_level: &mut ConstantHashMap,
+    nesting: &[Rc<str>],
+    asgn: &Casgn,
+    send_handler: &SendHandler<'_>,
+    unevaluable: &mut Vec<UnevaluableConstant>,
+) -> () {
     if let Some(value) = &asgn.value {
-        match value.as_ref() {
-            Node::Int(_)
-            //| Node::Str(_)
-            | Node::Sym(_)
-            | Node::Float(_)
-            | Node::Nil(_)
-            | Node::True(_)
-            | Node::False(_) => {
-                map.insert(
-                    constant_to_rc_str(&Node::Casgn(asgn.clone())),
-                    ConstantHashValue::EndValue(value.as_ref().clone()),
-                );
+        let evaluator = ConstantEvaluator {
+            root: top_level,
+            nesting,
+            send_handler,
+        };
+        let constant_name = constant_to_rc_str(&Node::Casgn(asgn.clone()));
+        match evaluator.evaluate(value) {
+            Ok(value) => {
+                current_module(top_level, nesting)
+                    .insert(constant_name, ConstantHashValue::EndValue(value));
             }
-            Node::Send(send) => {
-                let new_asgn = Casgn { value: send_handler(send), ..asgn.clone() };
-                handle_casgn(map, &new_asgn, send_handler);
+            Err(reason) => {
+                let mut path: Vec<&str> = nesting.iter().map(|name| name.deref()).collect();
+                path.push(&constant_name);
+                unevaluable.push(UnevaluableConstant {
+                    path: path.join("::"),
+                    reason,
+                    expression_l: asgn.expression_l,
+                });
             }
-            _ => {}
         }
     }
 }
 
-fn constant_to_rc_str(constant_node: &Node) -> Rc<str> {
+pub fn constant_to_rc_str(constant_node: &Node) -> Rc<str> {
     match constant_node {
         Node::Const(constant) => {
             return get_constant_name(&constant.scope, &constant.name);
@@ -148,3 +273,23 @@ fn get_constant_name(scope: &Option<Box<Node>>, constant_name: &String) -> Rc<st
     }
     return constant_name.as_str().into();
 }
+
+/** Find the explored value of a constant from its full path (eg. `Test::C::D`) */
+pub fn find_constant_value<'a>(map: &'a ConstantHashMap, path: &str) -> Option<&'a Node> {
+    let segments: Vec<&str> = path.split("::").filter(|s| !s.is_empty()).collect();
+    for length in 1..=segments.len() {
+        let key = segments[..length].join("::");
+        match map.get(key.as_str()) {
+            Some(ConstantHashValue::EndValue(value)) if length == segments.len() => {
+                return Some(value);
+            }
+            Some(ConstantHashValue::ModuleValue(module)) if length < segments.len() => {
+                if let Some(value) = find_constant_value(module, &segments[length..].join("::")) {
+                    return Some(value);
+                }
+            }
+            _ => {}
+        }
+    }
+    return None;
+}
@@ -1,4 +1,7 @@
+pub mod constant_evaluator;
+pub mod constant_folding;
+pub mod constant_resolver;
 pub mod edit_methods;
-mod edit_methods_macros;
 pub mod explore_constants;
 pub mod insert_test_in_module;
+pub mod send_rules;
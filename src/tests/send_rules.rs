@@ -0,0 +1,326 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use lib_ruby_parser::{
+    nodes::{Pair, Send},
+    source::DecodedInput,
+    Loc, Node, Parser, ParserOptions,
+};
+
+use crate::visitor::{walk, NodePath, Visitor};
+
+/**
+ * Method evaluators described in a Ruby file, each rule being a method definition whose body is
+ * a constant expression over its arguments:
+ *
+ * ```ruby
+ * def gen(a, b) = 384 + a + b * 8
+ * def Color.rgb(r, g, b) = (r << 16) | (g << 8) | b
+ * def level(name) = { low: 1, medium: 2, high: 3 }[name]
+ * ```
+ */
+#[derive(Default)]
+pub struct SendRules {
+    rules: Vec<SendRule>,
+}
+
+struct SendRule {
+    /** Constant path of the receiver (`Color` in `Color.rgb`), None for receiverless calls */
+    receiver: Option<String>,
+    method_name: String,
+    parameters: Vec<String>,
+    /** Expression computing the result, parameters being local variables */
+    template: Node,
+    /** Whether the template calls the rule again, directly or through other rules */
+    recursive: bool,
+}
+
+/** Error found while compiling the send rules */
+#[derive(Debug)]
+pub struct SendRuleError {
+    pub message: String,
+    pub expression_l: Loc,
+}
+
+impl SendRuleError {
+    pub fn describe(&self, file_name: &str, input: &DecodedInput) -> String {
+        let line = match input.line_col_for_pos(self.expression_l.begin) {
+            Some((line, _)) => line + 1,
+            None => 0,
+        };
+        return format!("{}:{}: {}", file_name, line, self.message);
+    }
+}
+
+impl SendRules {
+    /** Compile the rules of a file, panicking with the list of errors if it is invalid */
+    pub fn from_file(path: &Path) -> Self {
+        let file_name = path.display().to_string();
+        let source = fs::read_to_string(path)
+            .expect(format!("Failed to read send rules file: {}", file_name).as_str());
+        let options = ParserOptions {
+            buffer_name: file_name.clone(),
+            record_tokens: false,
+            ..Default::default()
+        };
+        let result = Parser::new(source, options).do_parse();
+        if !result.diagnostics.is_empty() {
+            panic!("Failed to parse send rules file: {}", file_name);
+        }
+        match result.ast.map(|node| SendRules::compile(&node)) {
+            Some(Ok(rules)) => return rules,
+            Some(Err(errors)) => panic!(
+                "Invalid send rules:\n{}",
+                errors
+                    .iter()
+                    .map(|error| error.describe(&file_name, &result.input))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            ),
+            None => return SendRules::default(),
+        }
+    }
+
+    /** Compile the method definitions of a program into rules */
+    pub fn compile(node: &Node) -> Result<Self, Vec<SendRuleError>> {
+        let statements = match node {
+            Node::Begin(begin) => begin.statements.iter().collect(),
+            _ => vec![node],
+        };
+        let mut rules: Vec<SendRule> = vec![];
+        let mut errors: Vec<SendRuleError> = vec![];
+        for statement in statements {
+            match compile_rule(statement) {
+                Ok(rule) => rules.push(rule),
+                Err(error) => errors.push(error),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let mut send_rules = SendRules { rules };
+        let calls: Vec<Vec<usize>> = send_rules
+            .rules
+            .iter()
+            .map(|rule| send_rules.called_rules(&rule.template))
+            .collect();
+        for (index, rule) in send_rules.rules.iter_mut().enumerate() {
+            rule.recursive = reaches(&calls, index, index);
+        }
+        return Ok(send_rules);
+    }
+
+    /** Send handler giving the expression of the first rule matching the call */
+    pub fn handle(&self, send: &Send) -> Option<Box<Node>> {
+        let rule = &self.rules[self.find_rule(send)?];
+        // Expanding a recursive rule never ends, its calls cannot be evaluated
+        if rule.recursive {
+            return None;
+        }
+        let arguments: Vec<(&str, &Node)> = rule
+            .parameters
+            .iter()
+            .map(|parameter| parameter.as_str())
+            .zip(send.args.iter())
+            .collect();
+        return Some(Box::new(substitute_arguments(&rule.template, &arguments)));
+    }
+
+    /** Index of the first rule matching a call */
+    fn find_rule(&self, send: &Send) -> Option<usize> {
+        let receiver = match send.recv.as_deref() {
+            Some(recv) => Some(constant_path(recv)?),
+            None => None,
+        };
+        return self.rules.iter().position(|rule| {
+            rule.method_name == send.method_name
+                && rule.receiver == receiver
+                && rule.parameters.len() == send.args.len()
+        });
+    }
+
+    /** Indexes of the rules called by a template */
+    fn called_rules(&self, template: &Node) -> Vec<usize> {
+        let mut collector = RuleCallCollector {
+            send_rules: self,
+            called: vec![],
+        };
+        walk(&mut collector, template);
+        return collector.called;
+    }
+}
+
+struct RuleCallCollector<'r> {
+    send_rules: &'r SendRules,
+    called: Vec<usize>,
+}
+
+impl<'r, 'a> Visitor<'a> for RuleCallCollector<'r> {
+    fn enter(&mut self, node: &'a Node, _path: &NodePath<'a>) -> bool {
+        if let Node::Send(send) = node {
+            if let Some(index) = self.send_rules.find_rule(send) {
+                self.called.push(index);
+            }
+        }
+        return true;
+    }
+}
+
+/** Whether a rule is called from another one, following the calls of the rules */
+fn reaches(calls: &[Vec<usize>], from: usize, to: usize) -> bool {
+    let mut visited: HashSet<usize> = HashSet::new();
+    let mut pending: Vec<usize> = calls[from].clone();
+    while let Some(index) = pending.pop() {
+        if index == to {
+            return true;
+        }
+        if visited.insert(index) {
+            pending.extend(calls[index].iter().copied());
+        }
+    }
+    return false;
+}
+
+fn compile_rule(node: &Node) -> Result<SendRule, SendRuleError> {
+    let (receiver, method_name, args, body, expression_l) = match node {
+        Node::Def(def) => (None, &def.name, &def.args, &def.body, def.expression_l),
+        Node::Defs(defs) => match constant_path(&defs.definee) {
+            Some(receiver) => (
+                Some(receiver),
+                &defs.name,
+                &defs.args,
+                &defs.body,
+                defs.expression_l,
+            ),
+            None => {
+                return Err(SendRuleError {
+                    message: format!(
+                        "the receiver of {} must be a constant like `def Color.{}`",
+                        defs.name, defs.name
+                    ),
+                    expression_l: defs.expression_l,
+                })
+            }
+        },
+        _ => {
+            return Err(SendRuleError {
+                message: String::from("send rules must be method definitions"),
+                expression_l: *node.expression(),
+            })
+        }
+    };
+    let mut parameters: Vec<String> = vec![];
+    if let Some(Node::Args(args)) = args.as_deref() {
+        for arg in args.args.iter() {
+            match arg {
+                Node::Arg(arg) => parameters.push(arg.name.clone()),
+                _ => {
+                    return Err(SendRuleError {
+                        message: format!("{} may only take required arguments", method_name),
+                        expression_l: *arg.expression(),
+                    })
+                }
+            }
+        }
+    }
+    let template = match body.as_deref() {
+        Some(Node::Begin(begin)) if begin.statements.len() > 1 => {
+            return Err(SendRuleError {
+                message: format!("the body of {} must be a single expression", method_name),
+                expression_l,
+            })
+        }
+        Some(body) => body.clone(),
+        None => {
+            return Err(SendRuleError {
+                message: format!("{} has no expression", method_name),
+                expression_l,
+            })
+        }
+    };
+    return Ok(SendRule {
+        receiver,
+        method_name: method_name.clone(),
+        parameters,
+        template,
+        recursive: false,
+    });
+}
+
+/** Replace the parameters of a template by the (evaluated) arguments of the call */
+fn substitute_arguments(node: &Node, arguments: &[(&str, &Node)]) -> Node {
+    let substitute = |node: &Node| substitute_arguments(node, arguments);
+    let substitute_box = |node: &Node| Box::new(substitute_arguments(node, arguments));
+    match node {
+        Node::Lvar(lvar) => {
+            match arguments
+                .iter()
+                .find(|(parameter, _)| *parameter == lvar.name)
+            {
+                Some((_, value)) => return (*value).clone(),
+                None => return node.clone(),
+            }
+        }
+        Node::Begin(begin) => {
+            let mut begin = begin.clone();
+            begin.statements = begin.statements.iter().map(substitute).collect();
+            return Node::Begin(begin);
+        }
+        Node::Dstr(dstr) => {
+            let mut dstr = dstr.clone();
+            dstr.parts = dstr.parts.iter().map(substitute).collect();
+            return Node::Dstr(dstr);
+        }
+        Node::Array(array) => {
+            let mut array = array.clone();
+            array.elements = array.elements.iter().map(substitute).collect();
+            return Node::Array(array);
+        }
+        Node::Hash(hash) => {
+            let mut hash = hash.clone();
+            hash.pairs = hash.pairs.iter().map(substitute).collect();
+            return Node::Hash(hash);
+        }
+        Node::Pair(pair) => {
+            return Node::Pair(Pair {
+                key: substitute_box(&pair.key),
+                value: substitute_box(&pair.value),
+                ..pair.clone()
+            })
+        }
+        Node::Irange(range) => {
+            let mut range = range.clone();
+            range.left = range.left.as_deref().map(substitute_box);
+            range.right = range.right.as_deref().map(substitute_box);
+            return Node::Irange(range);
+        }
+        Node::Erange(range) => {
+            let mut range = range.clone();
+            range.left = range.left.as_deref().map(substitute_box);
+            range.right = range.right.as_deref().map(substitute_box);
+            return Node::Erange(range);
+        }
+        Node::Send(send) => {
+            let mut send = send.clone();
+            send.recv = send.recv.as_deref().map(substitute_box);
+            send.args = send.args.iter().map(substitute).collect();
+            return Node::Send(send);
+        }
+        Node::Index(index) => {
+            let mut index = index.clone();
+            index.recv = substitute_box(&index.recv);
+            index.indexes = index.indexes.iter().map(substitute).collect();
+            return Node::Index(index);
+        }
+        _ => return node.clone(),
+    }
+}
+
+fn constant_path(node: &Node) -> Option<String> {
+    match node {
+        Node::Const(constant) => match constant.scope.as_deref() {
+            None | Some(Node::Cbase(_)) => Some(constant.name.clone()),
+            Some(scope) => Some(format!("{}::{}", constant_path(scope)?, constant.name)),
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+
+use lib_ruby_parser::Node;
+
+use crate::{
+    documentation_context::DocumentationContext,
+    documentation_tree::{build_documentation_tree, DocumentedObject},
+    yard::YardDocstring,
+};
+
+/** Visibility of a definition, as set by `private`, `protected` or `public` */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+    Public,
+    Protected,
+    Private,
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Protected => "protected",
+            Visibility::Private => "private",
+        }
+    }
+
+    pub fn from_method_name(method_name: &str) -> Option<Self> {
+        match method_name {
+            "public" | "public_class_method" | "public_constant" => Some(Visibility::Public),
+            "protected" => Some(Visibility::Protected),
+            "private" | "private_class_method" | "private_constant" => Some(Visibility::Private),
+            _ => None,
+        }
+    }
+}
+
+/** Visibilities kept by the documentation writer */
+pub struct VisibilityFilter {
+    pub include_protected: bool,
+    pub include_private: bool,
+    /** Consider items tagged `@private` or `@api private` as private */
+    pub honor_yard_tags: bool,
+}
+
+impl VisibilityFilter {
+    /** Filter keeping only the public API */
+    pub fn new() -> Self {
+        return VisibilityFilter {
+            include_protected: false,
+            include_private: false,
+            honor_yard_tags: false,
+        };
+    }
+
+    fn includes(&self, visibility: Visibility) -> bool {
+        match visibility {
+            Visibility::Public => true,
+            Visibility::Protected => self.include_protected,
+            Visibility::Private => self.include_private,
+        }
+    }
+}
+
+/** Effective visibilities of the documented definitions: name and visibility by beginning of their definition */
+type Visibilities = HashMap<usize, Vec<(String, Visibility)>>;
+
+/** Remove the definitions that are not part of the documented API */
+pub fn filter_by_visibility(
+    node: &mut Node,
+    filter: &VisibilityFilter,
+    context: &DocumentationContext,
+) {
+    // The documentation tree knows the visibility of every definition, the code is filtered with it
+    let mut visibilities: Visibilities = HashMap::new();
+    collect_visibilities(
+        &build_documentation_tree(node, context),
+        filter,
+        &mut visibilities,
+    );
+    match node {
+        Node::Begin(begin) => filter_statements(&mut begin.statements, filter, &visibilities),
+        _ => filter_namespace(node, filter, &visibilities),
+    }
+}
+
+fn collect_visibilities(
+    objects: &Vec<DocumentedObject>,
+    filter: &VisibilityFilter,
+    visibilities: &mut Visibilities,
+) {
+    for object in objects.iter() {
+        let visibility = if filter.honor_yard_tags && has_private_yard_tag(&object.docstring) {
+            Visibility::Private
+        } else {
+            object.visibility
+        };
+        visibilities
+            .entry(object.location.begin)
+            .or_default()
+            .push((object.name.clone(), visibility));
+        collect_visibilities(&object.children, filter, visibilities);
+    }
+}
+
+fn filter_namespace(node: &mut Node, filter: &VisibilityFilter, visibilities: &Visibilities) {
+    let body = match node {
+        Node::Class(class) => &mut class.body,
+        Node::Module(module) => &mut module.body,
+        Node::SClass(sclass) => &mut sclass.body,
+        _ => return,
+    };
+    match body.as_deref_mut() {
+        Some(Node::Begin(begin)) => filter_statements(&mut begin.statements, filter, visibilities),
+        Some(statement) => {
+            let mut statements = vec![statement.clone()];
+            filter_statements(&mut statements, filter, visibilities);
+            *body = statements.pop().map(Box::new);
+        }
+        None => {}
+    }
+}
+
+fn filter_statements(
+    statements: &mut Vec<Node>,
+    filter: &VisibilityFilter,
+    visibilities: &Visibilities,
+) {
+    // Visibility in effect in the written code, a bare modifier not changing it is redundant
+    let mut written_visibility = Visibility::Public;
+    let mut kept_statements: Vec<Node> = vec![];
+    for mut statement in statements.drain(..) {
+        let is_kept = match &mut statement {
+            Node::Send(send) if send.recv.is_none() => match send.method_name.as_str() {
+                "private" | "protected" | "public" if send.args.is_empty() => {
+                    let visibility = Visibility::from_method_name(&send.method_name).unwrap();
+                    if !filter.includes(visibility) || written_visibility == visibility {
+                        continue;
+                    }
+                    written_visibility = visibility;
+                    true
+                }
+                "attr_reader" | "attr_writer" | "attr_accessor" | "attr" => {
+                    let begin = send.expression_l.begin;
+                    send.args.retain(|arg| match symbol_name(arg) {
+                        Some(name) => is_included(visibilities, begin, Some(&name), filter),
+                        None => true,
+                    });
+                    !send.args.is_empty()
+                }
+                // `private def name` has the visibility of the definition, `private :name` its own
+                method_name => match (send.args.first(), Visibility::from_method_name(method_name))
+                {
+                    (Some(arg), Some(visibility)) => match symbol_name(arg) {
+                        Some(_) => filter.includes(visibility),
+                        None => is_included(visibilities, arg.expression().begin, None, filter),
+                    },
+                    _ => is_included(visibilities, send.expression_l.begin, None, filter),
+                },
+            },
+            _ => is_included(visibilities, statement.expression().begin, None, filter),
+        };
+        if !is_kept {
+            continue;
+        }
+        filter_namespace(&mut statement, filter, visibilities);
+        kept_statements.push(statement);
+    }
+    *statements = kept_statements;
+}
+
+/** Whether the definitions starting at a position (with a name) are kept, code defining nothing is */
+fn is_included(
+    visibilities: &Visibilities,
+    begin: usize,
+    name: Option<&str>,
+    filter: &VisibilityFilter,
+) -> bool {
+    let definitions = match visibilities.get(&begin) {
+        Some(definitions) => definitions,
+        None => return true,
+    };
+    let mut visibilities = definitions
+        .iter()
+        .filter(|(definition_name, _)| name.map_or(true, |name| definition_name == name))
+        .peekable();
+    if visibilities.peek().is_none() {
+        return true;
+    }
+    return visibilities.any(|(_, visibility)| filter.includes(*visibility));
+}
+
+fn has_private_yard_tag(docstring: &Option<YardDocstring>) -> bool {
+    match docstring {
+        Some(docstring) => docstring.tags.iter().any(|tag| {
+            tag.tag_name == "private" || (tag.tag_name == "api" && tag.text.trim() == "private")
+        }),
+        None => false,
+    }
+}
+
+fn symbol_name(node: &Node) -> Option<String> {
+    match node {
+        Node::Sym(sym) => Some(sym.name.to_string_lossy()),
+        Node::Str(str) => Some(str.value.to_string_lossy()),
+        _ => None,
+    }
+}
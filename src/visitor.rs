@@ -0,0 +1,526 @@
+use lib_ruby_parser::Node;
+
+/**
+ * Expand to a match over every node type, calling `$one!`, `$opt!` and `$many!` on its
+ * `Box<Node>`, `Option<Box<Node>>` and `Vec<Node>` fields in source order. There is no
+ * catch-all arm: a node type added to lib_ruby_parser fails to compile until it is listed.
+ */
+macro_rules! match_children {
+    ($node: expr, $one: ident, $opt: ident, $many: ident) => {
+        match $node {
+            Node::Alias(inner) => {
+                $one!(inner, to);
+                $one!(inner, from);
+            }
+            Node::And(inner) => {
+                $one!(inner, lhs);
+                $one!(inner, rhs);
+            }
+            Node::AndAsgn(inner) => {
+                $one!(inner, recv);
+                $one!(inner, value);
+            }
+            Node::Args(inner) => {
+                $many!(inner, args);
+            }
+            Node::Array(inner) => {
+                $many!(inner, elements);
+            }
+            Node::ArrayPattern(inner) => {
+                $many!(inner, elements);
+            }
+            Node::ArrayPatternWithTail(inner) => {
+                $many!(inner, elements);
+            }
+            Node::Begin(inner) => {
+                $many!(inner, statements);
+            }
+            Node::Block(inner) => {
+                $one!(inner, call);
+                $opt!(inner, args);
+                $opt!(inner, body);
+            }
+            Node::BlockPass(inner) => {
+                $opt!(inner, value);
+            }
+            Node::Break(inner) => {
+                $many!(inner, args);
+            }
+            Node::CSend(inner) => {
+                $one!(inner, recv);
+                $many!(inner, args);
+            }
+            Node::Case(inner) => {
+                $opt!(inner, expr);
+                $many!(inner, when_bodies);
+                $opt!(inner, else_body);
+            }
+            Node::CaseMatch(inner) => {
+                $one!(inner, expr);
+                $many!(inner, in_bodies);
+                $opt!(inner, else_body);
+            }
+            Node::Casgn(inner) => {
+                $opt!(inner, scope);
+                $opt!(inner, value);
+            }
+            Node::Class(inner) => {
+                $one!(inner, name);
+                $opt!(inner, superclass);
+                $opt!(inner, body);
+            }
+            Node::Const(inner) => {
+                $opt!(inner, scope);
+            }
+            Node::ConstPattern(inner) => {
+                $one!(inner, const_);
+                $one!(inner, pattern);
+            }
+            Node::Cvasgn(inner) => {
+                $opt!(inner, value);
+            }
+            Node::Def(inner) => {
+                $opt!(inner, args);
+                $opt!(inner, body);
+            }
+            Node::Defined(inner) => {
+                $one!(inner, value);
+            }
+            Node::Defs(inner) => {
+                $one!(inner, definee);
+                $opt!(inner, args);
+                $opt!(inner, body);
+            }
+            Node::Dstr(inner) => {
+                $many!(inner, parts);
+            }
+            Node::Dsym(inner) => {
+                $many!(inner, parts);
+            }
+            Node::EFlipFlop(inner) => {
+                $opt!(inner, left);
+                $opt!(inner, right);
+            }
+            Node::Ensure(inner) => {
+                $opt!(inner, body);
+                $opt!(inner, ensure);
+            }
+            Node::Erange(inner) => {
+                $opt!(inner, left);
+                $opt!(inner, right);
+            }
+            Node::FindPattern(inner) => {
+                $many!(inner, elements);
+            }
+            Node::For(inner) => {
+                $one!(inner, iterator);
+                $one!(inner, iteratee);
+                $opt!(inner, body);
+            }
+            Node::Gvasgn(inner) => {
+                $opt!(inner, value);
+            }
+            Node::Hash(inner) => {
+                $many!(inner, pairs);
+            }
+            Node::HashPattern(inner) => {
+                $many!(inner, elements);
+            }
+            Node::Heredoc(inner) => {
+                $many!(inner, parts);
+            }
+            Node::IFlipFlop(inner) => {
+                $opt!(inner, left);
+                $opt!(inner, right);
+            }
+            Node::If(inner) => {
+                $one!(inner, cond);
+                $opt!(inner, if_true);
+                $opt!(inner, if_false);
+            }
+            Node::IfGuard(inner) => {
+                $one!(inner, cond);
+            }
+            Node::IfMod(inner) => {
+                $one!(inner, cond);
+                $opt!(inner, if_true);
+                $opt!(inner, if_false);
+            }
+            Node::IfTernary(inner) => {
+                $one!(inner, cond);
+                $one!(inner, if_true);
+                $one!(inner, if_false);
+            }
+            Node::InPattern(inner) => {
+                $one!(inner, pattern);
+                $opt!(inner, guard);
+                $opt!(inner, body);
+            }
+            Node::Index(inner) => {
+                $one!(inner, recv);
+                $many!(inner, indexes);
+            }
+            Node::IndexAsgn(inner) => {
+                $one!(inner, recv);
+                $many!(inner, indexes);
+                $opt!(inner, value);
+            }
+            Node::Irange(inner) => {
+                $opt!(inner, left);
+                $opt!(inner, right);
+            }
+            Node::Ivasgn(inner) => {
+                $opt!(inner, value);
+            }
+            Node::KwBegin(inner) => {
+                $many!(inner, statements);
+            }
+            Node::Kwargs(inner) => {
+                $many!(inner, pairs);
+            }
+            Node::Kwoptarg(inner) => {
+                $one!(inner, default);
+            }
+            Node::Kwsplat(inner) => {
+                $one!(inner, value);
+            }
+            Node::Lvasgn(inner) => {
+                $opt!(inner, value);
+            }
+            Node::Masgn(inner) => {
+                $one!(inner, lhs);
+                $one!(inner, rhs);
+            }
+            Node::MatchAlt(inner) => {
+                $one!(inner, lhs);
+                $one!(inner, rhs);
+            }
+            Node::MatchAs(inner) => {
+                $one!(inner, value);
+                $one!(inner, as_);
+            }
+            Node::MatchCurrentLine(inner) => {
+                $one!(inner, re);
+            }
+            Node::MatchPattern(inner) => {
+                $one!(inner, value);
+                $one!(inner, pattern);
+            }
+            Node::MatchPatternP(inner) => {
+                $one!(inner, value);
+                $one!(inner, pattern);
+            }
+            Node::MatchRest(inner) => {
+                $opt!(inner, name);
+            }
+            Node::MatchWithLvasgn(inner) => {
+                $one!(inner, re);
+                $one!(inner, value);
+            }
+            Node::Mlhs(inner) => {
+                $many!(inner, items);
+            }
+            Node::Module(inner) => {
+                $one!(inner, name);
+                $opt!(inner, body);
+            }
+            Node::Next(inner) => {
+                $many!(inner, args);
+            }
+            Node::Numblock(inner) => {
+                $one!(inner, call);
+                $one!(inner, body);
+            }
+            Node::OpAsgn(inner) => {
+                $one!(inner, recv);
+                $one!(inner, value);
+            }
+            Node::Optarg(inner) => {
+                $one!(inner, default);
+            }
+            Node::Or(inner) => {
+                $one!(inner, lhs);
+                $one!(inner, rhs);
+            }
+            Node::OrAsgn(inner) => {
+                $one!(inner, recv);
+                $one!(inner, value);
+            }
+            Node::Pair(inner) => {
+                $one!(inner, key);
+                $one!(inner, value);
+            }
+            Node::Pin(inner) => {
+                $one!(inner, var);
+            }
+            Node::Postexe(inner) => {
+                $opt!(inner, body);
+            }
+            Node::Preexe(inner) => {
+                $opt!(inner, body);
+            }
+            Node::Procarg0(inner) => {
+                $many!(inner, args);
+            }
+            Node::Regexp(inner) => {
+                $many!(inner, parts);
+                $opt!(inner, options);
+            }
+            Node::Rescue(inner) => {
+                $opt!(inner, body);
+                $many!(inner, rescue_bodies);
+                $opt!(inner, else_);
+            }
+            Node::RescueBody(inner) => {
+                $opt!(inner, exc_list);
+                $opt!(inner, exc_var);
+                $opt!(inner, body);
+            }
+            Node::Return(inner) => {
+                $many!(inner, args);
+            }
+            Node::SClass(inner) => {
+                $one!(inner, expr);
+                $opt!(inner, body);
+            }
+            Node::Send(inner) => {
+                $opt!(inner, recv);
+                $many!(inner, args);
+            }
+            Node::Splat(inner) => {
+                $opt!(inner, value);
+            }
+            Node::Super(inner) => {
+                $many!(inner, args);
+            }
+            Node::Undef(inner) => {
+                $many!(inner, names);
+            }
+            Node::UnlessGuard(inner) => {
+                $one!(inner, cond);
+            }
+            Node::Until(inner) => {
+                $one!(inner, cond);
+                $opt!(inner, body);
+            }
+            Node::UntilPost(inner) => {
+                $one!(inner, cond);
+                $one!(inner, body);
+            }
+            Node::When(inner) => {
+                $many!(inner, patterns);
+                $opt!(inner, body);
+            }
+            Node::While(inner) => {
+                $one!(inner, cond);
+                $opt!(inner, body);
+            }
+            Node::WhilePost(inner) => {
+                $one!(inner, cond);
+                $one!(inner, body);
+            }
+            Node::XHeredoc(inner) => {
+                $many!(inner, parts);
+            }
+            Node::Xstr(inner) => {
+                $many!(inner, parts);
+            }
+            Node::Yield(inner) => {
+                $many!(inner, args);
+            }
+            Node::Arg(_)
+            | Node::BackRef(_)
+            | Node::Blockarg(_)
+            | Node::Cbase(_)
+            | Node::Complex(_)
+            | Node::Cvar(_)
+            | Node::EmptyElse(_)
+            | Node::Encoding(_)
+            | Node::False(_)
+            | Node::File(_)
+            | Node::Float(_)
+            | Node::ForwardArg(_)
+            | Node::ForwardedArgs(_)
+            | Node::Gvar(_)
+            | Node::Int(_)
+            | Node::Ivar(_)
+            | Node::Kwarg(_)
+            | Node::Kwnilarg(_)
+            | Node::Kwrestarg(_)
+            | Node::Lambda(_)
+            | Node::Line(_)
+            | Node::Lvar(_)
+            | Node::MatchNilPattern(_)
+            | Node::MatchVar(_)
+            | Node::Nil(_)
+            | Node::NthRef(_)
+            | Node::Rational(_)
+            | Node::Redo(_)
+            | Node::RegOpt(_)
+            | Node::Restarg(_)
+            | Node::Retry(_)
+            | Node::Self_(_)
+            | Node::Shadowarg(_)
+            | Node::Str(_)
+            | Node::Sym(_)
+            | Node::True(_)
+            | Node::ZSuper(_) => {}
+        }
+    };
+}
+
+/** Step from a node to one of its children */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathStep {
+    /** Type of the parent node, as in `Node::str_type` (`send`, `lvasgn`...) */
+    pub parent_type: &'static str,
+    /** Field of the parent node holding the child (`recv`, `args`...) */
+    pub field: &'static str,
+    /** Position of the child when the field is a list */
+    pub index: Option<usize>,
+}
+
+/** Ancestors of the visited node and the steps leading to it from the root of the walk */
+#[derive(Debug, Default)]
+pub struct NodePath<'a> {
+    /** Enclosing nodes, the parent coming last */
+    pub ancestors: Vec<&'a Node>,
+    pub steps: Vec<PathStep>,
+}
+
+/**
+ * Read-only pass over a tree. `walk` calls `enter` on every node before its children and
+ * `leave` after them, a pass only overrides the hooks and matches the node types it cares about.
+ */
+pub trait Visitor<'a> {
+    /** Called before the children of the node, returning false skips them */
+    fn enter(&mut self, _node: &'a Node, _path: &NodePath<'a>) -> bool {
+        return true;
+    }
+
+    /** Called after the children of the node (even if they were skipped) */
+    fn leave(&mut self, _node: &'a Node, _path: &NodePath<'a>) {}
+}
+
+/**
+ * Pass editing a tree in place. Changes made by `enter` are walked (a replaced node gets its new
+ * children visited), `leave` sees the children already edited.
+ */
+pub trait VisitorMut {
+    /** Called before the children of the node, returning false skips them */
+    fn enter(&mut self, _node: &mut Node, _path: &[PathStep]) -> bool {
+        return true;
+    }
+
+    /** Called after the children of the node (even if they were skipped) */
+    fn leave(&mut self, _node: &mut Node, _path: &[PathStep]) {}
+}
+
+/** Visit a node and all its descendants, depth first in source order */
+pub fn walk<'a, V: Visitor<'a>>(visitor: &mut V, node: &'a Node) {
+    let mut path = NodePath::default();
+    walk_node(visitor, node, &mut path);
+}
+
+fn walk_node<'a, V: Visitor<'a>>(visitor: &mut V, node: &'a Node, path: &mut NodePath<'a>) {
+    if visitor.enter(node, path) {
+        path.ancestors.push(node);
+        for (step, child) in children_with_steps(node) {
+            path.steps.push(step);
+            walk_node(visitor, child, path);
+            path.steps.pop();
+        }
+        path.ancestors.pop();
+    }
+    visitor.leave(node, path);
+}
+
+/** Visit and edit a node and all its descendants, depth first in source order */
+pub fn walk_mut<V: VisitorMut>(visitor: &mut V, node: &mut Node) {
+    let mut path: Vec<PathStep> = vec![];
+    walk_node_mut(visitor, node, &mut path);
+}
+
+fn walk_node_mut<V: VisitorMut>(visitor: &mut V, node: &mut Node, path: &mut Vec<PathStep>) {
+    if visitor.enter(node, path) {
+        for (step, child) in children_with_steps_mut(node) {
+            path.push(step);
+            walk_node_mut(visitor, child, path);
+            path.pop();
+        }
+    }
+    visitor.leave(node, path);
+}
+
+/** Direct children of a node, in source order */
+pub fn children_mut(node: &mut Node) -> Vec<&mut Node> {
+    return children_with_steps_mut(node)
+        .into_iter()
+        .map(|(_, child)| child)
+        .collect();
+}
+
+/** Direct children of a node with the field holding them, in source order */
+pub fn children_with_steps(node: &Node) -> Vec<(PathStep, &Node)> {
+    let parent_type = node.str_type();
+    let mut children: Vec<(PathStep, &Node)> = vec![];
+    macro_rules! one {
+        ($inner: ident, $field: ident) => {
+            children.push((step(parent_type, stringify!($field), None), &*$inner.$field));
+        };
+    }
+    macro_rules! opt {
+        ($inner: ident, $field: ident) => {
+            if let Some(child) = &$inner.$field {
+                children.push((step(parent_type, stringify!($field), None), child.as_ref()));
+            }
+        };
+    }
+    macro_rules! many {
+        ($inner: ident, $field: ident) => {
+            for (index, child) in $inner.$field.iter().enumerate() {
+                children.push((step(parent_type, stringify!($field), Some(index)), child));
+            }
+        };
+    }
+    match_children!(node, one, opt, many);
+    return children;
+}
+
+/** Direct children of a node with the field holding them, in source order */
+pub fn children_with_steps_mut(node: &mut Node) -> Vec<(PathStep, &mut Node)> {
+    let parent_type = node.str_type();
+    let mut children: Vec<(PathStep, &mut Node)> = vec![];
+    macro_rules! one {
+        ($inner: ident, $field: ident) => {
+            children.push((
+                step(parent_type, stringify!($field), None),
+                &mut *$inner.$field,
+            ));
+        };
+    }
+    macro_rules! opt {
+        ($inner: ident, $field: ident) => {
+            if let Some(child) = &mut $inner.$field {
+                children.push((step(parent_type, stringify!($field), None), child.as_mut()));
+            }
+        };
+    }
+    macro_rules! many {
+        ($inner: ident, $field: ident) => {
+            for (index, child) in $inner.$field.iter_mut().enumerate() {
+                children.push((step(parent_type, stringify!($field), Some(index)), child));
+            }
+        };
+    }
+    match_children!(node, one, opt, many);
+    return children;
+}
+
+fn step(parent_type: &'static str, field: &'static str, index: Option<usize>) -> PathStep {
+    return PathStep {
+        parent_type,
+        field,
+        index,
+    };
+}
@@ -0,0 +1,358 @@
+use crate::json::JsonValue;
+
+/** Structured YARD documentation of a Ruby definition */
+#[derive(Debug, Clone, Default)]
+pub struct YardDocstring {
+    /** First sentence of the free text */
+    pub summary: String,
+    /** Free text following the summary */
+    pub description: String,
+    pub tags: Vec<YardTag>,
+}
+
+/** Single `@tag` of a YARD docstring */
+#[derive(Debug, Clone, Default)]
+pub struct YardTag {
+    /** Name of the tag without `@` (eg. `param`, `!macro`) */
+    pub tag_name: String,
+    /** Name of the parameter, option hash or macro the tag refers to */
+    pub name: Option<String>,
+    pub types: Vec<String>,
+    pub text: String,
+    /** Key of an `@option` tag */
+    pub option_key: Option<String>,
+    /** Default value of an `@option` tag */
+    pub option_default: Option<String>,
+    /** Title of an `@example` tag */
+    pub title: Option<String>,
+}
+
+impl YardDocstring {
+    pub fn to_json(&self) -> JsonValue {
+        return JsonValue::object(vec![
+            ("summary", JsonValue::string(self.summary.as_str())),
+            ("description", JsonValue::string(self.description.as_str())),
+            (
+                "tags",
+                JsonValue::Array(self.tags.iter().map(|tag| tag.to_json()).collect()),
+            ),
+        ]);
+    }
+}
+
+impl YardTag {
+    fn new(tag_name: &str) -> Self {
+        return YardTag {
+            tag_name: String::from(tag_name),
+            ..Default::default()
+        };
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut entries = vec![
+            ("tag", JsonValue::string(self.tag_name.as_str())),
+            ("name", JsonValue::optional_string(&self.name)),
+            (
+                "types",
+                JsonValue::Array(self.types.iter().map(JsonValue::string).collect()),
+            ),
+            ("text", JsonValue::string(self.text.as_str())),
+        ];
+        if self.option_key.is_some() {
+            entries.push(("key", JsonValue::optional_string(&self.option_key)));
+            entries.push(("default", JsonValue::optional_string(&self.option_default)));
+        }
+        if self.title.is_some() {
+            entries.push(("title", JsonValue::optional_string(&self.title)));
+        }
+        return JsonValue::object(entries);
+    }
+}
+
+/** Tags whose text keeps its line breaks (code blocks) */
+fn is_block_tag(tag_name: &str) -> bool {
+    return tag_name == "example" || tag_name.starts_with('!');
+}
+
+/**
+ * Turn the raw text of a comment block (`# ...` lines or `=begin`/`=end`) into the lines of the
+ * docstring, without the comment markers.
+ */
+pub fn comment_lines(comment_text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in comment_text.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with("=begin") || line.starts_with("=end") {
+            continue;
+        }
+        let trimmed = line.trim_start();
+        if let Some(content) = trimmed.strip_prefix('#') {
+            let content = content.strip_prefix(' ').unwrap_or(content);
+            lines.push(String::from(content.trim_end()));
+        } else {
+            lines.push(String::from(line.trim_end()));
+        }
+    }
+    return lines;
+}
+
+/** Parse the raw text of a comment block into a YARD docstring */
+pub fn parse_yard_comment(comment_text: &str) -> YardDocstring {
+    return parse_yard_lines(&comment_lines(comment_text));
+}
+
+/** Parse docstring lines (comment markers already removed) into a YARD docstring */
+pub fn parse_yard_lines(lines: &[String]) -> YardDocstring {
+    let mut free_text: Vec<&str> = vec![];
+    let mut tags: Vec<YardTag> = vec![];
+    let mut current_tag: Option<(String, Vec<String>)> = None;
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index].as_str();
+        if line.starts_with('@') {
+            if let Some((head, body)) = current_tag.take() {
+                tags.push(parse_tag(&head, &body));
+            }
+            current_tag = Some((String::from(line), vec![]));
+        } else if let Some((_, body)) = current_tag.as_mut() {
+            if line.starts_with(' ') || line.starts_with('\t') {
+                body.push(String::from(line));
+            } else if line.is_empty() && next_line_is_indented(lines, index) {
+                body.push(String::new());
+            } else if line.is_empty() {
+                // Blank line closing a tag
+            } else {
+                // Unindented text after a tag is free text again
+                if let Some((head, body)) = current_tag.take() {
+                    tags.push(parse_tag(&head, &body));
+                }
+                free_text.push(line);
+            }
+        } else {
+            free_text.push(line);
+        }
+        index += 1;
+    }
+    if let Some((head, body)) = current_tag.take() {
+        tags.push(parse_tag(&head, &body));
+    }
+
+    let (summary, description) = split_summary(&free_text);
+    return YardDocstring {
+        summary,
+        description,
+        tags,
+    };
+}
+
+fn next_line_is_indented(lines: &[String], index: usize) -> bool {
+    for line in lines[(index + 1)..].iter() {
+        if line.is_empty() {
+            continue;
+        }
+        return line.starts_with(' ') || line.starts_with('\t');
+    }
+    return false;
+}
+
+fn split_summary(free_text: &Vec<&str>) -> (String, String) {
+    let text = free_text.join("\n");
+    let text = text.trim();
+    let paragraph_end = text.find("\n\n").unwrap_or(text.len());
+    let first_paragraph = &text[..paragraph_end];
+    let mut summary_end = paragraph_end;
+    for (i, _) in first_paragraph.match_indices('.') {
+        let next = first_paragraph[(i + 1)..].chars().next();
+        if next.is_none() || next == Some(' ') || next == Some('\n') {
+            summary_end = i + 1;
+            break;
+        }
+    }
+    let summary = text[..summary_end]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let description = String::from(text[summary_end..].trim());
+    return (summary, description);
+}
+
+fn parse_tag(head: &str, body: &Vec<String>) -> YardTag {
+    let head = &head[1..];
+    let (tag_name, rest) = match head.find(char::is_whitespace) {
+        Some(i) => (&head[..i], head[i..].trim_start()),
+        None => (head, ""),
+    };
+    let mut tag = YardTag::new(tag_name);
+    match tag_name {
+        "param" | "yieldparam" | "attr" | "attr_reader" | "attr_writer" => {
+            let (types, rest) = take_types(rest);
+            let (name, rest) = take_word(rest);
+            let (types, rest) = if types.is_empty() {
+                take_types(rest)
+            } else {
+                (types, rest)
+            };
+            tag.name = name;
+            tag.types = types;
+            tag.text = String::from(rest);
+        }
+        "option" => {
+            let (name, rest) = take_word(rest);
+            let (types, rest) = take_types(rest);
+            let (key, rest) = take_word(rest);
+            let (types, rest) = if types.is_empty() {
+                take_types(rest)
+            } else {
+                (types, rest)
+            };
+            let (default, rest) = take_default(rest);
+            tag.name = name;
+            tag.types = types;
+            tag.option_key = key;
+            tag.option_default = default;
+            tag.text = String::from(rest);
+        }
+        "return" | "raise" | "yield" | "yieldreturn" => {
+            let (types, rest) = take_types(rest);
+            tag.types = types;
+            tag.text = String::from(rest);
+        }
+        "example" => {
+            if !rest.is_empty() {
+                tag.title = Some(String::from(rest));
+            }
+        }
+        "!macro" => {
+            let (_, rest) = take_types(rest);
+            let (name, rest) = take_word(rest);
+            tag.name = name;
+            tag.text = String::from(rest);
+        }
+        _ => {
+            tag.text = String::from(rest);
+        }
+    }
+
+    if !body.is_empty() {
+        if is_block_tag(tag_name) {
+            let block = dedent(body);
+            if tag.text.is_empty() {
+                tag.text = block;
+            } else {
+                tag.text.push('\n');
+                tag.text.push_str(&block);
+            }
+        } else {
+            for line in body.iter() {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                if !tag.text.is_empty() {
+                    tag.text.push(' ');
+                }
+                tag.text.push_str(line);
+            }
+        }
+    }
+    return tag;
+}
+
+/** Read a `[Type1, Type2<A>]` list at the beginning of the text */
+fn take_types(text: &str) -> (Vec<String>, &str) {
+    if !text.starts_with('[') {
+        return (vec![], text);
+    }
+    let mut depth = 0;
+    let mut types: Vec<String> = vec![];
+    let mut current = String::new();
+    let mut previous = ' ';
+    for (i, character) in text.char_indices() {
+        let after_equal = previous == '=';
+        previous = character;
+        match character {
+            '>' if after_equal => current.push(character),
+            '[' | '<' | '{' | '(' => {
+                if depth > 0 {
+                    current.push(character);
+                }
+                depth += 1;
+            }
+            ']' | '>' | '}' | ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    if !current.trim().is_empty() {
+                        types.push(String::from(current.trim()));
+                    }
+                    return (types, text[(i + 1)..].trim_start());
+                }
+                current.push(character);
+            }
+            ',' if depth == 1 => {
+                types.push(String::from(current.trim()));
+                current.clear();
+            }
+            _ => {
+                if depth > 0 {
+                    current.push(character);
+                }
+            }
+        }
+    }
+    return (vec![], text);
+}
+
+fn take_word(text: &str) -> (Option<String>, &str) {
+    if text.is_empty() || text.starts_with('[') {
+        return (None, text);
+    }
+    match text.find(char::is_whitespace) {
+        Some(i) => (Some(String::from(&text[..i])), text[i..].trim_start()),
+        None => (Some(String::from(text)), ""),
+    }
+}
+
+fn take_default(text: &str) -> (Option<String>, &str) {
+    if !text.starts_with('(') {
+        return (None, text);
+    }
+    let mut depth = 0;
+    for (i, character) in text.char_indices() {
+        match character {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return (
+                        Some(String::from(text[1..i].trim())),
+                        text[(i + 1)..].trim_start(),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+    return (None, text);
+}
+
+fn dedent(lines: &Vec<String>) -> String {
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    return lines
+        .iter()
+        .map(|line| {
+            if line.len() >= indent {
+                &line[indent..]
+            } else {
+                ""
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end()
+        .to_string();
+}